@@ -1,5 +1,5 @@
 use criterion::{criterion_group, criterion_main, Criterion};
-use ghaladb::GhalaDb;
+use ghaladb::{DatabaseOptions, GhalaDb};
 use rand::{distributions::Alphanumeric, prelude::ThreadRng, Rng};
 use tempfile::tempdir;
 
@@ -10,7 +10,8 @@ fn gen_bytes(rng: &mut ThreadRng, len: usize) -> Vec<u8> {
 pub fn small_kv_benchmark(c: &mut Criterion) {
     let mut rng = rand::thread_rng();
     let tmp_dir = tempdir().expect("failed to create temp dir");
-    let mut db = GhalaDb::new(tmp_dir.path(), None).unwrap();
+    let mut db: GhalaDb<Vec<u8>, Vec<u8>> =
+        GhalaDb::new(tmp_dir.path(), None).unwrap();
 
     let mut data = (0usize..)
         .map(|_| (gen_bytes(&mut rng, 36usize), gen_bytes(&mut rng, 1000usize)));
@@ -25,7 +26,8 @@ pub fn small_kv_benchmark(c: &mut Criterion) {
         )
     });
     let tmp_dir = tempdir().expect("failed to create temp dir");
-    let mut db = GhalaDb::new(tmp_dir.path(), None).unwrap();
+    let mut db: GhalaDb<Vec<u8>, Vec<u8>> =
+        GhalaDb::new(tmp_dir.path(), None).unwrap();
     let mut keys = (0usize..1_000_000)
         .map(|_| {
             let (k, v) =
@@ -46,5 +48,41 @@ pub fn small_kv_benchmark(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, small_kv_benchmark);
+pub fn vlog_reads_benchmark(c: &mut Criterion) {
+    let mut rng = rand::thread_rng();
+    let mut group = c.benchmark_group("vlog_reads");
+    group.throughput(criterion::Throughput::Bytes(1000u64));
+    for (name, mmap_reads) in [("buffered", false), ("mmap", true)] {
+        let tmp_dir = tempdir().expect("failed to create temp dir");
+        let opts = DatabaseOptions::builder()
+            .max_vlog_size(8_000_000)
+            .mmap_reads(mmap_reads)
+            .compact(false)
+            .build();
+        let mut db: GhalaDb<Vec<u8>, Vec<u8>> =
+            GhalaDb::new(tmp_dir.path(), Some(opts)).unwrap();
+        let keys = (0usize..100_000)
+            .map(|_| {
+                let (k, v) =
+                    (gen_bytes(&mut rng, 36usize), gen_bytes(&mut rng, 1000usize));
+                db.put(&k, &v).ok();
+                k
+            })
+            .collect::<Vec<_>>();
+        // reopen so that all but the tail vlog are sealed
+        drop(db);
+        let mut db: GhalaDb<Vec<u8>, Vec<u8>> =
+            GhalaDb::new(tmp_dir.path(), Some(opts)).unwrap();
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || keys[rng.gen_range(0..keys.len())].clone(),
+                |k| db.get(&k),
+                criterion::BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, small_kv_benchmark, vlog_reads_benchmark);
 criterion_main!(benches);
@@ -0,0 +1,369 @@
+//! GhalaDb's incremental backups module.
+use crate::{
+    core::{FileKind, VlogNum},
+    dec::Dec,
+    error::{GhalaDbError, GhalaDbResult},
+    fs::{FileReader, FileSystem, OpenMode, StdFs},
+    ghaladb::GhalaDb,
+    utils::{init_empty_dir, t},
+};
+use bincode::{Decode, Encode};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+const META_FILE: &str = "meta";
+const TMP_EXT: &str = "tmp";
+
+/// A backup's metadata, stored in its directory.
+#[derive(Debug, Encode, Decode)]
+struct BackupMeta {
+    id: u64,
+    /// The tail vlog and its size when the backup was taken. Bytes before this
+    /// point are part of this backup or of an earlier one.
+    vlog: VlogNum,
+    offset: u64,
+    /// File ranges copied by this backup.
+    pieces: Vec<Piece>,
+    /// The data store's files and their sizes.
+    live: Vec<(String, u64)>,
+}
+
+/// A file range copied by a backup.
+#[derive(Debug, Encode, Decode)]
+struct Piece {
+    path: String,
+    offset: u64,
+    len: u64,
+    crc: u32,
+}
+
+/// A summary of a backup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackupInfo {
+    /// Backup id. Backups are numbered sequentially, starting with the full
+    /// backup.
+    pub id: u64,
+    /// The tail vlog when the backup was taken.
+    pub vlog: u64,
+    /// The tail vlog's size when the backup was taken.
+    pub offset: u64,
+    /// Number of bytes copied by the backup.
+    pub size: u64,
+}
+
+/// Incremental backups of a [GhalaDb].
+///
+/// The first backup copies the whole data store, while the following ones
+/// only copy what changed since the previous backup. Vlogs are append only and
+/// numbered sequentially, so a backup records the last backed-up vlog and
+/// offset and the next one copies the bytes appended after that point, along
+/// with any new vlogs. SSTables are never modified either, so only new ones
+/// are copied, while the small metadata files and change logs are copied in
+/// full.
+///
+/// Every backup is stored in its own directory, and lists the copied file
+/// ranges along with their checksums, which are verified on restore. Backups
+/// are kept on disk, including those of data stores kept in another
+/// [FileSystem](crate::FileSystem).
+pub struct BackupEngine {
+    path: PathBuf,
+}
+
+impl BackupEngine {
+    /// Opens the backups directory, creating it if needed.
+    pub fn new<P: AsRef<Path>>(path: P) -> GhalaDbResult<BackupEngine> {
+        let path = path.as_ref().to_path_buf();
+        std::fs::create_dir_all(&path)?;
+        Ok(BackupEngine { path })
+    }
+
+    /// Backs up the changes made to the data store since the last backup, or
+    /// the whole data store if there are no backups yet.
+    ///
+    /// Backups of a data store must all be taken using the same engine
+    /// directory, as every backup builds on the previous ones.
+    pub fn create_backup<K, V>(
+        &self,
+        db: &mut GhalaDb<K, V>,
+    ) -> GhalaDbResult<BackupInfo>
+    where
+        K: Encode + Decode,
+        V: Encode + Decode,
+    {
+        trace!("BackupEngine::create_backup");
+        self.remove_partial()?;
+        let prev = self.metas()?.pop();
+        let id = prev.as_ref().map_or(0, |m| m.id + 1);
+        let prev_live: BTreeSet<&str> = prev
+            .iter()
+            .flat_map(|m| m.live.iter().map(|(p, _)| p.as_str()))
+            .collect();
+        let tmp_dir = self.path.join(format!("{id}.{TMP_EXT}"));
+        std::fs::create_dir_all(&tmp_dir)?;
+
+        let base = db.path().to_path_buf();
+        let mut meta = BackupMeta {
+            id,
+            vlog: prev.as_ref().map_or(0, |m| m.vlog),
+            offset: prev.as_ref().map_or(0, |m| m.offset),
+            pieces: vec![],
+            live: vec![],
+        };
+        for file in t!("GhalaDb::live_files", db.live_files())? {
+            let name = path_str(&file.path);
+            let start = match (&prev, file.kind) {
+                (None, _) | (_, FileKind::Mutable) => Some(0),
+                (Some(_), FileKind::Immutable) => {
+                    (!prev_live.contains(name.as_str())).then_some(0)
+                }
+                (Some(prev), FileKind::Vlog { num, .. }) => match num {
+                    n if n < prev.vlog => None,
+                    n if n == prev.vlog => Some(prev.offset),
+                    _ => Some(0),
+                },
+            };
+            if let FileKind::Vlog { num, tail: true } = file.kind {
+                (meta.vlog, meta.offset) = (num, file.len);
+            }
+            if let Some(start) = start.filter(|s| *s < file.len || file.len == 0) {
+                let dest = tmp_dir.join(&file.path);
+                if let Some(parent) = dest.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let src = db.fs().open(&base.join(&file.path), OpenMode::Read)?;
+                let rdr = FileReader::new(src, start);
+                let mut wtr = File::create(&dest)?;
+                let (len, crc) =
+                    copy_crc(&mut rdr.take(file.len - start), &mut wtr)?;
+                wtr.sync_all()?;
+                meta.pieces.push(Piece {
+                    path: name.clone(),
+                    offset: start,
+                    len,
+                    crc,
+                });
+            }
+            meta.live.push((name, file.len));
+        }
+        std::fs::write(tmp_dir.join(META_FILE), Dec::ser_raw(&meta)?)?;
+        std::fs::rename(&tmp_dir, self.backup_dir(id))?;
+        StdFs.sync_dir(&self.path)?;
+        let info = Self::info(&meta);
+        debug!("BackupEngine::create_backup done: {info:?}");
+        Ok(info)
+    }
+
+    /// Lists the backups, oldest first.
+    pub fn backups(&self) -> GhalaDbResult<Vec<BackupInfo>> {
+        Ok(self.metas()?.iter().map(Self::info).collect())
+    }
+
+    /// Restores the data store as of backup `id` into the `dest` directory,
+    /// verifying the checksums of the backed-up data.
+    ///
+    /// The `dest` directory is created if needed, and has to be empty.
+    pub fn restore<P: AsRef<Path>>(&self, id: u64, dest: P) -> GhalaDbResult<()> {
+        let dest = dest.as_ref();
+        trace!("BackupEngine::restore id: {id} dest: {}", dest.display());
+        init_empty_dir(&StdFs, dest)?;
+        let mut restored = BTreeSet::new();
+        let mut live = vec![];
+        for n in 0..=id {
+            let dir = self.backup_dir(n);
+            let meta = Self::read_meta(&dir)?;
+            for piece in &meta.pieces {
+                let src = dir.join(str_path(&piece.path));
+                let dst = dest.join(str_path(&piece.path));
+                Self::restore_piece(piece, &src, &dst)?;
+                restored.insert(dst);
+            }
+            live = meta.live;
+        }
+        // drop the files that were removed from the data store, such as
+        // collected vlogs and compacted SSTables
+        let live: BTreeMap<PathBuf, u64> = live
+            .into_iter()
+            .map(|(p, len)| (dest.join(str_path(&p)), len))
+            .collect();
+        for path in &restored {
+            if !live.contains_key(path) {
+                std::fs::remove_file(path)?;
+            }
+        }
+        for (path, len) in &live {
+            let restored_len = std::fs::metadata(path).map(|m| m.len()).ok();
+            if restored_len != Some(*len) {
+                return Err(GhalaDbError::CorruptBackup(path.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    fn restore_piece(piece: &Piece, src: &Path, dst: &Path) -> GhalaDbResult<()> {
+        let corrupt = || GhalaDbError::CorruptBackup(src.to_path_buf());
+        if let Some(parent) = dst.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut wtr = if piece.offset == 0 {
+            File::create(dst)?
+        } else {
+            let file = OpenOptions::new().append(true).open(dst)?;
+            if file.metadata()?.len() != piece.offset {
+                return Err(corrupt());
+            }
+            file
+        };
+        let mut rdr = File::open(src).map_err(|_| corrupt())?;
+        let (len, crc) = copy_crc(&mut rdr, &mut wtr)?;
+        if len != piece.len || crc != piece.crc {
+            return Err(corrupt());
+        }
+        wtr.sync_all()?;
+        Ok(())
+    }
+
+    /// Reads the metadata of all backups, oldest first.
+    fn metas(&self) -> GhalaDbResult<Vec<BackupMeta>> {
+        let mut ids = vec![];
+        for entry in std::fs::read_dir(&self.path)? {
+            let path = entry?.path();
+            if let Some(id) = path.file_name().and_then(|n| n.to_str()?.parse().ok())
+            {
+                ids.push(id);
+            }
+        }
+        ids.sort_unstable();
+        ids.into_iter()
+            .map(|id: u64| Self::read_meta(&self.backup_dir(id)))
+            .collect()
+    }
+
+    fn read_meta(dir: &Path) -> GhalaDbResult<BackupMeta> {
+        let path = dir.join(META_FILE);
+        let bytes =
+            std::fs::read(&path).map_err(|_| GhalaDbError::CorruptBackup(path))?;
+        Dec::deser_raw(&bytes)
+    }
+
+    /// Removes backups that were interrupted.
+    fn remove_partial(&self) -> GhalaDbResult<()> {
+        for entry in std::fs::read_dir(&self.path)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == TMP_EXT) {
+                debug!("BackupEngine::remove_partial path: {}", path.display());
+                std::fs::remove_dir_all(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn backup_dir(&self, id: u64) -> PathBuf {
+        self.path.join(id.to_string())
+    }
+
+    fn info(meta: &BackupMeta) -> BackupInfo {
+        BackupInfo {
+            id: meta.id,
+            vlog: meta.vlog,
+            offset: meta.offset,
+            size: meta.pieces.iter().map(|p| p.len).sum(),
+        }
+    }
+}
+
+/// Copies a stream, returning the number of bytes copied and their checksum.
+fn copy_crc<R: Read, W: Write>(rdr: &mut R, wtr: &mut W) -> io::Result<(u64, u32)> {
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut len = 0;
+    loop {
+        let n = match rdr.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buf[..n]);
+        wtr.write_all(&buf[..n])?;
+        len += n as u64;
+    }
+    Ok((len, hasher.finalize()))
+}
+
+/// A portable representation of a relative path.
+fn path_str(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn str_path(path: &str) -> PathBuf {
+    path.split('/').collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::DatabaseOptions, core::FixtureGen};
+    use tempfile::tempdir;
+
+    #[test]
+    fn incremental_backups() -> GhalaDbResult<()> {
+        env_logger::try_init().ok();
+        let tmp_dir = tempdir()?;
+        let opts = DatabaseOptions::builder()
+            .max_vlog_size(8 * 1024)
+            .keys_memtable_size(4 * 1024)
+            .sync(false)
+            .build();
+        let engine = BackupEngine::new(tmp_dir.path().join("backups"))?;
+        let mut db: GhalaDb<u32, Vec<u8>> =
+            GhalaDb::new(tmp_dir.path().join("db"), Some(opts))?;
+        let mut states = vec![];
+        let mut data = BTreeMap::new();
+        for round in 0..3u32 {
+            for i in 0..100 {
+                let v = Vec::<u8>::gen();
+                db.put(&(round * 50 + i), &v)?;
+                data.insert(round * 50 + i, v);
+            }
+            db.delete(&round)?;
+            data.remove(&round);
+            let info = engine.create_backup(&mut db)?;
+            assert_eq!(info.id, round as u64);
+            states.push((info, data.clone()));
+        }
+        // an unchanged data store only has its metadata backed up
+        let (full, _) = states[0];
+        let info = engine.create_backup(&mut db)?;
+        assert!(info.size < full.size / 10);
+        drop(db);
+        assert_eq!(engine.backups()?.len(), 4);
+
+        for (info, data) in &states {
+            let dest = tmp_dir.path().join(format!("restored-{}", info.id));
+            engine.restore(info.id, &dest)?;
+            let mut db: GhalaDb<u32, Vec<u8>> = GhalaDb::new(&dest, Some(opts))?;
+            let restored = db.iter()?.collect::<GhalaDbResult<BTreeMap<_, _>>>()?;
+            assert!(&restored == data);
+        }
+        assert!(matches!(
+            engine.restore(0, tmp_dir.path().join("restored-0")),
+            Err(GhalaDbError::DirNotEmpty(_))
+        ));
+
+        // tampered backups are detected
+        let piece = engine.backup_dir(1).join("vlog_info");
+        std::fs::write(piece, b"garbage")?;
+        assert!(matches!(
+            engine.restore(1, tmp_dir.path().join("tampered")),
+            Err(GhalaDbError::CorruptBackup(_))
+        ));
+        Ok(())
+    }
+}
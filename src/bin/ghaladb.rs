@@ -0,0 +1,5 @@
+//! GhalaDb's command-line tool.
+
+fn main() -> std::process::ExitCode {
+    ghaladb::cli::main()
+}
@@ -0,0 +1,189 @@
+//! GhalaDb's bulk loading module.
+use crate::{
+    core::{Bytes, KeyEntry},
+    dec::Dec,
+    error::GhalaDbResult,
+    ghaladb::GhalaDb,
+    keyspace::DEFAULT_KEYSPACE_ID,
+    sstable::SsTable,
+    utils::t,
+};
+use bincode::{Decode, Encode};
+use std::{borrow::Borrow, collections::BTreeMap, path::PathBuf};
+
+/// A loader inserting key-value pairs in bulk, returned by
+/// [GhalaDb::bulk_loader].
+///
+/// Values are appended to the vlogs through large write buffers, with
+/// garbage collection and keys syncing paused. Keys are sorted in memory, in
+/// runs of [bulk_run_size](crate::DatabaseOptions::bulk_run_size) bytes, and
+/// written straight to SSTables instead of going through the keys memtable.
+///
+/// The loaded pairs become visible at once when the load is committed. A
+/// loader dropped without being committed discards its pairs, as does a crash
+/// before the commit completes. Keys inserted more than once keep their last
+/// value, and the default keyspace expiry is counted from the loader's
+/// creation.
+pub struct BulkLoader<'a, K, V>
+where
+    K: Encode + Decode,
+    V: Encode + Decode,
+{
+    db: &'a mut GhalaDb<K, V>,
+    /// Keys sorted in memory, they have no tombstones.
+    run: BTreeMap<Bytes, Option<KeyEntry>>,
+    run_sz: usize,
+    /// Sorted runs written to disk, oldest first.
+    runs: Vec<PathBuf>,
+    expires_at: Option<u64>,
+    count: u64,
+    committed: bool,
+}
+
+impl<'a, K, V> BulkLoader<'a, K, V>
+where
+    K: Encode + Decode,
+    V: Encode + Decode,
+{
+    pub(crate) fn new(db: &'a mut GhalaDb<K, V>) -> Self {
+        let buf_cap = db.opts().bulk_buf_size;
+        db.vlogs_man().set_buf_cap(Some(buf_cap));
+        let expires_at = db.default_expiry(DEFAULT_KEYSPACE_ID);
+        Self {
+            db,
+            run: BTreeMap::new(),
+            run_sz: 0,
+            runs: vec![],
+            expires_at,
+            count: 0,
+            committed: false,
+        }
+    }
+
+    /// Inserts a key-value pair.
+    pub fn put<Q>(&mut self, k: &Q, v: &V) -> GhalaDbResult<()>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Encode,
+    {
+        self.put_raw(Dec::ser_raw(k)?, Dec::ser_raw(v)?)
+    }
+
+    /// Number of key-value pairs inserted so far.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Inserts the pairs into the data store, syncing it, and returns their
+    /// number.
+    pub fn commit(mut self) -> GhalaDbResult<u64> {
+        trace!("BulkLoader::commit");
+        self.write_run()?;
+        t!("vlogs_man::sync", self.db.vlogs_man().sync())?;
+        let runs = std::mem::take(&mut self.runs);
+        t!("keys::ingest", self.db.default_keys().ingest(runs))?;
+        self.committed = true;
+        debug!("BulkLoader::commit count: {}", self.count);
+        Ok(self.count)
+    }
+
+    pub(crate) fn put_raw(&mut self, key: Bytes, val: Bytes) -> GhalaDbResult<()> {
+        let entry = t!(
+            "GhalaDb::store_value",
+            self.db.store_value(
+                DEFAULT_KEYSPACE_ID,
+                key.clone(),
+                val,
+                self.expires_at
+            )
+        )?;
+        let record = (key, Some(entry));
+        self.run_sz += SsTable::record_sz(&record);
+        self.run.insert(record.0, record.1);
+        self.count += 1;
+        if self.run_sz >= self.db.opts().bulk_run_size {
+            self.write_run()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the keys sorted in memory to a new run.
+    fn write_run(&mut self) -> GhalaDbResult<()> {
+        if self.run.is_empty() {
+            return Ok(());
+        }
+        let num = self.runs.len();
+        let path = t!(
+            "keys::write_run",
+            self.db.default_keys().write_run(num, &self.run)
+        )?;
+        self.runs.push(path);
+        self.run.clear();
+        self.run_sz = 0;
+        Ok(())
+    }
+}
+
+impl<K, V> Drop for BulkLoader<'_, K, V>
+where
+    K: Encode + Decode,
+    V: Encode + Decode,
+{
+    fn drop(&mut self) {
+        self.db.vlogs_man().set_buf_cap(None);
+        if !self.committed {
+            debug!("BulkLoader::drop discarding: {}", self.count);
+            t!("keys::clear_bulk", self.db.default_keys().clear_bulk()).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DatabaseOptions;
+    use tempfile::tempdir;
+
+    #[test]
+    fn bulk_load() -> GhalaDbResult<()> {
+        let tmp_dir = tempdir()?;
+        let opts = DatabaseOptions::builder()
+            .sync(true)
+            .bulk_run_size(16_384)
+            .keys_max_tables(4)
+            .build();
+        let mut db: GhalaDb<u32, String> = GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        for i in 0..100 {
+            db.put(&i, &"old".to_owned())?;
+        }
+
+        let mut loader = db.bulk_loader();
+        for i in 0..1000 {
+            loader.put(&i, &format!("dropped-{i}"))?;
+        }
+        let runs = tmp_dir.path().join("keys").join("bulk");
+        assert!(runs.exists());
+        drop(loader);
+        assert_eq!(db.len(), 100);
+        assert_eq!(db.get(&50)?, Some("old".to_owned()));
+        assert!(!runs.exists());
+
+        let mut loader = db.bulk_loader();
+        for i in 50..5000 {
+            loader.put(&i, &format!("value-{i}"))?;
+        }
+        loader.put(&4999, &"last".to_owned())?;
+        assert_eq!(loader.commit()?, 4951);
+        assert_eq!(db.len(), 5000);
+        assert_eq!(db.get(&49)?, Some("old".to_owned()));
+        assert_eq!(db.get(&50)?, Some("value-50".to_owned()));
+        assert_eq!(db.get(&4999)?, Some("last".to_owned()));
+        drop(db);
+
+        let mut db: GhalaDb<u32, String> = GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        assert_eq!(db.len(), 5000);
+        assert_eq!(db.iter()?.count(), 5000);
+        assert_eq!(db.get(&2500)?, Some("value-2500".to_owned()));
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+//! GhalaDb's value cache module.
+use crate::{
+    core::{DataPtr, VlogNum},
+    vlog::DataEntry,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::{BuildHasher, RandomState},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+const SHARDS: usize = 16;
+/// Minimum shard capacity in bytes, smaller caches use fewer shards.
+const MIN_SHARD_SZ: usize = 64 * 1024;
+/// Approximate per entry bookkeeping overhead in bytes.
+const ENTRY_OVERHEAD: usize = 64;
+
+/// Value cache statistics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of reads served from the cache.
+    pub hits: u64,
+    /// Number of reads that went to the values log.
+    pub misses: u64,
+    /// Number of cached data entries.
+    pub entries: usize,
+    /// Approximate size of the cached data entries in bytes.
+    pub size: usize,
+}
+
+#[derive(Default)]
+struct Shard {
+    entries: HashMap<DataPtr, (DataEntry, u64)>,
+    /// Recency order, least recently used first.
+    lru: BTreeMap<u64, DataPtr>,
+    tick: u64,
+    size: usize,
+}
+
+impl Shard {
+    fn get(&mut self, dp: &DataPtr) -> Option<DataEntry> {
+        self.tick += 1;
+        let (de, tick) = self.entries.get_mut(dp)?;
+        self.lru.remove(tick);
+        *tick = self.tick;
+        self.lru.insert(self.tick, *dp);
+        Some(de.clone())
+    }
+
+    fn insert(&mut self, dp: DataPtr, de: DataEntry, capacity: usize) {
+        let sz = ValueCache::entry_sz(&de);
+        if sz > capacity {
+            return;
+        }
+        self.remove(&dp);
+        while self.size + sz > capacity {
+            let Some((_, lru)) = self.lru.pop_first() else {
+                break;
+            };
+            if let Some((de, _)) = self.entries.remove(&lru) {
+                self.size -= ValueCache::entry_sz(&de);
+            }
+        }
+        self.tick += 1;
+        self.lru.insert(self.tick, dp);
+        self.entries.insert(dp, (de, self.tick));
+        self.size += sz;
+    }
+
+    fn remove(&mut self, dp: &DataPtr) {
+        if let Some((de, tick)) = self.entries.remove(dp) {
+            self.lru.remove(&tick);
+            self.size -= ValueCache::entry_sz(&de);
+        }
+    }
+}
+
+/// A bounded, sharded LRU cache of decoded data entries keyed by their data
+/// pointer.
+///
+/// The capacity is split evenly between the shards, each of which evicts its
+/// least recently used entries once full. Small caches use fewer shards, so
+/// that a shard can hold entries of a sensible size. A capacity of zero
+/// disables the cache.
+pub(crate) struct ValueCache {
+    shards: Vec<Mutex<Shard>>,
+    shard_capacity: usize,
+    hasher: RandomState,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ValueCache {
+    pub fn new(capacity: usize) -> ValueCache {
+        let shards = match capacity {
+            0 => 0,
+            _ => (capacity / MIN_SHARD_SZ).clamp(1, SHARDS),
+        };
+        ValueCache {
+            shards: (0..shards).map(|_| Mutex::default()).collect(),
+            shard_capacity: capacity / shards.max(1),
+            hasher: RandomState::new(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.shards.is_empty()
+    }
+
+    pub fn get(&self, dp: &DataPtr) -> Option<DataEntry> {
+        let shard = self.shard(dp)?;
+        let de = shard.lock().unwrap().get(dp);
+        let counter = if de.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        de
+    }
+
+    pub fn insert(&self, dp: DataPtr, de: DataEntry) {
+        if let Some(shard) = self.shard(&dp) {
+            shard.lock().unwrap().insert(dp, de, self.shard_capacity);
+        }
+    }
+
+    /// Removes the entry cached for a data pointer.
+    pub fn invalidate(&self, dp: &DataPtr) {
+        if let Some(shard) = self.shard(dp) {
+            shard.lock().unwrap().remove(dp);
+        }
+    }
+
+    /// Removes all entries cached for a vlog.
+    pub fn invalidate_vlog(&self, vnum: VlogNum) {
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap();
+            let dps: Vec<DataPtr> = shard
+                .entries
+                .keys()
+                .filter(|dp| dp.vlog == vnum)
+                .copied()
+                .collect();
+            for dp in dps {
+                shard.remove(&dp);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let mut stats = CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            ..Default::default()
+        };
+        for shard in &self.shards {
+            let shard = shard.lock().unwrap();
+            stats.entries += shard.entries.len();
+            stats.size += shard.size;
+        }
+        stats
+    }
+
+    fn shard(&self, dp: &DataPtr) -> Option<&Mutex<Shard>> {
+        if self.shards.is_empty() {
+            return None;
+        }
+        let idx = self.hasher.hash_one(dp) as usize % self.shards.len();
+        self.shards.get(idx)
+    }
+
+    fn entry_sz(de: &DataEntry) -> usize {
+        de.key.len() + de.val.len() + ENTRY_OVERHEAD
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lru_eviction() {
+        let de = DataEntry::new(vec![0; 36], vec![0; 1000]);
+        let entry_sz = ValueCache::entry_sz(&de);
+        let cache = ValueCache::new(entry_sz * 2 * SHARDS);
+        // fill the cache well past its capacity
+        let dps: Vec<DataPtr> = (0..1000)
+            .map(|i| DataPtr::new(i % 3, i, 0, false))
+            .collect();
+        for dp in &dps {
+            cache.insert(*dp, de.clone());
+        }
+        let stats = cache.stats();
+        assert!(stats.size <= entry_sz * 2 * SHARDS);
+        assert!(stats.entries <= 2 * SHARDS);
+        // the most recently inserted entry is always cached
+        assert_eq!(cache.get(&dps[999]), Some(de.clone()));
+        assert_eq!(cache.get(&dps[0]), None);
+        assert_eq!(cache.stats().hits, 1);
+        assert_eq!(cache.stats().misses, 1);
+
+        cache.invalidate(&dps[999]);
+        assert_eq!(cache.get(&dps[999]), None);
+        cache.invalidate_vlog(0);
+        cache.invalidate_vlog(1);
+        cache.invalidate_vlog(2);
+        assert_eq!(cache.stats().entries, 0);
+        assert_eq!(cache.stats().size, 0);
+    }
+
+    #[test]
+    fn small_cache() {
+        let de = DataEntry::new(vec![0; 36], vec![0; 1000]);
+        let cache = ValueCache::new(ValueCache::entry_sz(&de) * 2);
+        let dps: Vec<DataPtr> =
+            (0..3).map(|i| DataPtr::new(0, i, 0, false)).collect();
+        for dp in &dps {
+            cache.insert(*dp, de.clone());
+        }
+        assert_eq!(cache.stats().entries, 2);
+        assert_eq!(cache.get(&dps[0]), None);
+        assert_eq!(cache.get(&dps[2]), Some(de));
+    }
+
+    #[test]
+    fn disabled_cache() {
+        let cache = ValueCache::new(0);
+        let dp = DataPtr::new(0, 0, 0, false);
+        cache.insert(dp, DataEntry::new(vec![1], vec![2]));
+        assert_eq!(cache.get(&dp), None);
+        assert_eq!(cache.stats(), CacheStats::default());
+    }
+}
@@ -0,0 +1,320 @@
+//! GhalaDb's command-line tool module.
+use crate::{
+    core::{Bytes, FileKind, VlogNum},
+    export::ImportOptions,
+    ghaladb::GhalaDb,
+    keyspace::{RawStore, DEFAULT_KEYSPACE_ID},
+};
+use clap::{Parser, Subcommand};
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, Write},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+type CliResult<T> = Result<T, Box<dyn Error>>;
+
+/// Inspects and manages a GhalaDb data store.
+///
+/// Keys and values are the raw bytes stored by the data store, that is their
+/// bincode encoding. Raw bytes are printed with non-printable bytes escaped.
+#[derive(Debug, Parser)]
+#[command(name = "ghaladb", version)]
+struct Cli {
+    /// Data store directory
+    db: PathBuf,
+    /// Read and print keys and values as hex
+    #[arg(long, global = true)]
+    hex: bool,
+    #[command(subcommand)]
+    cmd: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Print the number of keys and the size of the data store's files
+    Stats,
+    /// Print the value of a key
+    Get { key: String },
+    /// Insert a key-value pair
+    Put { key: String, value: String },
+    /// Delete a key
+    Delete { key: String },
+    /// Print key-value pairs in key order
+    Scan {
+        /// Only print keys starting with this prefix
+        #[arg(long)]
+        prefix: Option<String>,
+        /// Maximum number of pairs printed
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// Print the data pointer and data entry of every record of a vlog
+    DumpVlog { num: VlogNum },
+    /// Verify the integrity of the keys tables and vlogs
+    Verify,
+    /// Salvage a damaged data store, quarantining unreadable data
+    Repair,
+    /// Reclaim the space of stale values
+    Compact,
+    /// Write all key-value pairs to a binary dump
+    Export { file: PathBuf },
+    /// Insert the key-value pairs of a binary dump, creating the data store
+    /// if needed
+    Import {
+        file: PathBuf,
+        /// Load the pairs in bulk
+        #[arg(long)]
+        bulk: bool,
+    },
+    /// Create a consistent copy of the data store
+    Checkpoint { dest: PathBuf },
+}
+
+/// Runs the `ghaladb` command-line tool.
+pub fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli, &mut io::stdout().lock()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("ghaladb: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run<W: Write>(cli: Cli, out: &mut W) -> CliResult<()> {
+    let creates = matches!(cli.cmd, Command::Import { .. });
+    if !creates && !cli.db.is_dir() {
+        return Err(format!("no data store at: {}", cli.db.display()).into());
+    }
+    // a damaged data store may not open
+    if let Command::Repair = cli.cmd {
+        let report = GhalaDb::<Bytes, Bytes>::repair(&cli.db, None)?;
+        writeln!(
+            out,
+            "vlogs: {} records: {} fragments: {} keys: {}",
+            report.vlogs, report.records, report.fragments, report.keys
+        )?;
+        writeln!(out, "lost+found: {}", report.lost_found.display())?;
+        return Ok(());
+    }
+    let mut db: GhalaDb<Bytes, Bytes> = GhalaDb::new(&cli.db, None)?;
+    let fmt = Fmt { hex: cli.hex };
+    let ks = DEFAULT_KEYSPACE_ID;
+    match cli.cmd {
+        Command::Stats => stats(&mut db, out)?,
+        Command::Get { key } => match db.get_raw(ks, &fmt.parse(&key)?)? {
+            Some(val) => writeln!(out, "{}", fmt.show(&val))?,
+            None => return Err("key not found".into()),
+        },
+        Command::Put { key, value } => {
+            let expires_at = db.default_expiry(ks);
+            db.put_raw(ks, fmt.parse(&key)?, fmt.parse(&value)?, expires_at)?;
+        }
+        Command::Delete { key } => db.delete_raw(ks, &fmt.parse(&key)?)?,
+        Command::Scan { prefix, limit } => {
+            let prefix = match prefix {
+                Some(prefix) => fmt.parse(&prefix)?,
+                None => vec![],
+            };
+            let mut count = 0;
+            for kv in db.iter_raw(ks)? {
+                let (key, val) = kv?;
+                // keys are sorted, so the matching ones are contiguous
+                if key < prefix {
+                    continue;
+                }
+                if !key.starts_with(&prefix) || limit.is_some_and(|l| count >= l) {
+                    break;
+                }
+                writeln!(out, "{}\t{}", fmt.show(&key), fmt.show(&val))?;
+                count += 1;
+            }
+        }
+        Command::DumpVlog { num } => {
+            if !db.vlogs_man().vlog_nums().contains(&num) {
+                return Err(format!("vlog not found: {num}").into());
+            }
+            db.sync()?;
+            for record in db.vlogs_man().reader(num)? {
+                let (dp, de) = record?;
+                writeln!(
+                    out,
+                    "offset: {} len: {} compressed: {} kind: {:?} keyspace: {} \
+                     key: {} value: {}",
+                    dp.offset,
+                    dp.len,
+                    dp.compressed,
+                    de.kind,
+                    de.keyspace,
+                    fmt.show(&de.key),
+                    fmt.show(&de.val),
+                )?;
+            }
+        }
+        Command::Verify => {
+            let report = db.verify()?;
+            for issue in &report.issues {
+                writeln!(out, "{issue}")?;
+            }
+            writeln!(
+                out,
+                "keys: {} pointers: {} vlogs: {} records: {} issues: {}",
+                report.keys,
+                report.pointers,
+                report.vlogs,
+                report.records,
+                report.issues.len()
+            )?;
+            if !report.is_ok() {
+                return Err(format!("{} issues found", report.issues.len()).into());
+            }
+        }
+        Command::Repair => unreachable!("repaired before opening"),
+        Command::Compact => db.compact()?,
+        Command::Export { file } => {
+            let count = db.export(File::create(file)?, |_| {})?;
+            writeln!(out, "exported: {count}")?;
+        }
+        Command::Import { file, bulk } => {
+            let opts = ImportOptions::builder().bulk(bulk).build();
+            let count = db.import(File::open(file)?, opts, |_| {})?;
+            writeln!(out, "imported: {count}")?;
+        }
+        Command::Checkpoint { dest } => db.checkpoint(dest)?,
+    }
+    Ok(())
+}
+
+fn stats<W: Write>(db: &mut GhalaDb<Bytes, Bytes>, out: &mut W) -> CliResult<()> {
+    let (mut vlogs, mut vlogs_sz, mut others, mut others_sz) = (0, 0, 0, 0);
+    for file in db.live_files()? {
+        match file.kind {
+            FileKind::Vlog { .. } => {
+                vlogs += 1;
+                vlogs_sz += file.len;
+            }
+            FileKind::Immutable | FileKind::Mutable => {
+                others += 1;
+                others_sz += file.len;
+            }
+        }
+    }
+    writeln!(out, "keys: {}", db.len())?;
+    writeln!(out, "vlogs: {vlogs} bytes: {vlogs_sz}")?;
+    writeln!(out, "keys files: {others} bytes: {others_sz}")?;
+    Ok(())
+}
+
+/// Keys and values formatting.
+struct Fmt {
+    hex: bool,
+}
+
+impl Fmt {
+    fn parse(&self, arg: &str) -> CliResult<Bytes> {
+        if !self.hex {
+            return Ok(arg.as_bytes().to_vec());
+        }
+        if !arg.len().is_multiple_of(2) {
+            return Err(format!("invalid hex: {arg}").into());
+        }
+        (0..arg.len())
+            .step_by(2)
+            .map(|i| {
+                arg.get(i..i + 2)
+                    .and_then(|b| u8::from_str_radix(b, 16).ok())
+                    .ok_or_else(|| format!("invalid hex: {arg}").into())
+            })
+            .collect()
+    }
+
+    fn show(&self, bytes: &[u8]) -> String {
+        if self.hex {
+            bytes.iter().map(|b| format!("{b:02x}")).collect()
+        } else {
+            bytes.escape_ascii().to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn exec(args: &[&str]) -> CliResult<String> {
+        let cli = Cli::try_parse_from(["ghaladb"].iter().chain(args))?;
+        let mut out = vec![];
+        run(cli, &mut out)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    #[test]
+    fn commands() -> CliResult<()> {
+        let tmp_dir = tempdir()?;
+        let path = tmp_dir.path().join("db");
+        let db = path.to_str().unwrap();
+        assert!(exec(&[db, "stats"]).is_err());
+        let mut typed: GhalaDb<String, String> = GhalaDb::new(&path, None)?;
+        typed.put(&"apple".to_owned(), &"red".to_owned())?;
+        typed.put(&"banana".to_owned(), &"yellow".to_owned())?;
+        drop(typed);
+
+        // a String is encoded as its length, a u64, followed by its bytes
+        assert_eq!(
+            exec(&[db, "get", "--hex", "05000000000000006170706c65"])?,
+            "0300000000000000726564\n"
+        );
+        exec(&[db, "put", "raw", "bytes"])?;
+        assert_eq!(exec(&[db, "get", "raw"])?, "bytes\n");
+        exec(&[db, "delete", "raw"])?;
+        assert!(exec(&[db, "get", "raw"]).is_err());
+        assert!(exec(&[db, "get", "--hex", "zz"]).is_err());
+
+        let len = |n| format!("\\x{n:02x}{}", "\\x00".repeat(7));
+        assert_eq!(
+            exec(&[db, "scan"])?,
+            format!(
+                "{}apple\t{}red\n{}banana\t{}yellow\n",
+                len(5),
+                len(3),
+                len(6),
+                len(6)
+            )
+        );
+        let prefix = "\x06\0\0\0\0\0\0\0b";
+        assert_eq!(exec(&[db, "scan", "--prefix", prefix])?.lines().count(), 1);
+        assert_eq!(exec(&[db, "scan", "--limit", "1"])?.lines().count(), 1);
+        assert!(exec(&[db, "stats"])?.starts_with("keys: 2\n"));
+        // reopened stores keep appending to their tail vlog
+        assert_eq!(exec(&[db, "dump-vlog", "0"])?.lines().count(), 3);
+        assert!(exec(&[db, "dump-vlog", "42"]).is_err());
+        assert_eq!(
+            exec(&[db, "verify"])?,
+            "keys: 2 pointers: 2 vlogs: 1 records: 3 issues: 0\n"
+        );
+        exec(&[db, "compact"])?;
+
+        let dump = tmp_dir.path().join("dump");
+        let dump = dump.to_str().unwrap();
+        assert_eq!(exec(&[db, "export", dump])?, "exported: 2\n");
+        let copy = tmp_dir.path().join("copy");
+        let copy = copy.to_str().unwrap();
+        assert_eq!(exec(&[copy, "import", dump, "--bulk"])?, "imported: 2\n");
+        assert_eq!(exec(&[copy, "scan"])?, exec(&[db, "scan"])?);
+        let checkpoint = tmp_dir.path().join("checkpoint");
+        let checkpoint = checkpoint.to_str().unwrap();
+        exec(&[db, "checkpoint", checkpoint])?;
+        assert_eq!(exec(&[checkpoint, "scan"])?, exec(&[db, "scan"])?);
+        assert!(exec(&[checkpoint, "repair"])?.starts_with("vlogs: 1 records: 3 "));
+        assert_eq!(
+            exec(&[checkpoint, "get", "--hex", "05000000000000006170706c65"])?,
+            "0300000000000000726564\n"
+        );
+        Ok(())
+    }
+}
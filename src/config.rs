@@ -17,6 +17,41 @@ pub struct DatabaseOptions {
     /// disk.
     #[builder(default = false)]
     pub sync: bool,
+    /// read sealed (non-tail) vlogs through memory maps instead of buffered
+    /// file reads
+    #[builder(default = false)]
+    pub mmap_reads: bool,
+    /// value cache capacity in bytes, zero disables the cache: default 0
+    #[builder(default = 0)]
+    pub value_cache_size: usize,
+    /// values of at most this many bytes are stored in the keys table instead
+    /// of a vlog, zero disables inlining: default 0
+    #[builder(default = 0)]
+    pub inline_threshold: usize,
+    /// size of the chunks streamed values are split into: default 1mb
+    #[builder(default = 1_000_000)]
+    pub chunk_size: u32,
+    /// maximum size of a vlog span read at once by unordered scans: default 4mb
+    #[builder(default = 4_000_000)]
+    pub scan_readahead: usize,
+    /// number of values read ahead by iterators, zero disables prefetching:
+    /// default 0
+    #[builder(default = 0)]
+    pub prefetch_depth: usize,
+    /// number of threads prefetching values: default 4
+    #[builder(default = 4)]
+    pub prefetch_threads: usize,
+    /// time in milliseconds an iterator waits for a prefetched value before
+    /// reading it synchronously: default 1000
+    #[builder(default = 1000)]
+    pub prefetch_timeout_ms: u64,
+    /// vlog write buffer size in bytes of bulk loaders: default 64mb
+    #[builder(default = u32::MAX as usize)]
+    pub bulk_buf_size: usize,
+    /// size in bytes of the keys bulk loaders sort in memory before writing
+    /// them to an SSTable: default 64mb
+    #[builder(default = u32::MAX as usize)]
+    pub bulk_run_size: usize,
     /// enable vlog compaction
     #[builder(default = true)]
     pub compact: bool,
@@ -26,4 +61,41 @@ pub struct DatabaseOptions {
     /// keys sync interval in seconds
     #[builder(default = 10)]
     pub keys_sync_interval: u128,
+    /// keys memtable size in bytes, it is flushed to an SSTable when full:
+    /// default 4mb
+    #[builder(default = 4_000_000)]
+    pub keys_memtable_size: usize,
+    /// keys SSTable data block size in bytes: default 4kb
+    #[builder(default = 4096)]
+    pub keys_block_size: usize,
+    /// bloom filter bits per key of keys SSTables
+    #[builder(default = 10)]
+    pub keys_bloom_bits: usize,
+    /// maximum number of keys SSTables, they are compacted into a single
+    /// table when exceeded
+    #[builder(default = 8)]
+    pub keys_max_tables: usize,
+    /// enable zstd dictionary compression. When enabled a dictionary is
+    /// trained automatically once the store holds `dict_train_samples` keys.
+    #[builder(default = false)]
+    pub dict_compression: bool,
+    /// maximum zstd dictionary size in bytes: default 16kb
+    #[builder(default = 16_384)]
+    pub dict_size: usize,
+    /// number of values sampled when training a zstd dictionary
+    #[builder(default = 1000)]
+    pub dict_train_samples: usize,
+    /// zstd compression level
+    #[builder(default = 3)]
+    pub zstd_level: i32,
+    /// maximum size in bytes of a data entry, its key and value once encoded.
+    /// Larger entries are rejected, and compressed entries claiming a larger
+    /// size are treated as corrupt: default 4gb, the largest entry a vlog
+    /// holds
+    #[builder(default = u32::MAX as usize)]
+    pub max_value_size: usize,
+    /// keep the data store's files in memory instead of on disk, they are
+    /// lost once the data store is dropped
+    #[builder(default = false)]
+    pub in_memory: bool,
 }
@@ -1,4 +1,10 @@
+use crate::fs::FileSystem;
 use bincode::{Decode, Encode};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 #[cfg(test)]
 use rand::{distributions::Standard, thread_rng, Rng};
@@ -39,6 +45,138 @@ impl DataPtr {
     }
 }
 
+/// Location of a key's value.
+#[derive(Debug, Clone, Encode, Decode, PartialEq, Eq)]
+pub enum ValueRef {
+    /// A small value stored in the keys table itself.
+    Inline(Bytes),
+    /// Pointer to a data entry in a values log.
+    Ptr(DataPtr),
+    /// A large value split into chunks stored in values logs.
+    Chunked(ChunkManifest),
+}
+
+/// The chunks of a streamed value.
+#[derive(Debug, Clone, Encode, Decode, PartialEq, Eq)]
+pub struct ChunkManifest {
+    /// Size of every chunk but the last one.
+    pub chunk_size: u32,
+    /// Total size of the value.
+    pub len: u64,
+    /// Pointers to the chunks, in order.
+    pub chunks: Vec<DataPtr>,
+}
+
+/// A key's entry in the keys table.
+#[derive(Debug, Clone, Encode, Decode, PartialEq, Eq)]
+pub struct KeyEntry {
+    /// The key's value. It is absent if the key was only merged into.
+    pub val: Option<ValueRef>,
+    /// Pointers to merge operands that are yet to be combined with the value,
+    /// oldest first.
+    pub operands: Vec<DataPtr>,
+    /// Expiry time in milliseconds since the unix epoch.
+    pub expires_at: Option<u64>,
+}
+impl KeyEntry {
+    /// Create a key entry pointing to a data entry in a values log.
+    pub fn new(dp: DataPtr, expires_at: Option<u64>) -> Self {
+        Self {
+            val: Some(ValueRef::Ptr(dp)),
+            operands: vec![],
+            expires_at,
+        }
+    }
+
+    /// Create a key entry holding its value inline.
+    pub fn inline(val: Bytes, expires_at: Option<u64>) -> Self {
+        Self {
+            val: Some(ValueRef::Inline(val)),
+            operands: vec![],
+            expires_at,
+        }
+    }
+
+    /// Pointer to the key's value, if it is stored in a values log.
+    pub fn dp(&self) -> Option<DataPtr> {
+        match self.val {
+            Some(ValueRef::Ptr(dp)) => Some(dp),
+            _ => None,
+        }
+    }
+
+    /// Check if the entry points to the data at `dp`, either as its value, as
+    /// one of its value's chunks or as one of its merge operands.
+    pub fn refers_to(&self, dp: &DataPtr) -> bool {
+        match &self.val {
+            Some(ValueRef::Ptr(ptr)) if ptr == dp => return true,
+            Some(ValueRef::Chunked(manifest)) if manifest.chunks.contains(dp) => {
+                return true
+            }
+            _ => {}
+        }
+        self.operands.contains(dp)
+    }
+
+    /// Check if the entry has expired at time `now` (in milliseconds since the
+    /// unix epoch).
+    pub fn is_expired(&self, now: u64) -> bool {
+        matches!(self.expires_at, Some(at) if at <= now)
+    }
+}
+
+/// The kind of a [LiveFile].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    /// A vlog. Only the tail vlog is appended to, the other vlogs are sealed.
+    Vlog { num: VlogNum, tail: bool },
+    /// A file that is never modified once written, such as an SSTable.
+    Immutable,
+    /// A file that is rewritten or appended to, such as a manifest.
+    Mutable,
+}
+
+/// A file holding part of a data store's persisted state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiveFile {
+    /// Path relative to the data store's directory.
+    pub path: PathBuf,
+    pub len: u64,
+    pub kind: FileKind,
+}
+
+impl LiveFile {
+    /// Describes the file named `name` in the `base` directory.
+    pub fn new(
+        fs: &dyn FileSystem,
+        base: &Path,
+        name: &str,
+        kind: FileKind,
+    ) -> io::Result<LiveFile> {
+        Self::from_path(fs, base, &base.join(name), kind)
+    }
+
+    /// Describes the file at `path` within the `base` directory.
+    pub fn from_path(
+        fs: &dyn FileSystem,
+        base: &Path,
+        path: &Path,
+        kind: FileKind,
+    ) -> io::Result<LiveFile> {
+        let len = fs.size(path)?;
+        let path = path.strip_prefix(base).unwrap_or(path).to_path_buf();
+        Ok(LiveFile { path, len, kind })
+    }
+}
+
+/// Current time in milliseconds since the unix epoch.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
 #[cfg(test)]
 pub trait FixtureGen<T> {
     fn gen() -> T;
@@ -73,4 +211,34 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn key_entry_expiry() {
+        let dp = DataPtr::new(0, 0, 0, true);
+        assert!(!KeyEntry::new(dp, None).is_expired(u64::MAX));
+        assert!(!KeyEntry::new(dp, Some(10)).is_expired(9));
+        assert!(KeyEntry::new(dp, Some(10)).is_expired(10));
+    }
+
+    #[test]
+    fn key_entry_refs() {
+        let dp = DataPtr::new(0, 0, 0, true);
+        let entry = KeyEntry::new(dp, None);
+        assert_eq!(entry.dp(), Some(dp));
+        assert!(entry.refers_to(&dp));
+        let entry = KeyEntry::inline(vec![1, 2, 3], None);
+        assert_eq!(entry.dp(), None);
+        assert!(!entry.refers_to(&dp));
+        let entry = KeyEntry {
+            val: Some(ValueRef::Chunked(ChunkManifest {
+                chunk_size: 1,
+                len: 1,
+                chunks: vec![dp],
+            })),
+            operands: vec![],
+            expires_at: None,
+        };
+        assert_eq!(entry.dp(), None);
+        assert!(entry.refers_to(&dp));
+    }
 }
@@ -0,0 +1,202 @@
+//! Encryption at rest.
+//!
+//! With the `encryption` feature enabled, every vlog record is sealed with
+//! [ChaCha20-Poly1305](https://docs.rs/chacha20poly1305/latest/chacha20poly1305/)
+//! after compression, and so are the keys table, the vlogs info and the zstd
+//! dictionary files.
+//!
+//! Sealed data is laid out as:
+//!
+//! | version <1 byte> | key id <4 bytes> | nonce <12 bytes> | ciphertext |
+//!
+//! Keys are supplied by a user provided [KeyProvider]. Rotating the current key
+//! only affects newly written data, data sealed with older keys is
+//! re-encrypted with the current key as the garbage collector rewrites the
+//! vlogs. Older keys must therefore remain available until then.
+#[cfg(feature = "encryption")]
+pub use imp::*;
+
+#[cfg(not(feature = "encryption"))]
+pub(crate) use noop::Cipher;
+
+use crate::{core::Bytes, error::GhalaDbResult};
+use std::borrow::Cow;
+
+/// Seals `data` if a cipher is given, otherwise returns it as is.
+pub(crate) fn seal(cipher: Option<&Cipher>, data: Bytes) -> GhalaDbResult<Bytes> {
+    match cipher {
+        Some(cipher) => cipher.seal(&data),
+        None => Ok(data),
+    }
+}
+
+/// Opens `data` if a cipher is given, otherwise returns it as is.
+pub(crate) fn open<'a>(
+    cipher: Option<&Cipher>,
+    data: &'a [u8],
+) -> GhalaDbResult<Cow<'a, [u8]>> {
+    match cipher {
+        Some(cipher) => cipher.open(data),
+        None => Ok(Cow::Borrowed(data)),
+    }
+}
+
+#[cfg(feature = "encryption")]
+mod imp {
+    use crate::{
+        core::Bytes,
+        error::{GhalaDbError, GhalaDbResult},
+    };
+    use chacha20poly1305::{
+        aead::{Aead, AeadCore, KeyInit, OsRng},
+        ChaCha20Poly1305, Nonce,
+    };
+    use std::{
+        borrow::Cow,
+        collections::HashMap,
+        sync::{Arc, RwLock},
+    };
+
+    /// Identifies an [EncryptionKey] within a [KeyProvider].
+    pub type KeyId = u32;
+    /// A 256 bit encryption key.
+    pub type EncryptionKey = [u8; 32];
+
+    const ENVELOPE_VERSION: u8 = 1;
+    const NONCE_SZ: usize = 12;
+    const HEADER_SZ: usize = 1 + 4 + NONCE_SZ;
+
+    /// Supplies the keys used to encrypt and decrypt data at rest.
+    pub trait KeyProvider: Send + Sync {
+        /// Returns the key used to encrypt new data along with its id.
+        fn current_key(&self) -> (KeyId, EncryptionKey);
+        /// Returns the key with the given id, if known.
+        fn key(&self, id: KeyId) -> Option<EncryptionKey>;
+    }
+
+    /// An in-memory [KeyProvider].
+    ///
+    /// New data is encrypted with the most recently added key, while all
+    /// added keys remain available for decryption.
+    pub struct KeyRing {
+        inner: RwLock<(KeyId, HashMap<KeyId, EncryptionKey>)>,
+    }
+
+    impl KeyRing {
+        /// Creates a key ring holding a single key.
+        pub fn new(id: KeyId, key: EncryptionKey) -> KeyRing {
+            let keys = HashMap::from([(id, key)]);
+            Self {
+                inner: RwLock::new((id, keys)),
+            }
+        }
+
+        /// Adds a key and makes it the current key.
+        pub fn rotate(&self, id: KeyId, key: EncryptionKey) {
+            let mut inner = self.inner.write().expect("key ring lock poisoned");
+            inner.0 = id;
+            inner.1.insert(id, key);
+        }
+    }
+
+    impl KeyProvider for KeyRing {
+        fn current_key(&self) -> (KeyId, EncryptionKey) {
+            let inner = self.inner.read().expect("key ring lock poisoned");
+            (inner.0, inner.1[&inner.0])
+        }
+
+        fn key(&self, id: KeyId) -> Option<EncryptionKey> {
+            let inner = self.inner.read().expect("key ring lock poisoned");
+            inner.1.get(&id).copied()
+        }
+    }
+
+    /// Seals and opens data using keys from a [KeyProvider].
+    #[derive(Clone)]
+    pub(crate) struct Cipher {
+        provider: Arc<dyn KeyProvider>,
+    }
+
+    impl Cipher {
+        pub fn new(provider: Arc<dyn KeyProvider>) -> Cipher {
+            Self { provider }
+        }
+
+        /// Encrypts `data` using the current key.
+        pub fn seal(&self, data: &[u8]) -> GhalaDbResult<Bytes> {
+            let (id, key) = self.provider.current_key();
+            let aead = ChaCha20Poly1305::new(&key.into());
+            let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+            let ct = aead
+                .encrypt(&nonce, data)
+                .map_err(|_| GhalaDbError::EncryptionError)?;
+            let mut out = Vec::with_capacity(HEADER_SZ + ct.len());
+            out.push(ENVELOPE_VERSION);
+            out.extend_from_slice(&id.to_le_bytes());
+            out.extend_from_slice(&nonce);
+            out.extend_from_slice(&ct);
+            Ok(out)
+        }
+
+        /// Decrypts data previously sealed by [Cipher::seal].
+        pub fn open<'a>(&self, data: &'a [u8]) -> GhalaDbResult<Cow<'a, [u8]>> {
+            if data.len() < HEADER_SZ || data[0] != ENVELOPE_VERSION {
+                return Err(GhalaDbError::DecryptionError);
+            }
+            let id = KeyId::from_le_bytes(data[1..5].try_into().unwrap());
+            let key = self
+                .provider
+                .key(id)
+                .ok_or(GhalaDbError::MissingEncryptionKey(id))?;
+            let aead = ChaCha20Poly1305::new(&key.into());
+            let nonce = Nonce::from_slice(&data[5..HEADER_SZ]);
+            let plain = aead
+                .decrypt(nonce, &data[HEADER_SZ..])
+                .map_err(|_| GhalaDbError::DecryptionError)?;
+            Ok(Cow::Owned(plain))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn seal_open() -> GhalaDbResult<()> {
+            let ring = Arc::new(KeyRing::new(1, [7u8; 32]));
+            let cipher = Cipher::new(ring.clone());
+            let sealed = cipher.seal(b"hello world")?;
+            assert_ne!(&sealed[HEADER_SZ..], b"hello world");
+            ring.rotate(2, [9u8; 32]);
+            assert_eq!(cipher.open(&sealed)?.as_ref(), b"hello world");
+            let sealed = cipher.seal(b"hello world")?;
+            assert_eq!(&sealed[1..5], &2u32.to_le_bytes());
+
+            let other = Cipher::new(Arc::new(KeyRing::new(2, [0u8; 32])));
+            assert!(other.open(&sealed).is_err());
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(feature = "encryption"))]
+mod noop {
+    use crate::{core::Bytes, error::GhalaDbResult};
+    use std::borrow::Cow;
+
+    /// Stand-in for the cipher when the `encryption` feature is disabled.
+    ///
+    /// It has no values, an `Option<Cipher>` is thus always `None`.
+    #[derive(Clone)]
+    pub(crate) enum Cipher {}
+
+    impl Cipher {
+        pub fn seal(&self, _data: &[u8]) -> GhalaDbResult<Bytes> {
+            match *self {}
+        }
+
+        pub fn open<'a>(&self, _data: &'a [u8]) -> GhalaDbResult<Cow<'a, [u8]>> {
+            match *self {}
+        }
+    }
+}
@@ -1,62 +1,160 @@
+use crate::{
+    core::Bytes,
+    crypto::{self, Cipher},
+    error::GhalaDbResult,
+};
 use bincode::{Decode, Encode};
 use snap::raw::{Decoder, Encoder};
+use std::borrow::Cow;
+use zstd::bulk::{Compressor, Decompressor};
+
+/// Compression codec used by a [Dec].
+enum Codec {
+    /// No compression.
+    None,
+    /// Per record [snappy](https://docs.rs/snap/latest/snap/) compression.
+    Snappy(Box<Encoder>, Decoder),
+    /// [zstd](https://docs.rs/zstd/latest/zstd/) compression using a trained
+    /// dictionary.
+    Zstd(Compressor<'static>, Decompressor<'static>),
+}
+
 /// Data Encoding and Compression (DEC)
-#[derive(Debug)]
 pub(crate) struct Dec {
-    encoder: Option<snap::raw::Encoder>,
-    decoder: Option<snap::raw::Decoder>,
+    codec: Codec,
+    /// Cipher used to encrypt compressed data, if any.
+    cipher: Option<Cipher>,
+    /// Maximum decompressed size of the data.
+    max_size: usize,
 }
 
 impl Dec {
     /// Create a new Data Encoder and Compressor
     pub fn new(compress: bool) -> Dec {
-        let (encoder, decoder) = if compress {
-            (Some(Encoder::new()), Some(Decoder::new()))
+        let codec = if compress {
+            Codec::Snappy(Box::new(Encoder::new()), Decoder::new())
         } else {
-            (None, None)
+            Codec::None
         };
 
-        Self { encoder, decoder }
+        Self {
+            codec,
+            cipher: None,
+            max_size: usize::MAX,
+        }
+    }
+
+    /// Create a new Data Encoder and Compressor that uses zstd with the given
+    /// dictionary.
+    pub fn with_dict(dict: &[u8], level: i32) -> GhalaDbResult<Dec> {
+        let cmp = Compressor::with_dictionary(level, dict)?;
+        let dcmp = Decompressor::with_dictionary(dict)?;
+        Ok(Self {
+            codec: Codec::Zstd(cmp, dcmp),
+            cipher: None,
+            max_size: usize::MAX,
+        })
+    }
+
+    /// Sets the cipher used by [Dec::seal] and [Dec::open].
+    pub fn with_cipher(mut self, cipher: Option<Cipher>) -> Dec {
+        self.cipher = cipher;
+        self
     }
+
+    /// Sets the maximum size data is decompressed to, larger compressed data
+    /// fails to decode.
+    pub fn with_max_size(mut self, max_size: usize) -> Dec {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Encrypts bytes if a cipher is set.
+    pub fn seal(&self, bytes: Bytes) -> GhalaDbResult<Bytes> {
+        crypto::seal(self.cipher.as_ref(), bytes)
+    }
+
+    /// Decrypts bytes if a cipher is set.
+    pub fn open<'a>(&self, bytes: &'a [u8]) -> GhalaDbResult<Cow<'a, [u8]>> {
+        crypto::open(self.cipher.as_ref(), bytes)
+    }
+
     /// Deserializes a slice of bytes into an instance of `T`
-    pub fn deser<T: Decode>(
-        &mut self,
-        bytes: &[u8],
-    ) -> crate::error::GhalaDbResult<T> {
-        let t: T = if let Some(ref mut dcr) = self.decoder {
-            Self::deser_raw(&dcr.decompress_vec(bytes)?)?
-        } else {
-            Self::deser_raw(bytes)?
+    pub fn deser<T: Decode>(&mut self, bytes: &[u8]) -> GhalaDbResult<T> {
+        let t: T = match self.codec {
+            Codec::None => Self::deser_raw(bytes)?,
+            Codec::Snappy(_, ref mut dcr) => {
+                Self::check_size(snap::raw::decompress_len(bytes)?, self.max_size)?;
+                Self::deser_raw(&dcr.decompress_vec(bytes)?)?
+            }
+            Codec::Zstd(_, ref mut dcr) => {
+                let cap = Self::zstd_content_size(bytes)?;
+                Self::check_size(cap, self.max_size)?;
+                Self::deser_raw(&dcr.decompress(bytes, cap)?)?
+            }
         };
         Ok(t)
     }
     /// Deserializes a slice of bytes into an instance of `T` without
     /// decompressing
-    pub fn deser_raw<T: Decode>(bytes: &[u8]) -> crate::error::GhalaDbResult<T> {
+    pub fn deser_raw<T: Decode>(bytes: &[u8]) -> GhalaDbResult<T> {
         Ok(bincode::decode_from_slice(bytes, Self::conf())?.0)
     }
 
     /// Serializes a serializable object into a `Vec` of bytes
-    pub fn ser<T: ?Sized + Encode>(
-        &mut self,
-        value: &T,
-    ) -> crate::error::GhalaDbResult<Vec<u8>> {
+    pub fn ser<T: ?Sized + Encode>(&mut self, value: &T) -> GhalaDbResult<Vec<u8>> {
         let bytes = Self::ser_raw(value)?;
-        let ret = if let Some(ref mut enc) = self.encoder {
-            enc.compress_vec(&bytes)?
-        } else {
-            bytes
+        self.compress(bytes)
+    }
+
+    /// Compresses serialized bytes.
+    pub fn compress(&mut self, bytes: Vec<u8>) -> GhalaDbResult<Vec<u8>> {
+        let ret = match self.codec {
+            Codec::None => bytes,
+            Codec::Snappy(ref mut enc, _) => enc.compress_vec(&bytes)?,
+            Codec::Zstd(ref mut enc, _) => enc.compress(&bytes)?,
         };
         Ok(ret)
     }
     /// Serializes a serializable object into a `Vec` of bytes without
     /// compression
-    pub fn ser_raw<T: ?Sized + Encode>(
-        value: &T,
-    ) -> crate::error::GhalaDbResult<Vec<u8>> {
+    pub fn ser_raw<T: ?Sized + Encode>(value: &T) -> GhalaDbResult<Vec<u8>> {
         Ok(bincode::encode_to_vec(value, Self::conf())?)
     }
 
+    /// Trains a zstd dictionary of at most `max_size` bytes from the given
+    /// samples.
+    pub fn train_dict(
+        samples: &[Vec<u8>],
+        max_size: usize,
+    ) -> GhalaDbResult<Vec<u8>> {
+        Ok(zstd::dict::from_samples(samples, max_size)?)
+    }
+
+    /// Reads the decompressed size from a zstd frame header.
+    fn zstd_content_size(bytes: &[u8]) -> GhalaDbResult<usize> {
+        match zstd::zstd_safe::get_frame_content_size(bytes) {
+            Ok(Some(sz)) => Ok(sz as usize),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "zstd frame content size unknown",
+            )
+            .into()),
+        }
+    }
+
+    /// Fails if the size a frame decompresses to exceeds `max_size`.
+    fn check_size(size: usize, max_size: usize) -> GhalaDbResult<()> {
+        if size > max_size {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("decompressed size {size} exceeds {max_size}"),
+            )
+            .into());
+        }
+        Ok(())
+    }
+
     #[inline]
     fn conf() -> impl bincode::config::Config {
         bincode::config::standard()
@@ -65,3 +163,25 @@ impl Dec {
             .with_no_limit()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zstd_dict_roundtrip() -> GhalaDbResult<()> {
+        let samples: Vec<Vec<u8>> = (0..500)
+            .map(|i| Dec::ser_raw(&format!("sample value number {i}")))
+            .collect::<GhalaDbResult<_>>()?;
+        let dict = Dec::train_dict(&samples, 4096)?;
+        let mut dec = Dec::with_dict(&dict, 3)?;
+        let val = "sample value number 1000".to_owned();
+        let bytes = dec.ser(&val)?;
+        let out: String = dec.deser(&bytes)?;
+        assert_eq!(out, val);
+        // the decompressed size claimed by a frame is bounded
+        let mut dec = Dec::with_dict(&dict, 3)?.with_max_size(8);
+        assert!(dec.deser::<String>(&bytes).is_err());
+        Ok(())
+    }
+}
@@ -25,10 +25,60 @@ pub enum GhalaDbError {
     /// The datastore path exists and is, unexpectedly, not a directory.
     #[error("Database path exists but it's not a directory: {0}")]
     DbPathNotDirectory(PathBuf),
+    /// A data entry is larger than the
+    /// [max_value_size](crate::DatabaseOptions::max_value_size) option.
+    #[error("Data entry too large: {0} bytes")]
+    ValueTooLarge(usize),
+    /// The data store was written in a format version that is not supported,
+    /// most likely by a newer release.
+    #[error("Unsupported data store format version: {0}")]
+    UnsupportedFormat(u32),
+    /// A key is longer than the 65535 bytes supported by keys tables.
+    #[error("Key too large: {0} bytes")]
+    KeyTooLarge(usize),
     /// A Vlog entity was not found.
     #[error("Missing Vlog: {0}")]
     MissingVlog(VlogNum),
     /// Data compression using [snap](https://docs.rs/snap/latest/snap/) failed.
     #[error(transparent)]
     DataCompressionError(#[from] snap::Error),
+    /// Data encryption failed.
+    #[cfg(feature = "encryption")]
+    #[error("Data encryption failed")]
+    EncryptionError,
+    /// Data decryption failed. The data is corrupt, was tampered with or was
+    /// encrypted using a different key.
+    #[cfg(feature = "encryption")]
+    #[error("Data decryption failed")]
+    DecryptionError,
+    /// The key provider does not hold the key used to encrypt the data.
+    #[cfg(feature = "encryption")]
+    #[error("Missing encryption key: {0}")]
+    MissingEncryptionKey(u32),
+    /// A key holds merge operands but no merge operator is registered.
+    #[error("No merge operator registered")]
+    MissingMergeOperator,
+    /// A key entry holds neither a value nor merge operands.
+    #[error("Corrupt key entry")]
+    CorruptKeyEntry,
+    /// A keyspace was not found.
+    #[error("Missing keyspace: {0}")]
+    MissingKeyspace(u32),
+    /// A keys table file is corrupt.
+    #[error("Corrupt keys table: {0}")]
+    CorruptKeysTable(PathBuf),
+    /// A directory expected to be empty, such as a checkpoint or restore
+    /// destination, is not.
+    #[error("Directory is not empty: {0}")]
+    DirNotEmpty(PathBuf),
+    /// A backup file is missing or does not match its checksum.
+    #[error("Corrupt backup: {0}")]
+    CorruptBackup(PathBuf),
+    /// A dump being imported is malformed.
+    #[error("Invalid dump: {0}")]
+    InvalidDump(String),
+    /// JSON encoding or decoding failed.
+    #[cfg(feature = "json")]
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
 }
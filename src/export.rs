@@ -0,0 +1,330 @@
+//! GhalaDb's logical export and import module.
+use crate::{
+    core::Bytes,
+    error::{GhalaDbError, GhalaDbResult},
+    ghaladb::GhalaDb,
+    keyspace::{RawStore, DEFAULT_KEYSPACE_ID},
+    sstable::MAX_KEY_SZ,
+};
+use bincode::{Decode, Encode};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use typed_builder::TypedBuilder;
+
+/// Magic bytes opening a binary dump.
+const DUMP_MAGIC: &[u8; 8] = b"GHALADMP";
+/// Number of records between progress callbacks.
+pub const PROGRESS_INTERVAL: u64 = 10_000;
+
+/// Import Configuration
+#[derive(Debug, Copy, Clone, TypedBuilder)]
+pub struct ImportOptions {
+    /// load the records with a [BulkLoader](crate::BulkLoader), the records
+    /// become visible once all of them are loaded.
+    #[builder(default = false)]
+    pub bulk: bool,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions::builder().build()
+    }
+}
+
+/// Reports progress every [PROGRESS_INTERVAL] records.
+fn report<F: FnMut(u64)>(progress: &mut F, count: u64) {
+    if count.is_multiple_of(PROGRESS_INTERVAL) {
+        progress(count);
+    }
+}
+
+impl<K, V> GhalaDb<K, V>
+where
+    K: Encode + Decode,
+    V: Encode + Decode,
+{
+    /// Writes all key-value pairs to a binary dump, in key order.
+    ///
+    /// The dump starts with the `GHALADMP` magic bytes, followed by a record
+    /// per key-value pair made of the key's length as a little endian `u32`,
+    /// the key, the value's length as a little endian `u64` and the value.
+    /// Keys and values are written in their encoded form, so the dump can be
+    /// imported into a data store using the same key and value types.
+    ///
+    /// `progress` is called with the number of records written every
+    /// [PROGRESS_INTERVAL] records, and once done. Returns the number of
+    /// records written.
+    pub fn export<W, F>(&mut self, writer: W, mut progress: F) -> GhalaDbResult<u64>
+    where
+        W: Write,
+        F: FnMut(u64),
+    {
+        trace!("GhalaDb::export");
+        let mut wtr = BufWriter::new(writer);
+        wtr.write_all(DUMP_MAGIC)?;
+        let mut count = 0;
+        for kv in self.iter_raw(DEFAULT_KEYSPACE_ID)? {
+            let (key, val) = kv?;
+            wtr.write_all(&(key.len() as u32).to_le_bytes())?;
+            wtr.write_all(&key)?;
+            wtr.write_all(&(val.len() as u64).to_le_bytes())?;
+            wtr.write_all(&val)?;
+            count += 1;
+            report(&mut progress, count);
+        }
+        wtr.flush()?;
+        progress(count);
+        Ok(count)
+    }
+
+    /// Inserts the key-value pairs of a binary dump written by
+    /// [GhalaDb::export].
+    ///
+    /// `progress` is called with the number of records read every
+    /// [PROGRESS_INTERVAL] records, and once done. Returns the number of
+    /// records imported.
+    pub fn import<R, F>(
+        &mut self,
+        reader: R,
+        opts: ImportOptions,
+        mut progress: F,
+    ) -> GhalaDbResult<u64>
+    where
+        R: Read,
+        F: FnMut(u64),
+    {
+        trace!("GhalaDb::import");
+        let mut rdr = BufReader::new(reader);
+        let mut magic = [0u8; 8];
+        rdr.read_exact(&mut magic)
+            .map_err(|_| GhalaDbError::InvalidDump("missing header".to_owned()))?;
+        if &magic != DUMP_MAGIC {
+            return Err(GhalaDbError::InvalidDump("bad magic bytes".to_owned()));
+        }
+        let max_val_len = self.opts().max_value_size as u64;
+        self.load(opts, &mut progress, || {
+            let mut key_len = [0u8; 4];
+            match rdr.read_exact(&mut key_len) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e.into()),
+            }
+            let truncated =
+                |_| GhalaDbError::InvalidDump("truncated record".to_owned());
+            // lengths are checked before allocating, as dumps are untrusted
+            let key_len = u32::from_le_bytes(key_len) as usize;
+            if key_len > MAX_KEY_SZ {
+                return Err(GhalaDbError::InvalidDump(format!(
+                    "key length {key_len} exceeds {MAX_KEY_SZ}"
+                )));
+            }
+            let mut key = vec![0u8; key_len];
+            rdr.read_exact(&mut key).map_err(truncated)?;
+            let mut val_len = [0u8; 8];
+            rdr.read_exact(&mut val_len).map_err(truncated)?;
+            let val_len = u64::from_le_bytes(val_len);
+            if val_len > max_val_len {
+                return Err(GhalaDbError::InvalidDump(format!(
+                    "value length {val_len} exceeds {max_val_len}"
+                )));
+            }
+            let mut val = vec![];
+            (&mut rdr).take(val_len).read_to_end(&mut val)?;
+            if val.len() as u64 != val_len {
+                return Err(GhalaDbError::InvalidDump(
+                    "truncated record".to_owned(),
+                ));
+            }
+            Ok(Some((key, val)))
+        })
+    }
+
+    /// Writes all key-value pairs as JSON Lines, in key order.
+    ///
+    /// Every line holds a `{"key": .., "value": ..}` object. `progress` is
+    /// called as in [GhalaDb::export]. Returns the number of records written.
+    #[cfg(feature = "json")]
+    pub fn export_json<W, F>(
+        &mut self,
+        writer: W,
+        mut progress: F,
+    ) -> GhalaDbResult<u64>
+    where
+        W: Write,
+        F: FnMut(u64),
+        K: serde::Serialize,
+        V: serde::Serialize,
+    {
+        trace!("GhalaDb::export_json");
+        let mut wtr = BufWriter::new(writer);
+        let mut count = 0;
+        for kv in self.iter()? {
+            let (key, value) = kv?;
+            serde_json::to_writer(&mut wtr, &JsonRecord { key, value })?;
+            wtr.write_all(b"\n")?;
+            count += 1;
+            report(&mut progress, count);
+        }
+        wtr.flush()?;
+        progress(count);
+        Ok(count)
+    }
+
+    /// Inserts the key-value pairs of JSON Lines written by
+    /// [GhalaDb::export_json]. Blank lines are skipped.
+    ///
+    /// `progress` is called as in [GhalaDb::import]. Returns the number of
+    /// records imported.
+    #[cfg(feature = "json")]
+    pub fn import_json<R, F>(
+        &mut self,
+        reader: R,
+        opts: ImportOptions,
+        mut progress: F,
+    ) -> GhalaDbResult<u64>
+    where
+        R: Read,
+        F: FnMut(u64),
+        K: serde::de::DeserializeOwned,
+        V: serde::de::DeserializeOwned,
+    {
+        use crate::dec::Dec;
+        use std::io::BufRead;
+
+        trace!("GhalaDb::import_json");
+        let mut lines = BufReader::new(reader).lines();
+        self.load(opts, &mut progress, || loop {
+            let Some(line) = lines.next() else {
+                return Ok(None);
+            };
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: JsonRecord<K, V> = serde_json::from_str(&line)?;
+            return Ok(Some((
+                Dec::ser_raw(&record.key)?,
+                Dec::ser_raw(&record.value)?,
+            )));
+        })
+    }
+
+    /// Inserts the records returned by `next` until it returns `None`.
+    fn load<F, N>(
+        &mut self,
+        opts: ImportOptions,
+        progress: &mut F,
+        mut next: N,
+    ) -> GhalaDbResult<u64>
+    where
+        F: FnMut(u64),
+        N: FnMut() -> GhalaDbResult<Option<(Bytes, Bytes)>>,
+    {
+        let mut count = 0;
+        if opts.bulk {
+            let mut loader = self.bulk_loader();
+            while let Some((key, val)) = next()? {
+                loader.put_raw(key, val)?;
+                count += 1;
+                report(progress, count);
+            }
+            loader.commit()?;
+        } else {
+            let ks = DEFAULT_KEYSPACE_ID;
+            while let Some((key, val)) = next()? {
+                let expires_at = self.default_expiry(ks);
+                self.put_raw(ks, key, val, expires_at)?;
+                count += 1;
+                report(progress, count);
+            }
+        }
+        progress(count);
+        Ok(count)
+    }
+}
+
+/// A JSON Lines record.
+#[cfg(feature = "json")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct JsonRecord<K, V> {
+    key: K,
+    value: V,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DatabaseOptions;
+    use tempfile::tempdir;
+
+    fn populated(path: &std::path::Path) -> GhalaDbResult<GhalaDb<u32, String>> {
+        let opts = DatabaseOptions::builder().sync(false).build();
+        let mut db = GhalaDb::new(path, Some(opts))?;
+        for i in 0..25_000 {
+            db.put(&i, &format!("value-{i}"))?;
+        }
+        Ok(db)
+    }
+
+    #[test]
+    fn binary_dump() -> GhalaDbResult<()> {
+        let tmp_dir = tempdir()?;
+        let mut db = populated(&tmp_dir.path().join("src"))?;
+        let mut dump = vec![];
+        let mut reports = vec![];
+        assert_eq!(db.export(&mut dump, |n| reports.push(n))?, 25_000);
+        assert_eq!(reports, [10_000, 20_000, 25_000]);
+
+        for bulk in [false, true] {
+            let path = tmp_dir.path().join(format!("dst-{bulk}"));
+            let mut dst: GhalaDb<u32, String> = GhalaDb::new(&path, None)?;
+            let opts = ImportOptions::builder().bulk(bulk).build();
+            assert_eq!(dst.import(dump.as_slice(), opts, |_| {})?, 25_000);
+            drop(dst);
+            let mut dst: GhalaDb<u32, String> = GhalaDb::new(&path, None)?;
+            assert_eq!(dst.len(), 25_000);
+            assert_eq!(dst.get(&42)?, Some("value-42".to_owned()));
+        }
+
+        let mut dst: GhalaDb<u32, String> =
+            GhalaDb::new(tmp_dir.path().join("bad"), None)?;
+        let res = dst.import(&b"NOTADUMP"[..], ImportOptions::default(), |_| {});
+        assert!(matches!(res, Err(GhalaDbError::InvalidDump(_))));
+        let res =
+            dst.import(&dump[..dump.len() - 3], ImportOptions::default(), |_| {});
+        assert!(matches!(res, Err(GhalaDbError::InvalidDump(_))));
+
+        // corrupt lengths are rejected before allocating
+        let mut corrupt = DUMP_MAGIC.to_vec();
+        corrupt.extend(u32::MAX.to_le_bytes());
+        let res = dst.import(corrupt.as_slice(), ImportOptions::default(), |_| {});
+        assert!(matches!(res, Err(GhalaDbError::InvalidDump(_))));
+        let mut corrupt = DUMP_MAGIC.to_vec();
+        corrupt.extend(4u32.to_le_bytes());
+        corrupt.extend(7u32.to_le_bytes());
+        corrupt.extend(u64::MAX.to_le_bytes());
+        let res = dst.import(corrupt.as_slice(), ImportOptions::default(), |_| {});
+        assert!(matches!(res, Err(GhalaDbError::InvalidDump(_))));
+        Ok(())
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_lines() -> GhalaDbResult<()> {
+        let tmp_dir = tempdir()?;
+        let mut db = populated(&tmp_dir.path().join("src"))?;
+        let mut lines = vec![];
+        assert_eq!(db.export_json(&mut lines, |_| {})?, 25_000);
+        let first = lines.split(|b| *b == b'\n').next().unwrap();
+        assert!(first.starts_with(b"{\"key\":"));
+
+        let mut dst: GhalaDb<u32, String> =
+            GhalaDb::new(tmp_dir.path().join("dst"), None)?;
+        let imported =
+            dst.import_json(lines.as_slice(), ImportOptions::default(), |_| {})?;
+        assert_eq!(imported, 25_000);
+        assert_eq!(dst.get(&24_999)?, Some("value-24999".to_owned()));
+        Ok(())
+    }
+}
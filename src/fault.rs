@@ -0,0 +1,627 @@
+//! GhalaDb's fault injection module.
+use crate::{
+    core::Bytes,
+    fs::{FileSystem, FsFile, InMemoryFs, OpenMode},
+    utils::parent_dir,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, RwLock},
+};
+
+/// An in-memory file system simulating crashes and I/O failures, used to test
+/// that a data store survives them.
+///
+/// Appended bytes are only durable once their file is synced. A simulated
+/// crash loses the unsynced bytes of every file, or tears them at a random
+/// offset if torn writes are enabled. Creating and removing files and
+/// directories is durable right away, while a rename is undone by a crash
+/// unless the directory it moved to was synced since.
+///
+/// Calls can be made to fail once a number of writes went through, as if the
+/// machine crashed at that point. Failures are deterministic for a given
+/// seed.
+///
+/// ```
+/// use ghaladb::{FaultInjectionFs, FileSystem, OpenMode};
+/// use std::path::Path;
+///
+/// let fs = FaultInjectionFs::new(42);
+/// let file = fs.open(Path::new("log"), OpenMode::Create).unwrap();
+/// file.append(b"synced").unwrap();
+/// file.sync().unwrap();
+/// file.append(b" lost").unwrap();
+/// fs.crash();
+/// assert!(file.append(b"!").is_err());
+/// fs.recover();
+/// assert_eq!(fs.read(Path::new("log")).unwrap(), b"synced");
+/// ```
+#[derive(Debug, Clone)]
+pub struct FaultInjectionFs {
+    /// Namespace of the files, whose contents are kept in `files`.
+    tree: InMemoryFs,
+    files: Arc<Mutex<BTreeMap<PathBuf, Arc<FaultFile>>>>,
+    faults: Arc<Mutex<Faults>>,
+    /// Renames whose destination directory was not synced since, oldest
+    /// first.
+    renames: Arc<Mutex<Vec<Rename>>>,
+}
+
+/// A rename that is not durable yet.
+#[derive(Debug)]
+struct Rename {
+    from: PathBuf,
+    to: PathBuf,
+    /// The file replaced by the rename, if any.
+    replaced: Option<Arc<FaultFile>>,
+}
+
+#[derive(Debug)]
+struct Faults {
+    /// xorshift random number generator state.
+    rng: u64,
+    /// Number of writes that succeed before calls start failing.
+    budget: Option<u64>,
+    /// Set once calls fail, until recovery.
+    failing: bool,
+    torn_writes: bool,
+}
+
+impl Faults {
+    /// Counts a call, which fails with `Err(true)` if it triggers the
+    /// failure, and with `Err(false)` if calls were already failing.
+    fn call(&mut self, write: bool) -> Result<(), bool> {
+        if self.failing {
+            return Err(false);
+        }
+        match self.budget {
+            Some(0) if write => {
+                self.failing = true;
+                Err(true)
+            }
+            Some(ref mut n) if write => {
+                *n -= 1;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Number of bytes surviving a crash out of `len` unsynced ones.
+    fn survivors(&mut self, len: u64) -> u64 {
+        if !self.torn_writes || len == 0 {
+            return 0;
+        }
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng % (len + 1)
+    }
+}
+
+#[derive(Debug)]
+struct FaultFile {
+    data: RwLock<Bytes>,
+    /// Length of the durable prefix of the file.
+    synced: Mutex<u64>,
+    faults: Arc<Mutex<Faults>>,
+}
+
+fn injected() -> io::Error {
+    io::Error::other("injected fault")
+}
+
+fn poisoned() -> io::Error {
+    io::Error::other("fault injection file system lock poisoned")
+}
+
+fn check(faults: &Mutex<Faults>, write: bool) -> io::Result<()> {
+    let mut faults = faults.lock().map_err(|_| poisoned())?;
+    faults.call(write).map_err(|_| injected())
+}
+
+impl FaultFile {
+    fn new(faults: Arc<Mutex<Faults>>) -> FaultFile {
+        FaultFile {
+            data: RwLock::default(),
+            synced: Mutex::new(0),
+            faults,
+        }
+    }
+}
+
+impl FsFile for FaultFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        check(&self.faults, false)?;
+        let data = self.data.read().map_err(|_| poisoned())?;
+        let start = (offset as usize).min(data.len());
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        Ok(n)
+    }
+
+    fn append(&self, buf: &[u8]) -> io::Result<()> {
+        let mut faults = self.faults.lock().map_err(|_| poisoned())?;
+        let (len, res) = match faults.call(true) {
+            Ok(()) => (buf.len(), Ok(())),
+            // the write that failed may be partially done
+            Err(true) => {
+                let n = faults.survivors(buf.len() as u64) as usize;
+                (n, Err(injected()))
+            }
+            Err(false) => (0, Err(injected())),
+        };
+        drop(faults);
+        let mut data = self.data.write().map_err(|_| poisoned())?;
+        data.extend_from_slice(&buf[..len]);
+        res
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        check(&self.faults, true)?;
+        let len = self.data.read().map_err(|_| poisoned())?.len() as u64;
+        *self.synced.lock().map_err(|_| poisoned())? = len;
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        check(&self.faults, false)?;
+        Ok(self.data.read().map_err(|_| poisoned())?.len() as u64)
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        check(&self.faults, true)?;
+        self.data
+            .write()
+            .map_err(|_| poisoned())?
+            .truncate(len as usize);
+        // the dropped bytes are gone even if the new size is not synced
+        let mut synced = self.synced.lock().map_err(|_| poisoned())?;
+        *synced = (*synced).min(len);
+        Ok(())
+    }
+}
+
+impl FaultInjectionFs {
+    /// Creates an empty file system, whose torn writes are drawn from `seed`.
+    pub fn new(seed: u64) -> FaultInjectionFs {
+        let faults = Faults {
+            // the generator's state must not be zero
+            rng: seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1,
+            budget: None,
+            failing: false,
+            torn_writes: false,
+        };
+        FaultInjectionFs {
+            tree: InMemoryFs::new(),
+            files: Arc::default(),
+            faults: Arc::new(Mutex::new(faults)),
+            renames: Arc::default(),
+        }
+    }
+
+    /// Enables torn writes: a crash keeps a random prefix of the unsynced
+    /// bytes of each file, as does the write triggering a failure.
+    pub fn set_torn_writes(&self, enabled: bool) {
+        if let Ok(mut faults) = self.faults() {
+            faults.torn_writes = enabled;
+        }
+    }
+
+    /// Makes calls fail once `writes` more writes went through. Appending,
+    /// truncating, syncing files and directories, and creating, renaming or
+    /// removing files are writes.
+    pub fn fail_after(&self, writes: u64) {
+        if let Ok(mut faults) = self.faults() {
+            faults.budget = Some(writes);
+        }
+    }
+
+    /// Simulates a crash: every call fails until [FaultInjectionFs::recover]
+    /// is called.
+    pub fn crash(&self) {
+        if let Ok(mut faults) = self.faults() {
+            faults.failing = true;
+        }
+    }
+
+    /// Restarts after a crash, losing the bytes that were not synced and
+    /// undoing the renames that were not synced, and clears the injected
+    /// failures.
+    pub fn recover(&self) {
+        let renames = match self.renames.lock() {
+            Ok(mut renames) => std::mem::take(&mut *renames),
+            Err(_) => return,
+        };
+        for rename in renames.into_iter().rev() {
+            self.undo(rename).ok();
+        }
+        let (Ok(files), Ok(mut faults)) = (self.files(), self.faults()) else {
+            return;
+        };
+        let mut seen = BTreeSet::new();
+        // linked files share their contents
+        for file in files.values().filter(|f| seen.insert(Arc::as_ptr(f))) {
+            let (Ok(mut data), Ok(mut synced)) =
+                (file.data.write(), file.synced.lock())
+            else {
+                continue;
+            };
+            let unsynced = data.len() as u64 - *synced;
+            let len = *synced + faults.survivors(unsynced);
+            data.truncate(len as usize);
+            *synced = len;
+        }
+        faults.budget = None;
+        faults.failing = false;
+    }
+
+    /// Moves a renamed file or directory back, and restores the file it
+    /// replaced.
+    fn undo(&self, rename: Rename) -> io::Result<()> {
+        debug!("FaultInjectionFs::undo rename: {rename:?}");
+        let mut files = self.files()?;
+        if self.tree.exists(&rename.to) {
+            self.tree.rename(&rename.to, &rename.from)?;
+            move_files(&mut files, &rename.to, &rename.from);
+        }
+        if let Some(file) = rename.replaced {
+            self.tree.open(&rename.to, OpenMode::Create)?;
+            files.insert(rename.to, file);
+        }
+        Ok(())
+    }
+
+    fn faults(&self) -> io::Result<MutexGuard<'_, Faults>> {
+        self.faults.lock().map_err(|_| poisoned())
+    }
+
+    fn files(
+        &self,
+    ) -> io::Result<MutexGuard<'_, BTreeMap<PathBuf, Arc<FaultFile>>>> {
+        self.files.lock().map_err(|_| poisoned())
+    }
+
+    fn check(&self, write: bool) -> io::Result<()> {
+        check(&self.faults, write)
+    }
+}
+
+impl FileSystem for FaultInjectionFs {
+    fn open(&self, path: &Path, mode: OpenMode) -> io::Result<Arc<dyn FsFile>> {
+        self.check(mode != OpenMode::Read)?;
+        self.tree.open(path, mode)?;
+        let mut files = self.files()?;
+        let file = match (mode, files.get(path)) {
+            (OpenMode::Read | OpenMode::Append, Some(file)) => file.clone(),
+            _ => {
+                let file = Arc::new(FaultFile::new(self.faults.clone()));
+                files.insert(path.to_path_buf(), file.clone());
+                file
+            }
+        };
+        Ok(file)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.check(true)?;
+        let mut files = self.files()?;
+        let replaced = files.get(to).cloned();
+        self.tree.rename(from, to)?;
+        move_files(&mut files, from, to);
+        let rename = Rename {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+            replaced,
+        };
+        self.renames.lock().map_err(|_| poisoned())?.push(rename);
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.check(true)?;
+        self.tree.remove(path)?;
+        self.files()?.remove(path);
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.check(true)?;
+        self.tree.remove_dir_all(path)?;
+        self.files()?.retain(|p, _| !p.starts_with(path));
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.check(true)?;
+        self.tree.create_dir_all(path)
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        self.check(false)?;
+        self.tree.list(dir)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.tree.exists(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.tree.is_dir(path)
+    }
+
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        self.check(true)?;
+        let mut renames = self.renames.lock().map_err(|_| poisoned())?;
+        renames.retain(|rename| parent_dir(&rename.to) != dir);
+        Ok(())
+    }
+
+    fn link(&self, src: &Path, dest: &Path) -> io::Result<()> {
+        self.check(true)?;
+        self.tree.link(src, dest)?;
+        let mut files = self.files()?;
+        if let Some(file) = files.get(src).cloned() {
+            files.insert(dest.to_path_buf(), file);
+        }
+        Ok(())
+    }
+}
+
+/// Moves the file at `from`, or the files under the `from` directory, to `to`.
+fn move_files(
+    files: &mut BTreeMap<PathBuf, Arc<FaultFile>>,
+    from: &Path,
+    to: &Path,
+) {
+    let moved: Vec<PathBuf> = files
+        .keys()
+        .filter(|p| p.starts_with(from))
+        .cloned()
+        .collect();
+    for path in moved {
+        let dest = match path.strip_prefix(from) {
+            Ok(rest) if !rest.as_os_str().is_empty() => to.join(rest),
+            _ => to.to_path_buf(),
+        };
+        if let Some(file) = files.remove(&path) {
+            files.insert(dest, file);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::DatabaseOptions, error::GhalaDbResult, GhalaDb, WriteBatch,
+    };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    type Db = GhalaDb<u32, Bytes>;
+    /// A key of the model: the index of its keyspace and the key itself.
+    type Key = (usize, u32);
+
+    /// Keyspaces written to, which are created on first use.
+    const KEYSPACES: [&str; 3] = ["default", "ks1", "ks2"];
+
+    fn open(fs: &FaultInjectionFs, opts: DatabaseOptions) -> GhalaDbResult<Db> {
+        GhalaDb::with_fs("db", Some(opts), Arc::new(fs.clone()))
+    }
+
+    /// Runs random writes until the file system fails or the workload
+    /// stops, which is followed by a crash. Only injected faults may fail
+    /// writes.
+    ///
+    /// Writes are acknowledged by a successful sync. `acked` holds the
+    /// acknowledged state and `pending` the values written to each key since,
+    /// `None` being a deletion. The writes of a batch are checked one by one,
+    /// its atomicity is checked by `atomic_batches`.
+    fn workload(
+        fs: &FaultInjectionFs,
+        db: &mut Db,
+        rng: &mut StdRng,
+        acked: &mut BTreeMap<Key, Bytes>,
+        pending: &mut BTreeMap<Key, Vec<Option<Bytes>>>,
+    ) {
+        let mut state = acked.clone();
+        for _ in 0..rng.gen_range(0..300) {
+            let res = match rng.gen_range(0..100) {
+                0..=49 => {
+                    let ((ks, key), val) = random_write(rng, &mut state, pending);
+                    db.keyspace::<u32, Bytes>(KEYSPACES[ks]).and_then(|mut ks| {
+                        match val {
+                            Some(val) => ks.put(&key, &val),
+                            None => ks.delete(&key),
+                        }
+                    })
+                }
+                50..=69 => {
+                    let mut batch = WriteBatch::new();
+                    for _ in 0..rng.gen_range(1..8) {
+                        let ((ks, key), val) =
+                            random_write(rng, &mut state, pending);
+                        match val {
+                            Some(val) => batch.put(KEYSPACES[ks], &key, &val),
+                            None => batch.delete(KEYSPACES[ks], &key),
+                        }
+                        .unwrap();
+                    }
+                    db.write(batch)
+                }
+                70..=97 => db.sync().map(|_| {
+                    *acked = state.clone();
+                    pending.clear();
+                }),
+                _ => db.compact(),
+            };
+            if let Err(e) = res {
+                assert!(fs.faults().unwrap().failing, "unexpected error: {e:?}");
+                return;
+            }
+        }
+    }
+
+    /// Picks a random put or deletion, and records it in `state` and
+    /// `pending`.
+    fn random_write(
+        rng: &mut StdRng,
+        state: &mut BTreeMap<Key, Bytes>,
+        pending: &mut BTreeMap<Key, Vec<Option<Bytes>>>,
+    ) -> (Key, Option<Bytes>) {
+        let key = (rng.gen_range(0..KEYSPACES.len()), rng.gen_range(0..64));
+        let val: Option<Bytes> = (rng.gen_range(0..10) < 7)
+            .then(|| (0..rng.gen_range(0..200)).map(|_| rng.gen()).collect());
+        pending.entry(key).or_default().push(val.clone());
+        match val {
+            Some(ref val) => state.insert(key, val.clone()),
+            None => state.remove(&key),
+        };
+        (key, val)
+    }
+
+    /// Checks that every key holds its acknowledged value or one written
+    /// since, and makes the recovered state the acknowledged one.
+    fn check(
+        db: &mut Db,
+        acked: &mut BTreeMap<Key, Bytes>,
+        pending: &mut BTreeMap<Key, Vec<Option<Bytes>>>,
+        seed: u64,
+    ) -> GhalaDbResult<()> {
+        let mut recovered = BTreeMap::new();
+        for (ks, name) in KEYSPACES.iter().enumerate() {
+            let mut keyspace = db.keyspace::<u32, Bytes>(name)?;
+            for kv in keyspace.iter()? {
+                let key = (ks, kv?.0);
+                assert!(
+                    acked.contains_key(&key) || pending.contains_key(&key),
+                    "seed: {seed} unexpected key: {key:?}"
+                );
+            }
+        }
+        for key in acked
+            .keys()
+            .chain(pending.keys())
+            .copied()
+            .collect::<BTreeSet<_>>()
+        {
+            let val = db.keyspace::<u32, Bytes>(KEYSPACES[key.0])?.get(&key.1)?;
+            let allowed = val == acked.get(&key).cloned()
+                || pending.get(&key).is_some_and(|vals| vals.contains(&val));
+            assert!(allowed, "seed: {seed} key: {key:?} lost its value");
+            if let Some(val) = val {
+                recovered.insert(key, val);
+            }
+        }
+        *acked = recovered;
+        pending.clear();
+        Ok(())
+    }
+
+    #[test]
+    fn lost_renames() -> io::Result<()> {
+        let fs = FaultInjectionFs::new(0);
+        let (dir, a, b) = (Path::new("db"), Path::new("db/a"), Path::new("db/b"));
+        fs.create_dir_all(dir)?;
+        for (path, data) in [(a, b"a"), (b, b"b")] {
+            let file = fs.open(path, OpenMode::Create)?;
+            file.append(data)?;
+            file.sync()?;
+        }
+        fs.rename(b, a)?;
+        fs.crash();
+        fs.recover();
+        assert_eq!(fs.read(a)?, b"a");
+        assert_eq!(fs.read(b)?, b"b");
+
+        fs.rename(b, a)?;
+        fs.sync_dir(dir)?;
+        fs.rename(dir, Path::new("moved"))?;
+        fs.crash();
+        fs.recover();
+        assert_eq!(fs.read(a)?, b"b");
+        assert!(!fs.exists(b) && !fs.exists(Path::new("moved")));
+        Ok(())
+    }
+
+    /// Crashes a data store at every point of a batch spanning keyspaces,
+    /// and checks that the batch is found applied to all of them or to none.
+    #[test]
+    fn atomic_batches() -> GhalaDbResult<()> {
+        let opts = DatabaseOptions::builder().keys_sync_interval(1000).build();
+        let (old, new) = (vec![1u8; 64], vec![2u8; 64]);
+        for writes in 0.. {
+            let fs = FaultInjectionFs::new(writes);
+            fs.set_torn_writes(true);
+            let mut db = open(&fs, opts)?;
+            for name in KEYSPACES {
+                db.keyspace::<u32, Bytes>(name)?.put(&1, &old)?;
+            }
+            db.sync()?;
+            fs.fail_after(writes);
+            let mut batch = WriteBatch::new();
+            for name in KEYSPACES {
+                batch.put(name, &1u32, &new)?;
+            }
+            let res = db.write(batch).and_then(|_| db.sync());
+            fs.crash();
+            drop(db);
+            fs.recover();
+
+            let mut db = open(&fs, opts)?;
+            let vals = KEYSPACES
+                .iter()
+                .map(|name| db.keyspace::<u32, Bytes>(name)?.get(&1))
+                .collect::<GhalaDbResult<Vec<_>>>()?;
+            let expected = match res {
+                Ok(()) => &new,
+                Err(_) => vals[0].as_ref().unwrap(),
+            };
+            assert!(
+                vals.iter().all(|val| val.as_ref() == Some(expected)),
+                "writes: {writes} batch partially applied: {vals:?}"
+            );
+            if res.is_ok() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Crashes data stores at random points and checks that no acknowledged
+    /// write is lost.
+    #[test]
+    fn crash_consistency() -> GhalaDbResult<()> {
+        for seed in 0..48u64 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let fs = FaultInjectionFs::new(seed);
+            fs.set_torn_writes(seed % 2 == 0);
+            let opts = DatabaseOptions::builder()
+                .max_vlog_size(rng.gen_range(512..4096))
+                .vlog_mem_buf_size(rng.gen_range(256..2048))
+                .keys_memtable_size(rng.gen_range(512..4096))
+                .keys_sync_interval(rng.gen_range(0..2) * 1000)
+                .keys_max_tables(2)
+                .inline_threshold(rng.gen_range(0..2) * 16)
+                .mmap_reads(rng.gen())
+                .build();
+            let (mut acked, mut pending) = (BTreeMap::new(), BTreeMap::new());
+            for _ in 0..4 {
+                let mut db = open(&fs, opts)?;
+                check(&mut db, &mut acked, &mut pending, seed)?;
+                if rng.gen() {
+                    fs.fail_after(rng.gen_range(0..400));
+                }
+                workload(&fs, &mut db, &mut rng, &mut acked, &mut pending);
+                fs.crash();
+                drop(db);
+                fs.recover();
+            }
+            let mut db = open(&fs, opts)?;
+            check(&mut db, &mut acked, &mut pending, seed)?;
+        }
+        Ok(())
+    }
+}
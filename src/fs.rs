@@ -0,0 +1,578 @@
+//! GhalaDb's file system module.
+//!
+//! A data store accesses its files through a [FileSystem], which keeps them on
+//! disk with [StdFs], the default, or in memory with [InMemoryFs]. Files are
+//! append-only: they are written by appending to them, and read at given
+//! offsets, so a file system only has to support a handful of operations.
+//! Files are only truncated to drop a torn write left by a crash.
+use crate::core::Bytes;
+use memmap2::Mmap;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, RwLock},
+};
+
+/// A shared file system.
+pub(crate) type Fs = Arc<dyn FileSystem>;
+
+/// How [FileSystem::open] opens a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    /// Opens an existing file for reading.
+    Read,
+    /// Opens a file for reading and appending, creating it if needed.
+    Append,
+    /// Creates an empty file for reading and appending, truncating the file
+    /// if it exists.
+    Create,
+}
+
+/// A file opened by a [FileSystem].
+pub trait FsFile: Send + Sync {
+    /// Reads bytes at `offset` into `buf`, returning the number of bytes
+    /// read, which is zero past the end of the file.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+
+    /// Appends bytes to the end of the file.
+    fn append(&self, buf: &[u8]) -> io::Result<()>;
+
+    /// Flushes the appended bytes to durable storage.
+    fn sync(&self) -> io::Result<()>;
+
+    /// Size of the file in bytes.
+    fn size(&self) -> io::Result<u64>;
+
+    /// Shrinks the file to `len` bytes. The new size is made durable by
+    /// [FsFile::sync].
+    fn truncate(&self, len: u64) -> io::Result<()>;
+
+    /// Reads exactly `buf.len()` bytes at `offset`.
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.read_at(buf, offset) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The files and directories a data store is kept in.
+///
+/// Paths are those of the data store's directory and of the files within it.
+pub trait FileSystem: Send + Sync {
+    /// Opens a file.
+    fn open(&self, path: &Path, mode: OpenMode) -> io::Result<Arc<dyn FsFile>>;
+
+    /// Renames a file or a directory, replacing the destination file if it
+    /// exists.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Removes a file.
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    /// Removes a directory and its content.
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Creates a directory and its missing parents.
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Lists the paths of a directory's files and subdirectories.
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Check if a file or a directory exists.
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Check if a directory exists.
+    fn is_dir(&self, path: &Path) -> bool;
+
+    /// Makes the creation, renaming and removal of a directory's entries
+    /// durable.
+    fn sync_dir(&self, dir: &Path) -> io::Result<()>;
+
+    /// Reads a whole file.
+    fn read(&self, path: &Path) -> io::Result<Bytes> {
+        let file = self.open(path, OpenMode::Read)?;
+        let mut buf = vec![0; file.size()? as usize];
+        file.read_exact_at(&mut buf, 0)?;
+        Ok(buf)
+    }
+
+    /// Writes a whole file, replacing it if it exists.
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        self.open(path, OpenMode::Create)?.append(data)
+    }
+
+    /// Size of a file in bytes.
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        self.open(path, OpenMode::Read)?.size()
+    }
+
+    /// Returns a read-only view of a file that is no longer modified.
+    fn map(&self, path: &Path) -> io::Result<FileMap> {
+        Ok(FileMap::new(self.read(path)?))
+    }
+
+    /// Makes `dest` a copy of the `src` file, sharing its storage if
+    /// possible.
+    fn link(&self, src: &Path, dest: &Path) -> io::Result<()> {
+        let file = self.open(dest, OpenMode::Create)?;
+        file.append(&self.read(src)?)?;
+        file.sync()
+    }
+}
+
+/// A read-only view of a file's bytes, returned by [FileSystem::map].
+#[derive(Clone)]
+pub struct FileMap(Arc<dyn AsRef<[u8]> + Send + Sync>);
+
+impl FileMap {
+    /// Creates a view of `bytes`.
+    pub fn new<B: AsRef<[u8]> + Send + Sync + 'static>(bytes: B) -> FileMap {
+        FileMap(Arc::new(bytes))
+    }
+}
+
+impl Deref for FileMap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.0.as_ref().as_ref()
+    }
+}
+
+impl fmt::Debug for FileMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileMap").field("len", &self.len()).finish()
+    }
+}
+
+/// A reader over a file, starting at an offset.
+pub(crate) struct FileReader {
+    file: Arc<dyn FsFile>,
+    pos: u64,
+}
+
+impl FileReader {
+    pub fn new(file: Arc<dyn FsFile>, pos: u64) -> FileReader {
+        FileReader { file, pos }
+    }
+}
+
+impl Read for FileReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.file.read_at(buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+/// A writer appending to a file.
+pub(crate) struct FileWriter(pub Arc<dyn FsFile>);
+
+impl Write for FileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.append(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The operating system's file system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StdFs;
+
+struct StdFile(File);
+
+impl FsFile for StdFile {
+    #[cfg(unix)]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        std::os::unix::fs::FileExt::read_at(&self.0, buf, offset)
+    }
+
+    #[cfg(windows)]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        std::os::windows::fs::FileExt::seek_read(&self.0, buf, offset)
+    }
+
+    fn append(&self, buf: &[u8]) -> io::Result<()> {
+        (&self.0).write_all(buf)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.0.sync_all()
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.0.metadata()?.len())
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        self.0.set_len(len)
+    }
+}
+
+impl FileSystem for StdFs {
+    fn open(&self, path: &Path, mode: OpenMode) -> io::Result<Arc<dyn FsFile>> {
+        let file = match mode {
+            OpenMode::Read => File::open(path)?,
+            OpenMode::Append | OpenMode::Create => {
+                if mode == OpenMode::Create {
+                    File::create(path)?;
+                }
+                OpenOptions::new()
+                    .read(true)
+                    .append(true)
+                    .create(true)
+                    .open(path)?
+            }
+        };
+        Ok(Arc::new(StdFile(file)))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        std::fs::rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        std::fs::remove_file(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        std::fs::remove_dir_all(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        std::fs::create_dir_all(path)
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        std::fs::read_dir(dir)?
+            .map(|entry| Ok(entry?.path()))
+            .collect()
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    #[cfg(unix)]
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        File::open(dir)?.sync_all()
+    }
+
+    /// Directories cannot be opened outside of unix, their entries are made
+    /// durable by the file system.
+    #[cfg(not(unix))]
+    fn sync_dir(&self, _dir: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Bytes> {
+        std::fs::read(path)
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        std::fs::write(path, data)
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        Ok(std::fs::metadata(path)?.len())
+    }
+
+    fn map(&self, path: &Path) -> io::Result<FileMap> {
+        let file = File::open(path)?;
+        if file.metadata()?.len() == 0 {
+            return Ok(FileMap::new([]));
+        }
+        // SAFETY: only files that are append-only or no longer modified are
+        // mapped, and they are never truncated.
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(FileMap::new(map))
+    }
+
+    fn link(&self, src: &Path, dest: &Path) -> io::Result<()> {
+        // hard links fail across file systems
+        if std::fs::hard_link(src, dest).is_err() {
+            std::fs::copy(src, dest)?;
+        }
+        Ok(())
+    }
+}
+
+/// A file system keeping files in memory.
+///
+/// Clones share their files, so a data store can be reopened by passing a
+/// clone to [GhalaDb::with_fs](crate::GhalaDb::with_fs). Syncing files and
+/// directories is a no-op.
+#[derive(Debug, Default, Clone)]
+pub struct InMemoryFs {
+    tree: Arc<Mutex<MemTree>>,
+}
+
+#[derive(Debug, Default)]
+struct MemTree {
+    files: BTreeMap<PathBuf, Arc<MemFile>>,
+    dirs: BTreeSet<PathBuf>,
+}
+
+#[derive(Debug, Default)]
+struct MemFile(RwLock<Bytes>);
+
+impl FsFile for MemFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let data = self.0.read().map_err(|_| poisoned())?;
+        let start = (offset as usize).min(data.len());
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        Ok(n)
+    }
+
+    fn append(&self, buf: &[u8]) -> io::Result<()> {
+        self.0
+            .write()
+            .map_err(|_| poisoned())?
+            .extend_from_slice(buf);
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.0.read().map_err(|_| poisoned())?.len() as u64)
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        self.0
+            .write()
+            .map_err(|_| poisoned())?
+            .truncate(len as usize);
+        Ok(())
+    }
+}
+
+fn poisoned() -> io::Error {
+    io::Error::other("in-memory file system lock poisoned")
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("not found: {}", path.display()),
+    )
+}
+
+impl InMemoryFs {
+    /// Creates an empty file system.
+    pub fn new() -> InMemoryFs {
+        InMemoryFs::default()
+    }
+
+    fn tree(&self) -> io::Result<MutexGuard<'_, MemTree>> {
+        self.tree.lock().map_err(|_| poisoned())
+    }
+}
+
+impl MemTree {
+    fn has_parent(&self, path: &Path) -> bool {
+        path.parent()
+            .is_none_or(|p| p.as_os_str().is_empty() || self.dirs.contains(p))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files.contains_key(path) || self.dirs.contains(path)
+    }
+}
+
+fn already_exists(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("already exists: {}", path.display()),
+    )
+}
+
+impl FileSystem for InMemoryFs {
+    fn open(&self, path: &Path, mode: OpenMode) -> io::Result<Arc<dyn FsFile>> {
+        let mut tree = self.tree()?;
+        if tree.dirs.contains(path) || !tree.has_parent(path) {
+            return Err(not_found(path));
+        }
+        let file = match (mode, tree.files.get(path)) {
+            (OpenMode::Read | OpenMode::Append, Some(file)) => file.clone(),
+            (OpenMode::Read, None) => return Err(not_found(path)),
+            (OpenMode::Append, None) | (OpenMode::Create, _) => {
+                let file = Arc::new(MemFile::default());
+                tree.files.insert(path.to_path_buf(), file.clone());
+                file
+            }
+        };
+        Ok(file)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut tree = self.tree()?;
+        if !tree.has_parent(to) {
+            return Err(not_found(to));
+        }
+        if tree.dirs.contains(to) {
+            return Err(already_exists(to));
+        }
+        if let Some(file) = tree.files.remove(from) {
+            tree.files.insert(to.to_path_buf(), file);
+            return Ok(());
+        }
+        if !tree.dirs.contains(from) {
+            return Err(not_found(from));
+        }
+        if tree.files.contains_key(to) || to.starts_with(from) {
+            return Err(already_exists(to));
+        }
+        let moved = |p: &Path| p.strip_prefix(from).ok().map(|rest| to.join(rest));
+        let dirs: Vec<PathBuf> = tree.dirs.iter().filter_map(|p| moved(p)).collect();
+        tree.dirs.retain(|p| !p.starts_with(from));
+        tree.dirs.extend(dirs);
+        let files: Vec<PathBuf> = tree
+            .files
+            .keys()
+            .filter(|p| p.starts_with(from))
+            .cloned()
+            .collect();
+        for path in files {
+            if let (Some(file), Some(dest)) =
+                (tree.files.remove(&path), moved(&path))
+            {
+                tree.files.insert(dest, file);
+            }
+        }
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        let mut tree = self.tree()?;
+        tree.files
+            .remove(path)
+            .map(|_| ())
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut tree = self.tree()?;
+        if !tree.dirs.contains(path) {
+            return Err(not_found(path));
+        }
+        tree.dirs.retain(|p| !p.starts_with(path));
+        tree.files.retain(|p, _| !p.starts_with(path));
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut tree = self.tree()?;
+        if tree.files.contains_key(path) {
+            return Err(already_exists(path));
+        }
+        for dir in path.ancestors().filter(|p| !p.as_os_str().is_empty()) {
+            tree.dirs.insert(dir.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let tree = self.tree()?;
+        if !tree.dirs.contains(dir) {
+            return Err(not_found(dir));
+        }
+        let in_dir = |p: &&PathBuf| p.parent() == Some(dir);
+        let dirs = tree.dirs.iter().filter(in_dir);
+        let files = tree.files.keys().filter(in_dir);
+        Ok(dirs.chain(files).cloned().collect())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.tree().is_ok_and(|tree| tree.exists(path))
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.tree().is_ok_and(|tree| tree.dirs.contains(path))
+    }
+
+    fn sync_dir(&self, _dir: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn link(&self, src: &Path, dest: &Path) -> io::Result<()> {
+        let mut tree = self.tree()?;
+        if !tree.has_parent(dest) || tree.dirs.contains(dest) {
+            return Err(not_found(dest));
+        }
+        let file = tree.files.get(src).cloned().ok_or_else(|| not_found(src))?;
+        tree.files.insert(dest.to_path_buf(), file);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn in_memory_fs() -> io::Result<()> {
+        let fs = InMemoryFs::new();
+        let (dir, path) = (Path::new("db"), Path::new("db/0.vlog"));
+        assert!(fs.open(path, OpenMode::Append).is_err());
+        fs.create_dir_all(&dir.join("keys"))?;
+        assert!(fs.is_dir(dir));
+
+        let file = fs.open(path, OpenMode::Append)?;
+        file.append(b"hello")?;
+        file.append(b" world")?;
+        assert_eq!(file.size()?, 11);
+        let mut buf = [0; 5];
+        file.read_exact_at(&mut buf, 6)?;
+        assert_eq!(&buf, b"world");
+        assert!(file.read_exact_at(&mut buf, 8).is_err());
+        assert_eq!(fs.read(path)?, b"hello world");
+        assert_eq!(&*fs.map(path)?, b"hello world");
+
+        fs.write(&dir.join("keys/manifest"), b"tables")?;
+        fs.link(path, &dir.join("1.vlog"))?;
+        let mut listed = fs.list(dir)?;
+        listed.sort();
+        let expected = ["db/0.vlog", "db/1.vlog", "db/keys"].map(PathBuf::from);
+        assert_eq!(listed, expected);
+
+        fs.rename(&dir.join("keys"), Path::new("keys"))?;
+        assert_eq!(fs.read(Path::new("keys/manifest"))?, b"tables");
+        assert!(!fs.exists(&dir.join("keys")));
+        fs.rename(path, &dir.join("2.vlog"))?;
+        assert!(fs.open(path, OpenMode::Read).is_err());
+        fs.remove(&dir.join("2.vlog"))?;
+        // open files outlive their removal
+        assert_eq!(file.size()?, 11);
+
+        let clone = fs.clone();
+        fs.remove_dir_all(dir)?;
+        assert!(!clone.exists(dir));
+        assert!(!clone.exists(&dir.join("1.vlog")));
+        Ok(())
+    }
+}
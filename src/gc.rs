@@ -1,11 +1,10 @@
-use std::path::Path;
-
 use crate::{
-    core::VlogNum,
-    error::GhalaDbResult,
-    keys::Keys,
+    core::{DataPtr, KeyEntry, VlogNum},
+    error::{GhalaDbError, GhalaDbResult},
+    keyspace::Keyspaces,
     vlog::{DataEntry, VlogReader},
 };
+use std::io;
 
 /// A Lightweight Garbage Collector
 ///
@@ -16,9 +15,12 @@ use crate::{
 /// If the data pointers do not much, it means that the data associated to
 /// the key got updated and we can safely delete the stale entry in the values
 /// logs. If the pointers match then the data is still live and valid.
+/// Merge operands are live as long as the key still refers to them.
+/// Entries of expired keys are treated as stale.
 ///
-/// The GC returns a live data entry when found. The database will re-insert it
-/// and trigger more sweeping later.
+/// The GC returns a live data entry, along with its data pointer and key
+/// entry, when found. The database will re-insert it and trigger more
+/// sweeping later.
 ///
 /// Once the GC goes through an entire values log, the database will drop it.
 ///
@@ -38,24 +40,38 @@ pub(crate) struct GarbageCollector {
 }
 
 impl GarbageCollector {
-    pub fn new(vnum: VlogNum, path: &Path) -> GhalaDbResult<Self> {
-        debug!("GarbageCollector::new vlog: {vnum} at: {path:?}");
-        let vlog_iter = VlogReader::from_path(path)?;
-        Ok(Self { vnum, vlog_iter })
+    pub fn new(vnum: VlogNum, vlog_iter: VlogReader) -> Self {
+        Self { vnum, vlog_iter }
     }
 
-    pub fn sweep(&mut self, keys: &mut Keys) -> GhalaDbResult<Option<DataEntry>> {
+    pub fn sweep(
+        &mut self,
+        keyspaces: &mut Keyspaces,
+    ) -> GhalaDbResult<Option<(DataPtr, DataEntry, KeyEntry)>> {
         trace!("GarbageCollector::sweep");
         loop {
-            match self.vlog_iter.next_entry()? {
+            let next = match self.vlog_iter.next_entry() {
+                // a crash may leave a vlog ending with a torn entry
+                Err(GhalaDbError::IOError(e))
+                    if e.kind() == io::ErrorKind::UnexpectedEof =>
+                {
+                    warn!("gc::sweep torn entry at the end of vlog: {}", self.vnum);
+                    None
+                }
+                res => res?,
+            };
+            match next {
                 None => return Ok(None),
                 Some((dp, de)) => {
-                    match keys.get(&de.key) {
+                    let Ok(keys) = keyspaces.keys(de.keyspace) else {
+                        continue;
+                    };
+                    match keys.get(&de.key)? {
                         None => continue,
-                        Some(cur_dp) => {
-                            if cur_dp == dp {
+                        Some(entry) => {
+                            if entry.refers_to(&dp) {
                                 // data is live and should move to tail
-                                return Ok(Some(de));
+                                return Ok(Some((dp, de, entry)));
                             } else {
                                 continue;
                             }
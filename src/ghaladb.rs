@@ -1,16 +1,58 @@
 use bincode::{Decode, Encode};
 
 use crate::{
+    bulk::BulkLoader,
+    cache::CacheStats,
     config::DatabaseOptions,
-    core::{Bytes, DataPtr},
+    core::{
+        now_millis, Bytes, ChunkManifest, DataPtr, FileKind, KeyEntry, LiveFile,
+        ValueRef,
+    },
+    crypto::Cipher,
     dec::Dec,
     error::{GhalaDbError, GhalaDbResult},
+    fs::{FileSystem, Fs, InMemoryFs, StdFs},
     gc::GarbageCollector,
     keys::Keys,
-    utils::t,
-    vlog::{DataEntry, VlogsMan},
+    keyspace::{
+        expiry, BatchUpdates, Keyspace, KeyspaceId, KeyspaceOptions, Keyspaces,
+        RawIter, RawStore, WriteBatch, DEFAULT_KEYSPACE_ID,
+    },
+    merge::{self, MergeOperator},
+    prefetch::{Prefetched, ReadPool},
+    sstable::MAX_KEY_SZ,
+    stream::ValueReader,
+    utils::{copy_range, init_empty_dir, t},
+    verify::{self, VerifyReport},
+    vlog::{DataEntry, EntryKind, VlogsMan},
 };
-use std::{borrow::Borrow, marker::PhantomData, path::Path};
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, HashMap, VecDeque},
+    io::Read,
+    marker::PhantomData,
+    ops::RangeBounds,
+    path::Path,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Minimum number of sampled values needed to train a zstd dictionary.
+const MIN_DICT_SAMPLES: usize = 8;
+
+/// The result of a conditional write such as [GhalaDb::compare_and_swap].
+pub type CompareAndSwapResult<V> = Result<(), CompareAndSwapError<V>>;
+
+/// A conditional write was not applied because the current value did not
+/// match the expected one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompareAndSwapError<V> {
+    /// The current value of the key.
+    pub current: Option<V>,
+}
 
 /// An LSM key value store with keys and values separation.
 pub struct GhalaDb<K, V>
@@ -18,14 +60,20 @@ where
     K: Encode + Decode,
     V: Encode + Decode,
 {
-    /// Keys Table
-    keys: Keys,
+    /// Keys Tables
+    keyspaces: Keyspaces,
     /// Values logs manager
     vlogs_man: VlogsMan,
     /// Garbage Collector
     gc: Option<GarbageCollector>,
     /// Database Configs
     opts: DatabaseOptions,
+    /// Set once a zstd dictionary has been trained (or training attempted).
+    dict_trained: bool,
+    /// Merge operator used to combine merge operands.
+    merge_op: Option<Box<dyn MergeOperator<V>>>,
+    /// Threads prefetching values for iterators, started on first use.
+    read_pool: Option<ReadPool>,
     _k: PhantomData<K>,
     _v: PhantomData<V>,
 }
@@ -41,17 +89,65 @@ where
         options: Option<DatabaseOptions>,
     ) -> GhalaDbResult<GhalaDb<K, V>> {
         trace!("GhalaDb::new path: {}", path.as_ref().display());
+        Self::open(path.as_ref(), options, None, None)
+    }
+
+    /// Creates a new data store or loads an existing one, keeping its files
+    /// in the given [FileSystem].
+    ///
+    /// A data store kept in an [InMemoryFs] can be reopened by passing a
+    /// clone of it.
+    pub fn with_fs<P: AsRef<Path>>(
+        path: P,
+        options: Option<DatabaseOptions>,
+        fs: Arc<dyn FileSystem>,
+    ) -> GhalaDbResult<GhalaDb<K, V>> {
+        trace!("GhalaDb::with_fs path: {}", path.as_ref().display());
+        Self::open(path.as_ref(), options, None, Some(fs))
+    }
+
+    /// Creates a new encrypted data store or loads an existing one.
+    ///
+    /// Vlog records are encrypted after compression, and the store's metadata
+    /// files are encrypted as well, using keys from the given
+    /// [KeyProvider](crate::KeyProvider).
+    ///
+    /// Data encrypted with an older key is re-encrypted with the provider's
+    /// current key as the garbage collector rewrites the vlogs holding it.
+    #[cfg(feature = "encryption")]
+    pub fn with_encryption<P: AsRef<Path>>(
+        path: P,
+        options: Option<DatabaseOptions>,
+        provider: std::sync::Arc<dyn crate::KeyProvider>,
+    ) -> GhalaDbResult<GhalaDb<K, V>> {
+        trace!("GhalaDb::with_encryption path: {}", path.as_ref().display());
+        Self::open(path.as_ref(), options, Some(Cipher::new(provider)), None)
+    }
+
+    fn open(
+        path: &Path,
+        options: Option<DatabaseOptions>,
+        cipher: Option<Cipher>,
+        fs: Option<Fs>,
+    ) -> GhalaDbResult<GhalaDb<K, V>> {
         let opts = options.unwrap_or_else(|| DatabaseOptions::builder().build());
-        Self::init_dir(path.as_ref())?;
-        let keys_path = path.as_ref().join("keys");
+        let fs = fs.unwrap_or_else(|| match opts.in_memory {
+            true => Arc::new(InMemoryFs::new()),
+            false => Arc::new(StdFs),
+        });
+        Self::init_dir(&*fs, path)?;
 
-        let vlogs_man = VlogsMan::new(path.as_ref(), opts)?;
-        let keys = Keys::from_path(keys_path, opts)?;
+        let vlogs_man = VlogsMan::new(fs.clone(), path, opts, cipher.clone())?;
+        let keyspaces = Keyspaces::new(fs, path, opts, cipher)?;
+        let dict_trained = vlogs_man.has_dict();
         let db = GhalaDb {
-            keys,
+            keyspaces,
             vlogs_man,
             gc: None,
             opts,
+            dict_trained,
+            merge_op: None,
+            read_pool: None,
             _k: PhantomData,
             _v: PhantomData,
         };
@@ -61,6 +157,7 @@ where
     /// Check if a key is present in the data store.
     ///
     /// Returns `true` if the store contains a value for the specified key.
+    #[allow(clippy::multiple_bound_locations)]
     pub fn exists<Q: ?Sized>(&mut self, k: &Q) -> GhalaDbResult<bool>
     where
         K: Borrow<Q>,
@@ -68,12 +165,13 @@ where
     {
         trace!("GhalaDb::contains_key");
         let key = Dec::ser_raw(k)?;
-        Ok(self.keys.exists(&key))
+        self.exists_raw(DEFAULT_KEYSPACE_ID, &key)
     }
 
     /// Deletes a key from the data store.
     ///
     /// We simply remove the key from the in-memory keys table.
+    #[allow(clippy::multiple_bound_locations)]
     pub fn delete<Q: ?Sized>(&mut self, key: &Q) -> GhalaDbResult<()>
     where
         K: Borrow<Q>,
@@ -81,16 +179,15 @@ where
     {
         trace!("GhalaDb::delete");
         let key = Dec::ser_raw(key)?;
-        t!("keys::del", self.keys.delete(&key))?;
-        t!("gc", self.gc())?;
-        Ok(())
+        self.delete_raw(DEFAULT_KEYSPACE_ID, &key)
     }
 
     /// Returns the value corresponding to the key.
     ///
     /// We first do a data pointer lookup in the in-memory keys table
     /// and then use the pointer to read the actual data entry from a
-    /// vlog on disk.
+    /// vlog on disk. Inlined values are returned straight from the keys table.
+    #[allow(clippy::multiple_bound_locations)]
     pub fn get<Q: ?Sized>(&mut self, key: &Q) -> GhalaDbResult<Option<V>>
     where
         K: Borrow<Q>,
@@ -98,16 +195,138 @@ where
     {
         trace!("GhalaDb::get");
         let key = Dec::ser_raw(key)?;
-        if let Some(dp) = self.keys.get(&key) {
-            let bytes = t!("vlogman::get", self.vlogs_man.get(&dp))?.val;
-            let val: V = Dec::deser_raw(&bytes)?;
-            Ok(Some(val))
-        } else {
-            Ok(None)
+        match self.get_raw(DEFAULT_KEYSPACE_ID, &key)? {
+            Some(bytes) => Ok(Some(Dec::deser_raw(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Registers the merge operator used to combine merge operands.
+    ///
+    /// The operator is not persisted, it has to be registered every time the
+    /// data store is loaded.
+    pub fn set_merge_operator<M>(&mut self, merge_op: M)
+    where
+        M: MergeOperator<V> + 'static,
+    {
+        self.merge_op = Some(Box::new(merge_op));
+    }
+
+    /// Merges an operand into the value of a key.
+    ///
+    /// The operand is appended to the values log and combined with the key's
+    /// value using the registered [MergeOperator] when the key is read. The
+    /// garbage collector folds the operands into a single value when it
+    /// relocates them.
+    pub fn merge<Q>(&mut self, k: &Q, operand: &V) -> GhalaDbResult<()>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + bincode::Encode,
+    {
+        trace!("GhalaDb::merge");
+        if self.merge_op.is_none() {
+            return Err(GhalaDbError::MissingMergeOperator);
+        }
+        let key = Dec::ser_raw(k)?;
+        check_key(&key)?;
+        let de = DataEntry::operand(key.clone(), Dec::ser_raw(operand)?);
+        let dp = t!(
+            "vlogman::put",
+            self.vlogs_man.put(&de, self.compress(DEFAULT_KEYSPACE_ID))
+        )?;
+        let keys = self.keyspaces.default_keys();
+        let entry = match keys.get(&key)? {
+            Some(mut entry) if !entry.is_expired(now_millis()) => {
+                entry.operands.push(dp);
+                entry
+            }
+            // the operand of an absent or expired key starts a fresh entry
+            _ => KeyEntry {
+                val: None,
+                operands: vec![dp],
+                expires_at: None,
+            },
+        };
+        t!("keys::put", keys.put(key, entry))?;
+        self.maintain_keys(DEFAULT_KEYSPACE_ID)?;
+        self.auto_train_dict();
+        t!("gc", self.gc())?;
+        Ok(())
+    }
+
+    /// Atomically swaps the value of a key if its current value matches the
+    /// expected one.
+    ///
+    /// An `expected` value of `None` means that the key is expected to be
+    /// absent, while a `new` value of `None` deletes the key. Values are
+    /// compared using their encoded bytes.
+    ///
+    /// On mismatch, nothing is written and the current value is returned in
+    /// the [CompareAndSwapError].
+    pub fn compare_and_swap<Q>(
+        &mut self,
+        k: &Q,
+        expected: Option<&V>,
+        new: Option<&V>,
+    ) -> GhalaDbResult<CompareAndSwapResult<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + bincode::Encode,
+    {
+        trace!("GhalaDb::compare_and_swap");
+        let ks = DEFAULT_KEYSPACE_ID;
+        let key = Dec::ser_raw(k)?;
+        let current = self.get_raw(ks, &key)?;
+        let expected = expected.map(Dec::ser_raw).transpose()?;
+        if current != expected {
+            let current = current.map(|b| Dec::deser_raw(&b)).transpose()?;
+            return Ok(Err(CompareAndSwapError { current }));
+        }
+        match new {
+            Some(v) => {
+                let expires_at = self.keyspaces.options(ks).default_expiry();
+                self.put_raw(ks, key, Dec::ser_raw(v)?, expires_at)?
+            }
+            None if current.is_some() => self.delete_raw(ks, &key)?,
+            None => {}
         }
+        Ok(Ok(()))
+    }
+
+    /// Inserts a key-value pair only if the key is absent.
+    ///
+    /// If the key is present, its current value is returned in the
+    /// [CompareAndSwapError].
+    pub fn put_if_absent<Q>(
+        &mut self,
+        k: &Q,
+        v: &V,
+    ) -> GhalaDbResult<CompareAndSwapResult<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + bincode::Encode,
+    {
+        self.compare_and_swap(k, None, Some(v))
+    }
+
+    /// Deletes a key only if its current value equals the expected one.
+    ///
+    /// On mismatch, the current value is returned in the
+    /// [CompareAndSwapError].
+    pub fn delete_if_equals<Q>(
+        &mut self,
+        k: &Q,
+        expected: &V,
+    ) -> GhalaDbResult<CompareAndSwapResult<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + bincode::Encode,
+    {
+        self.compare_and_swap(k, Some(expected), None)
     }
 
     /// Inserts a key-value pair into the data store.
+    #[allow(clippy::multiple_bound_locations)]
     pub fn put<Q: ?Sized>(&mut self, k: &Q, v: &V) -> GhalaDbResult<()>
     where
         K: Borrow<Q>,
@@ -115,78 +334,621 @@ where
     {
         let key = Dec::ser_raw(k)?;
         let val = Dec::ser_raw(v)?;
-        self.put_raw(key, val, false)
+        let expires_at =
+            self.keyspaces.options(DEFAULT_KEYSPACE_ID).default_expiry();
+        self.put_raw(DEFAULT_KEYSPACE_ID, key, val, expires_at)
     }
 
-    fn put_raw(
+    /// Inserts a key-value pair into the data store that expires after `ttl`.
+    ///
+    /// Once expired, the key is no longer visible to lookups and iteration,
+    /// and the garbage collector treats its data entry as stale.
+    pub fn put_with_ttl<Q>(
+        &mut self,
+        k: &Q,
+        v: &V,
+        ttl: Duration,
+    ) -> GhalaDbResult<()>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + bincode::Encode,
+    {
+        let key = Dec::ser_raw(k)?;
+        let val = Dec::ser_raw(v)?;
+        self.put_raw(DEFAULT_KEYSPACE_ID, key, val, Some(expiry(ttl)))
+    }
+
+    /// Inserts a value read from a stream.
+    ///
+    /// The value is split into chunks of
+    /// [chunk_size](DatabaseOptions::chunk_size) bytes, each written to the
+    /// values log as it is read, so values of any size can be stored without
+    /// being held in memory. The key's entry keeps a manifest of the chunks.
+    ///
+    /// The stream's bytes are stored as is, so the value is read back using
+    /// [GhalaDb::get_reader], while [GhalaDb::get] decodes the whole value.
+    pub fn put_stream<Q, R>(&mut self, k: &Q, mut reader: R) -> GhalaDbResult<()>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + bincode::Encode,
+        R: Read,
+    {
+        trace!("GhalaDb::put_stream");
+        let ks = DEFAULT_KEYSPACE_ID;
+        let key = Dec::ser_raw(k)?;
+        check_key(&key)?;
+        let chunk_size = self.opts.chunk_size.max(1);
+        let mut manifest = ChunkManifest {
+            chunk_size,
+            len: 0,
+            chunks: vec![],
+        };
+        loop {
+            let mut chunk = Vec::with_capacity(chunk_size as usize);
+            (&mut reader)
+                .take(chunk_size as u64)
+                .read_to_end(&mut chunk)?;
+            if chunk.is_empty() {
+                break;
+            }
+            manifest.len += chunk.len() as u64;
+            let de = DataEntry::chunk(key.clone(), chunk).in_keyspace(ks);
+            let dp = t!("vlogman::put", self.vlogs_man.put(&de, self.compress(ks)))?;
+            manifest.chunks.push(dp);
+        }
+        let entry = KeyEntry {
+            val: Some(ValueRef::Chunked(manifest)),
+            operands: vec![],
+            expires_at: self.keyspaces.options(ks).default_expiry(),
+        };
+        t!("keys::put", self.keyspaces.keys(ks)?.put(key, entry))?;
+        self.maintain_keys(ks)?;
+        t!("gc", self.gc())
+    }
+
+    /// Returns a reader over the value of a key.
+    ///
+    /// Values written using [GhalaDb::put_stream] are read a chunk at a time,
+    /// while other values are read in full and returned in their encoded form.
+    pub fn get_reader<Q>(&mut self, k: &Q) -> GhalaDbResult<Option<ValueReader<'_>>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + bincode::Encode,
+    {
+        trace!("GhalaDb::get_reader");
+        let ks = DEFAULT_KEYSPACE_ID;
+        let key = Dec::ser_raw(k)?;
+        let Some(entry) = self.keyspaces.keys(ks)?.get(&key)? else {
+            return Ok(None);
+        };
+        match entry.val {
+            Some(ValueRef::Chunked(manifest)) if entry.operands.is_empty() => {
+                Ok(Some(ValueReader::chunked(
+                    &mut self.vlogs_man,
+                    manifest.chunks,
+                    manifest.chunk_size,
+                    manifest.len,
+                )))
+            }
+            _ => {
+                let merge_op = keyspace_merge_op(&self.merge_op, ks);
+                let val = t!(
+                    "merge::resolve",
+                    merge::resolve(&mut self.vlogs_man, merge_op, &entry)
+                )?;
+                Ok(Some(ValueReader::in_memory(&mut self.vlogs_man, val)))
+            }
+        }
+    }
+
+    /// Returns a handle to the named keyspace, creating it if needed.
+    ///
+    /// Keyspaces share the data store's values logs, but have their own keys
+    /// table and key and value types. The default keyspace, used by the
+    /// [GhalaDb] methods, is named [DEFAULT_KEYSPACE](crate::DEFAULT_KEYSPACE).
+    pub fn keyspace<K2, V2>(
+        &mut self,
+        name: &str,
+    ) -> GhalaDbResult<Keyspace<'_, K2, V2>>
+    where
+        K2: Encode + Decode,
+        V2: Encode + Decode,
+    {
+        trace!("GhalaDb::keyspace name: {name}");
+        let id = self.keyspaces.get_or_create(name)?;
+        let opts = self.keyspaces.options(id);
+        Ok(Keyspace::new(self, id, opts))
+    }
+
+    /// Returns a handle to the named keyspace, creating it if needed, and sets
+    /// its options.
+    ///
+    /// Keyspace options are not persisted, they apply until the data store is
+    /// dropped.
+    pub fn keyspace_with_options<K2, V2>(
+        &mut self,
+        name: &str,
+        opts: KeyspaceOptions,
+    ) -> GhalaDbResult<Keyspace<'_, K2, V2>>
+    where
+        K2: Encode + Decode,
+        V2: Encode + Decode,
+    {
+        trace!("GhalaDb::keyspace_with_options name: {name}");
+        let id = self.keyspaces.get_or_create(name)?;
+        self.keyspaces.set_options(id, opts);
+        Ok(Keyspace::new(self, id, opts))
+    }
+
+    /// Returns a loader inserting key-value pairs in bulk, for fast initial
+    /// ingestion.
+    ///
+    /// The pairs become visible once the loader is committed, see
+    /// [BulkLoader].
+    pub fn bulk_loader(&mut self) -> BulkLoader<'_, K, V> {
+        trace!("GhalaDb::bulk_loader");
+        BulkLoader::new(self)
+    }
+
+    /// Atomically applies a batch of writes, which can span keyspaces.
+    ///
+    /// All data entries are written to the values log before any keys table
+    /// is updated, so the writes become visible all at once and none of them
+    /// are applied if writing a data entry fails.
+    ///
+    /// A batch spanning keyspaces is committed by a single durable record
+    /// listing its writes, and the keys tables it touches are synced right
+    /// away, so after a crash it is found either applied to all of them or
+    /// to none.
+    pub fn write(&mut self, batch: WriteBatch) -> GhalaDbResult<()> {
+        trace!("GhalaDb::write ops: {}", batch.len());
+        let mut updates: BatchUpdates = BTreeMap::new();
+        for op in batch.ops {
+            let ks = t!(
+                "keyspaces::get_or_create",
+                self.keyspaces.get_or_create(&op.keyspace)
+            )?;
+            let entry = match op.val {
+                Some(val) => {
+                    let expires_at = self.keyspaces.options(ks).default_expiry();
+                    Some(self.store_value(ks, op.key.clone(), val, expires_at)?)
+                }
+                None => None,
+            };
+            updates.entry(ks).or_default().push((op.key, entry));
+        }
+        let touched: Vec<KeyspaceId> = updates.keys().copied().collect();
+        if updates.len() > 1 {
+            t!("vlogs_man::sync", self.vlogs_man.sync())?;
+            t!(
+                "keyspaces::apply_batch",
+                self.keyspaces.apply_batch(updates)
+            )?;
+        } else {
+            for (ks, updates) in updates {
+                t!("keys::apply", self.keyspaces.keys(ks)?.apply(updates))?;
+            }
+        }
+        for ks in touched {
+            self.maintain_keys(ks)?;
+        }
+        self.auto_train_dict();
+        t!("gc", self.gc())
+    }
+
+    /// Stores a value and points its key to it.
+    fn write_entry(
         &mut self,
+        ks: KeyspaceId,
         key: Bytes,
         val: Bytes,
-        from_gc: bool,
+        expires_at: Option<u64>,
     ) -> GhalaDbResult<()> {
-        trace!("GhalaDb::put_raw key:{key:?}");
-        let de = DataEntry::new(key.clone(), val);
-        let dp = t!("vlogman::put", self.vlogs_man.put(&de))?;
-        t!("keys::put", self.keys.put(key, dp))?;
-        if !from_gc {
-            t!("gc", self.gc())?;
-        }
+        trace!("GhalaDb::write_entry key:{key:?}");
+        let entry = self.store_value(ks, key.clone(), val, expires_at)?;
+        t!("keys::put", self.keyspaces.keys(ks)?.put(key, entry))?;
+        self.maintain_keys(ks)
+    }
 
+    /// Persists a keys table's changes if due, once the data entries they
+    /// point to are durable.
+    fn maintain_keys(&mut self, ks: KeyspaceId) -> GhalaDbResult<()> {
+        if self.keyspaces.keys(ks)?.maintenance_due()? {
+            t!("vlogs_man::sync", self.vlogs_man.sync())?;
+            t!("keys::maintain", self.keyspaces.keys(ks)?.maintain())?;
+        }
         Ok(())
     }
 
+    /// Returns the key entry for a value.
+    ///
+    /// Values no larger than the inline threshold are kept in the key entry,
+    /// and are persisted along with it by the keys table. Larger values are
+    /// written to the tail vlog.
+    pub(crate) fn store_value(
+        &mut self,
+        ks: KeyspaceId,
+        key: Bytes,
+        val: Bytes,
+        expires_at: Option<u64>,
+    ) -> GhalaDbResult<KeyEntry> {
+        check_key(&key)?;
+        if self.opts.inline_threshold > 0 && val.len() <= self.opts.inline_threshold
+        {
+            return Ok(KeyEntry::inline(val, expires_at));
+        }
+        let de = DataEntry::new(key, val).in_keyspace(ks);
+        let dp = t!("vlogman::put", self.vlogs_man.put(&de, self.compress(ks)))?;
+        Ok(KeyEntry::new(dp, expires_at))
+    }
+
+    /// Check if data entries of the keyspace should be compressed.
+    fn compress(&self, ks: KeyspaceId) -> bool {
+        self.keyspaces.options(ks).compress
+    }
+
+    pub(crate) fn default_keys(&mut self) -> &mut Keys {
+        self.keyspaces.default_keys()
+    }
+
+    /// Trains a zstd dictionary from a sample of the stored values.
+    ///
+    /// New data entries are compressed using the trained dictionary, which is
+    /// stored alongside every vlog that uses it. Older vlogs are rewritten
+    /// with the new dictionary over time, as the garbage collector relocates
+    /// their live entries.
+    ///
+    /// Returns `false` if compression is disabled or the store does not hold
+    /// enough values to train a dictionary.
+    pub fn train_dict(&mut self) -> GhalaDbResult<bool> {
+        trace!("GhalaDb::train_dict");
+        if !self.opts.compress {
+            return Ok(false);
+        }
+        let samples = self.opts.dict_train_samples.max(1);
+        let keys = self.default_keys();
+        let step = (keys.len() / samples).max(1);
+        let dps = keys
+            .iter()?
+            .step_by(step)
+            .take(samples)
+            .filter_map(|kv| kv.map(|(_, e)| e.dp()).transpose())
+            .collect::<GhalaDbResult<Vec<DataPtr>>>()?;
+        if dps.len() < MIN_DICT_SAMPLES {
+            return Ok(false);
+        }
+        let mut samples = Vec::with_capacity(dps.len());
+        for dp in dps {
+            let de = t!("vlogman::get", self.vlogs_man.get(&dp))?;
+            samples.push(Dec::ser_raw(&de)?);
+        }
+        let dict = t!(
+            "dec::train_dict",
+            Dec::train_dict(&samples, self.opts.dict_size)
+        )?;
+        t!("vlogs_man::set_dict", self.vlogs_man.set_dict(dict))?;
+        self.dict_trained = true;
+        Ok(true)
+    }
+
+    fn auto_train_dict(&mut self) {
+        if self.opts.dict_compression
+            && !self.dict_trained
+            && self.default_keys().len() >= self.opts.dict_train_samples
+        {
+            // Only a single attempt is made, a failure leaves the store
+            // using per record snappy compression.
+            self.dict_trained = true;
+            t!("GhalaDb::train_dict", self.train_dict()).ok();
+        }
+    }
+
     /// An iterator visiting all key-value pairs in an ordered manner.
+    ///
+    /// If [prefetch_depth](DatabaseOptions::prefetch_depth) is set, the
+    /// values of the upcoming keys are read ahead concurrently by a pool of
+    /// threads.
     pub fn iter(
         &mut self,
     ) -> GhalaDbResult<impl Iterator<Item = GhalaDbResult<(K, V)>> + '_> {
         trace!("GhalaDb::iter");
-        let db_iter: GhalaDbIter<K, V> = GhalaDbIter {
-            iter: Box::new(self.keys.iter()),
+        let iter = self.iter_raw(DEFAULT_KEYSPACE_ID)?;
+        Ok(GhalaDbIter::new(iter))
+    }
+
+    /// An iterator visiting all keys in an ordered manner.
+    ///
+    /// Only the keys table is read, the values are left untouched on disk.
+    pub fn keys(
+        &self,
+    ) -> GhalaDbResult<impl Iterator<Item = GhalaDbResult<K>> + '_> {
+        trace!("GhalaDb::keys");
+        let iter = self.keyspaces.keys_ref(DEFAULT_KEYSPACE_ID)?.iter()?;
+        Ok(iter.map(|kv| Dec::deser_raw(&kv?.0)))
+    }
+
+    /// An iterator visiting the keys within a range, in the same order as
+    /// [GhalaDb::keys].
+    ///
+    /// Keys are ordered by their encoding rather than by `K`'s [Ord], so the
+    /// whole keys table is scanned, skipping the keys outside the range. As
+    /// with [GhalaDb::keys], the values are not read.
+    pub fn keys_range<'a, R>(
+        &'a self,
+        range: R,
+    ) -> GhalaDbResult<impl Iterator<Item = GhalaDbResult<K>> + 'a>
+    where
+        K: Ord,
+        R: RangeBounds<K> + 'a,
+    {
+        trace!("GhalaDb::keys_range");
+        Ok(self.keys()?.filter(move |k| match k {
+            Ok(k) => range.contains(k),
+            Err(_) => true,
+        }))
+    }
+
+    /// An iterator visiting all values, in the order of their keys.
+    pub fn values(
+        &mut self,
+    ) -> GhalaDbResult<impl Iterator<Item = GhalaDbResult<V>> + '_> {
+        trace!("GhalaDb::values");
+        let iter = self.iter_raw(DEFAULT_KEYSPACE_ID)?;
+        Ok(iter.map(|kv| Dec::deser_raw(&kv?.1)))
+    }
+
+    /// Returns the approximate number of keys in the data store.
+    ///
+    /// The count is kept up to date by the keys table, so this does not scan
+    /// the keys, but it drifts as keys flushed to the keys table's SSTables
+    /// are overwritten or deleted, until the SSTables are compacted. Expired
+    /// keys are counted until they are removed, either when looked up or when
+    /// the keys table is compacted.
+    pub fn len(&self) -> usize {
+        self.keyspaces
+            .keys_ref(DEFAULT_KEYSPACE_ID)
+            .map_or(0, |keys| keys.len())
+    }
+
+    /// Check if the data store holds no keys.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// An iterator visiting all key-value pairs in no particular order.
+    ///
+    /// Values are read in the order they are laid out in the values logs,
+    /// with neighbouring values fetched in a single read of up to
+    /// [scan_readahead](DatabaseOptions::scan_readahead) bytes. This avoids
+    /// the random reads of [GhalaDb::iter] for full scans where the key order
+    /// does not matter, such as analytics and backups.
+    ///
+    /// The keys and value pointers are collected upfront.
+    pub fn scan_unordered(
+        &mut self,
+    ) -> GhalaDbResult<impl Iterator<Item = GhalaDbResult<(K, V)>> + '_> {
+        trace!("GhalaDb::scan_unordered");
+        let ks = DEFAULT_KEYSPACE_ID;
+        let mut ptrs = vec![];
+        let mut others = VecDeque::new();
+        for kv in self.keyspaces.keys_ref(ks)?.iter()? {
+            let (key, entry) = kv?;
+            match entry.dp() {
+                Some(dp) if entry.operands.is_empty() => ptrs.push((dp, key)),
+                _ => others.push_back((key, entry)),
+            }
+        }
+        ptrs.sort_unstable_by_key(|(dp, _)| (dp.vlog, dp.offset));
+        let iter = ScanEntries {
             valman: &mut self.vlogs_man,
-            _k: PhantomData,
-            _v: PhantomData,
+            merge_op: keyspace_merge_op(&self.merge_op, ks),
+            ptrs: ptrs.into(),
+            others,
+            ready: VecDeque::new(),
+            readahead: self.opts.scan_readahead,
         };
+        Ok(GhalaDbIter::new(Box::new(iter)))
+    }
 
-        Ok(db_iter.into_iter())
+    /// Returns the value cache statistics.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.vlogs_man.cache_stats()
     }
 
-    /// Attempts to sync all data to disk.
+    /// Syncs all data to disk. Writes made before a successful sync survive
+    /// a crash.
+    ///
+    /// The vlogs are synced before the keys tables, so that keys never point
+    /// to data entries lost in a crash.
     pub fn sync(&mut self) -> GhalaDbResult<()> {
         trace!("GhalaDb::sync");
-        self.keys.sync()?;
         self.vlogs_man.sync()?;
+        self.keyspaces.sync()?;
+        Ok(())
+    }
+
+    /// Creates a consistent copy of the data store in the `dest` directory,
+    /// which can be opened as a data store of its own.
+    ///
+    /// The vlogs and keys tables are synced and snapshotted at the same
+    /// instant, as no writes or garbage collection happen while the checkpoint
+    /// is taken. Sealed vlogs and SSTables are hard linked when `dest` is on
+    /// the same file system, so checkpoints are cheap, and linked vlogs
+    /// outlive their removal from the data store by the garbage collector. The
+    /// tail vlog is copied up to its current size.
+    ///
+    /// The `dest` directory is created if needed, and has to be empty.
+    pub fn checkpoint<P: AsRef<Path>>(&mut self, dest: P) -> GhalaDbResult<()> {
+        let dest = dest.as_ref();
+        trace!("GhalaDb::checkpoint dest: {}", dest.display());
+        init_empty_dir(self.fs(), dest)?;
+        let base = self.path().to_path_buf();
+        for file in self.live_files()? {
+            let (src, dst) = (base.join(&file.path), dest.join(&file.path));
+            let fs = self.fs();
+            if let Some(parent) = dst.parent() {
+                fs.create_dir_all(parent)?;
+            }
+            match file.kind {
+                FileKind::Vlog { tail: false, .. } | FileKind::Immutable => {
+                    fs.link(&src, &dst)?
+                }
+                FileKind::Vlog { tail: true, .. } | FileKind::Mutable => {
+                    copy_range(fs, &src, &dst, 0, file.len)?
+                }
+            }
+        }
         Ok(())
     }
 
+    /// Verifies the integrity of the data store, after syncing it.
+    ///
+    /// Every live key's data pointers are checked to point into a vlog listed
+    /// in the vlogs info, at a data entry that can be read and belongs to the
+    /// key. The vlogs info is checked against the vlog files on disk, and
+    /// every vlog is parsed to its end. Problems are collected in the returned
+    /// report rather than failing the verification.
+    pub fn verify(&mut self) -> GhalaDbResult<VerifyReport> {
+        trace!("GhalaDb::verify");
+        self.sync()?;
+        t!(
+            "verify::verify",
+            verify::verify(&self.keyspaces, &mut self.vlogs_man)
+        )
+    }
+
+    /// Syncs the data store and lists its files.
+    pub(crate) fn live_files(&mut self) -> GhalaDbResult<Vec<LiveFile>> {
+        let mut files = t!("vlogs_man::live_files", self.vlogs_man.live_files())?;
+        files.extend(t!("keyspaces::live_files", self.keyspaces.live_files())?);
+        Ok(files)
+    }
+
+    /// Returns the data store's directory.
+    pub(crate) fn path(&self) -> &Path {
+        self.vlogs_man.base_path()
+    }
+
+    /// Returns the file system holding the data store.
+    pub(crate) fn fs(&self) -> &dyn FileSystem {
+        self.vlogs_man.fs()
+    }
+
+    pub(crate) fn opts(&self) -> &DatabaseOptions {
+        &self.opts
+    }
+
+    pub(crate) fn vlogs_man(&mut self) -> &mut VlogsMan {
+        &mut self.vlogs_man
+    }
+
+    /// Expiry time of an entry inserted now into a keyspace.
+    pub(crate) fn default_expiry(&self, ks: KeyspaceId) -> Option<u64> {
+        self.keyspaces.options(ks).default_expiry()
+    }
+
+    /// Runs the garbage collector over every vlog but the tail, reclaiming the
+    /// space of stale data entries, and syncs the data store.
+    ///
+    /// Garbage collection otherwise runs a step at a time during writes, if
+    /// [compact](DatabaseOptions::compact) is enabled.
+    pub fn compact(&mut self) -> GhalaDbResult<()> {
+        trace!("GhalaDb::compact");
+        let tail = self.vlogs_man.tail();
+        loop {
+            if self.gc.is_none() {
+                match self.vlogs_man.get_gc_cand()? {
+                    Some((vnum, _)) if vnum < tail => {}
+                    _ => break,
+                }
+            }
+            t!("gc", self.gc_step())?;
+        }
+        self.sync()
+    }
+
     fn gc(&mut self) -> GhalaDbResult<()> {
         trace!("GhalaDb::gc");
         if !self.opts.compact {
             return Ok(());
         }
+        self.gc_step()
+    }
+
+    fn gc_step(&mut self) -> GhalaDbResult<()> {
         if let Some(ref mut gc) = self.gc {
-            if let Some(de) = gc.sweep(&mut self.keys)? {
+            if let Some((dp, de, entry)) = gc.sweep(&mut self.keyspaces)? {
                 // GC found a live data entry. Re-insert it.
-                t!("gc::put_raw", self.put_raw(de.key, de.val, true))?;
+                t!("gc::relocate", self.relocate(dp, de, entry))?;
             } else {
-                // GC has finished going through the vlog.
-                t!("vlogs_man::drop_vlog", self.vlogs_man.drop_vlog(gc.vnum()))?;
+                // GC has finished going through the vlog. The relocated
+                // entries are made durable before the vlog is deleted.
+                let vnum = gc.vnum();
+                t!("GhalaDb::sync", self.sync())?;
+                t!("vlogs_man::drop_vlog", self.vlogs_man.drop_vlog(vnum))?;
                 self.gc = None;
             }
         } else if let Some((vnum, path)) = self.vlogs_man.get_gc_cand()? {
-            let gc = t!("gc::new", GarbageCollector::new(vnum, &path))?;
+            debug!("GarbageCollector::new vlog: {vnum} at: {path:?}");
+            let gc = t!(
+                "gc::new",
+                self.vlogs_man
+                    .reader(vnum)
+                    .map(|rdr| GarbageCollector::new(vnum, rdr))
+            )?;
             self.gc = Some(gc);
         }
 
         Ok(())
     }
 
-    fn init_dir(path: &Path) -> GhalaDbResult<()> {
+    /// Moves a live data entry to the tail vlog.
+    ///
+    /// If the key has merge operands and a merge operator is registered, the
+    /// operands are folded into a single value instead.
+    fn relocate(
+        &mut self,
+        dp: DataPtr,
+        de: DataEntry,
+        mut entry: KeyEntry,
+    ) -> GhalaDbResult<()> {
+        let ks = de.keyspace;
+        let merge_op = keyspace_merge_op(&self.merge_op, ks);
+        if !entry.operands.is_empty() && merge_op.is_some() {
+            let val = t!(
+                "merge::resolve",
+                merge::resolve(&mut self.vlogs_man, merge_op, &entry)
+            )?;
+            return self.write_entry(ks, de.key, val, entry.expires_at);
+        }
+        let new_dp = t!("vlogman::put", self.vlogs_man.put(&de, self.compress(ks)))?;
+        self.vlogs_man.invalidate(&dp);
+        match de.kind {
+            EntryKind::Value => entry.val = Some(ValueRef::Ptr(new_dp)),
+            EntryKind::Chunk => {
+                if let Some(ValueRef::Chunked(manifest)) = &mut entry.val {
+                    for chunk in manifest.chunks.iter_mut().filter(|c| **c == dp) {
+                        *chunk = new_dp;
+                    }
+                }
+            }
+            EntryKind::MergeOperand => {
+                for op_dp in entry.operands.iter_mut().filter(|op| **op == dp) {
+                    *op_dp = new_dp;
+                }
+            }
+        }
+        t!("keys::put", self.keyspaces.keys(ks)?.put(de.key, entry))?;
+        self.maintain_keys(ks)
+    }
+
+    fn init_dir(fs: &dyn FileSystem, path: &Path) -> GhalaDbResult<()> {
         trace!("GhalaDb::init_dir : {}", path.display());
-        match std::fs::create_dir_all(path) {
+        match fs.create_dir_all(path) {
             Ok(_) => Ok(()),
             Err(e) => match e.kind() {
                 std::io::ErrorKind::AlreadyExists => {
-                    if std::fs::metadata(path).map(|m| m.is_dir())? {
+                    if fs.is_dir(path) {
                         Ok(())
                     } else {
                         Err(GhalaDbError::DbPathNotDirectory(path.to_path_buf()))
@@ -199,40 +961,329 @@ where
     }
 }
 
-pub struct GhalaDbIter<'a, K, V> {
-    iter: Box<dyn Iterator<Item = (&'a Bytes, &'a DataPtr)> + 'a>,
-    valman: &'a mut VlogsMan,
-    _k: PhantomData<K>,
-    _v: PhantomData<V>,
-}
-
-impl<K, V> Iterator for GhalaDbIter<'_, K, V>
+impl<K, V> Drop for GhalaDb<K, V>
 where
-    K: Decode,
-    V: Decode,
+    K: Encode + Decode,
+    V: Encode + Decode,
 {
-    type Item = GhalaDbResult<(K, V)>;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.nxt() {
-            Err(e) => Some(Err(e)),
-            Ok(None) => None,
-            Ok(Some(kv)) => Some(Ok(kv)),
-        }
+    fn drop(&mut self) {
+        // the keys tables would otherwise be synced before the vlogs
+        t!("GhalaDb::drop", self.sync()).ok();
     }
 }
 
-impl<K, V> GhalaDbIter<'_, K, V>
+impl<K, V> RawStore for GhalaDb<K, V>
 where
-    K: Decode,
-    V: Decode,
+    K: Encode + Decode,
+    V: Encode + Decode,
 {
-    fn nxt(&mut self) -> GhalaDbResult<Option<(K, V)>> {
-        if let Some((_, dp)) = self.iter.next() {
-            let v = self.valman.get(dp)?;
-            let key: K = Dec::deser_raw(&v.key)?;
-            let val: V = Dec::deser_raw(&v.val)?;
-            Ok(Some((key, val)))
+    fn exists_raw(&mut self, ks: KeyspaceId, key: &[u8]) -> GhalaDbResult<bool> {
+        self.keyspaces.keys(ks)?.exists(key)
+    }
+
+    fn get_raw(
+        &mut self,
+        ks: KeyspaceId,
+        key: &[u8],
+    ) -> GhalaDbResult<Option<Bytes>> {
+        if let Some(entry) = self.keyspaces.keys(ks)?.get(key)? {
+            let merge_op = keyspace_merge_op(&self.merge_op, ks);
+            let bytes = t!(
+                "merge::resolve",
+                merge::resolve(&mut self.vlogs_man, merge_op, &entry)
+            )?;
+            Ok(Some(bytes))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn put_raw(
+        &mut self,
+        ks: KeyspaceId,
+        key: Bytes,
+        val: Bytes,
+        expires_at: Option<u64>,
+    ) -> GhalaDbResult<()> {
+        self.write_entry(ks, key, val, expires_at)?;
+        self.auto_train_dict();
+        t!("gc", self.gc())
+    }
+
+    fn delete_raw(&mut self, ks: KeyspaceId, key: &[u8]) -> GhalaDbResult<()> {
+        t!("keys::del", self.keyspaces.keys(ks)?.delete(key))?;
+        self.maintain_keys(ks)?;
+        t!("gc", self.gc())?;
+        Ok(())
+    }
+
+    fn iter_raw(&mut self, ks: KeyspaceId) -> GhalaDbResult<RawIter<'_>> {
+        let merge_op = keyspace_merge_op(&self.merge_op, ks);
+        let iter = Box::new(self.keyspaces.keys_ref(ks)?.iter()?);
+        if self.opts.prefetch_depth == 0 {
+            return Ok(Box::new(RawEntries {
+                iter,
+                valman: &mut self.vlogs_man,
+                merge_op,
+            }));
+        }
+        // prefetching threads read the vlogs from disk
+        t!("vlogs_man::flush", self.vlogs_man.flush())?;
+        let threads = self.opts.prefetch_threads;
+        let pool = self.read_pool.get_or_insert_with(|| ReadPool::new(threads));
+        let (reply, replies) = mpsc::channel();
+        Ok(Box::new(PrefetchEntries {
+            iter,
+            valman: &mut self.vlogs_man,
+            merge_op,
+            pool,
+            depth: self.opts.prefetch_depth,
+            window: VecDeque::new(),
+            seq: 0,
+            reply,
+            replies,
+            fetched: HashMap::new(),
+            timeout: Duration::from_millis(self.opts.prefetch_timeout_ms),
+            done: 0,
+        }))
+    }
+}
+
+/// Fails if a key is too large for the keys table, before any of its data is
+/// written.
+fn check_key(key: &[u8]) -> GhalaDbResult<()> {
+    if key.len() > MAX_KEY_SZ {
+        return Err(GhalaDbError::KeyTooLarge(key.len()));
+    }
+    Ok(())
+}
+
+/// Merge operator of a keyspace. Merges are only supported in the default
+/// keyspace.
+fn keyspace_merge_op<V>(
+    merge_op: &Option<Box<dyn MergeOperator<V>>>,
+    ks: KeyspaceId,
+) -> Option<&dyn MergeOperator<V>> {
+    if ks == DEFAULT_KEYSPACE_ID {
+        merge_op.as_deref()
+    } else {
+        None
+    }
+}
+
+/// Iterates over the raw key-value pairs of a keys table.
+struct RawEntries<'a, V> {
+    iter: Box<dyn Iterator<Item = GhalaDbResult<(Bytes, KeyEntry)>> + 'a>,
+    valman: &'a mut VlogsMan,
+    merge_op: Option<&'a dyn MergeOperator<V>>,
+}
+
+impl<V> Iterator for RawEntries<'_, V>
+where
+    V: Encode + Decode,
+{
+    type Item = GhalaDbResult<(Bytes, Bytes)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, entry) = match self.iter.next()? {
+            Ok(kv) => kv,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(merge::resolve(self.valman, self.merge_op, &entry).map(|v| (key, v)))
+    }
+}
+
+/// Iterates over the raw key-value pairs of a keys table, reading the values
+/// of the next keys ahead using a [ReadPool].
+struct PrefetchEntries<'a, V> {
+    iter: Box<dyn Iterator<Item = GhalaDbResult<(Bytes, KeyEntry)>> + 'a>,
+    valman: &'a mut VlogsMan,
+    merge_op: Option<&'a dyn MergeOperator<V>>,
+    pool: &'a ReadPool,
+    depth: usize,
+    /// Upcoming entries along with their prefetch request number, if any.
+    window: VecDeque<GhalaDbResult<(Bytes, KeyEntry, Option<u64>)>>,
+    seq: u64,
+    reply: Sender<Prefetched>,
+    replies: Receiver<Prefetched>,
+    /// Prefetched values that arrived ahead of their turn.
+    fetched: HashMap<u64, std::io::Result<Bytes>>,
+    /// Time waited for a prefetched value before reading it synchronously.
+    timeout: Duration,
+    /// Request number of the last value returned. Replies to earlier requests
+    /// arrive late, once their value was read synchronously, and are dropped.
+    done: u64,
+}
+
+impl<V> PrefetchEntries<'_, V>
+where
+    V: Encode + Decode,
+{
+    /// Pulls keys into the window, requesting their values.
+    fn fill(&mut self) {
+        while self.window.len() < self.depth {
+            let Some(kv) = self.iter.next() else {
+                return;
+            };
+            let item = kv.map(|(key, entry)| match entry.dp() {
+                Some(dp) if entry.operands.is_empty() => {
+                    self.seq += 1;
+                    let file = self.valman.file(dp.vlog);
+                    self.pool.read(self.seq, file, dp, self.reply.clone());
+                    (key, entry, Some(self.seq))
+                }
+                _ => (key, entry, None),
+            });
+            self.window.push_back(item);
+        }
+    }
+
+    fn value(&mut self, entry: &KeyEntry, seq: Option<u64>) -> GhalaDbResult<Bytes> {
+        let (Some(seq), Some(dp)) = (seq, entry.dp()) else {
+            return merge::resolve(self.valman, self.merge_op, entry);
+        };
+        // our own sender keeps the channel open, so a lost reply is only
+        // noticed by timing out
+        while !self.fetched.contains_key(&seq) {
+            match self.replies.recv_timeout(self.timeout) {
+                Ok((n, res)) if n > self.done => {
+                    self.fetched.insert(n, res);
+                }
+                Ok(_) => {}
+                Err(_) => {
+                    warn!("PrefetchEntries::value no reply for request: {seq}");
+                    break;
+                }
+            }
+        }
+        self.done = seq;
+        match self.fetched.remove(&seq) {
+            Some(Ok(buf)) => {
+                Ok(t!("vlogman::decode", self.valman.decode(&dp, &buf))?.val)
+            }
+            // fall back to reading the value in place
+            _ => Ok(t!("vlogman::get", self.valman.get(&dp))?.val),
+        }
+    }
+}
+
+impl<V> Iterator for PrefetchEntries<'_, V>
+where
+    V: Encode + Decode,
+{
+    type Item = GhalaDbResult<(Bytes, Bytes)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.fill();
+        let (key, entry, seq) = match self.window.pop_front()? {
+            Ok(item) => item,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(self.value(&entry, seq).map(|v| (key, v)))
+    }
+}
+
+/// Iterates over the raw key-value pairs of a keys table, reading values in
+/// their values log order.
+struct ScanEntries<'a, V> {
+    valman: &'a mut VlogsMan,
+    merge_op: Option<&'a dyn MergeOperator<V>>,
+    /// Pointers to plain values, sorted by location.
+    ptrs: VecDeque<(DataPtr, Bytes)>,
+    /// Inlined, chunked and merged values.
+    others: VecDeque<(Bytes, KeyEntry)>,
+    /// Values read ahead.
+    ready: VecDeque<(Bytes, Bytes)>,
+    readahead: usize,
+}
+
+impl<V> ScanEntries<'_, V> {
+    /// Reads the next run of values that lie close together in a vlog.
+    fn read_ahead(&mut self) -> GhalaDbResult<()> {
+        let Some(&(first, _)) = self.ptrs.front() else {
+            return Ok(());
+        };
+        let run = self
+            .ptrs
+            .iter()
+            .take_while(|(dp, _)| {
+                dp.vlog == first.vlog
+                    && dp.offset + dp.len as u64 - first.offset
+                        <= self.readahead as u64
+            })
+            .count()
+            .max(1);
+        let (dps, keys): (Vec<DataPtr>, Vec<Bytes>) = self.ptrs.drain(..run).unzip();
+        let des = t!("vlogman::get_span", self.valman.get_span(&dps))?;
+        self.ready
+            .extend(keys.into_iter().zip(des.into_iter().map(|de| de.val)));
+        Ok(())
+    }
+}
+
+impl<V> Iterator for ScanEntries<'_, V>
+where
+    V: Encode + Decode,
+{
+    type Item = GhalaDbResult<(Bytes, Bytes)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.ready.is_empty() {
+            if let Err(e) = self.read_ahead() {
+                return Some(Err(e));
+            }
+        }
+        if let Some(kv) = self.ready.pop_front() {
+            return Some(Ok(kv));
+        }
+        let (key, entry) = self.others.pop_front()?;
+        Some(merge::resolve(self.valman, self.merge_op, &entry).map(|v| (key, v)))
+    }
+}
+
+pub struct GhalaDbIter<'a, K, V> {
+    iter: RawIter<'a>,
+    _k: PhantomData<K>,
+    _v: PhantomData<V>,
+}
+
+impl<'a, K, V> GhalaDbIter<'a, K, V> {
+    pub(crate) fn new(iter: RawIter<'a>) -> Self {
+        Self {
+            iter,
+            _k: PhantomData,
+            _v: PhantomData,
+        }
+    }
+}
+
+impl<K, V> Iterator for GhalaDbIter<'_, K, V>
+where
+    K: Decode,
+    V: Decode,
+{
+    type Item = GhalaDbResult<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.nxt() {
+            Err(e) => Some(Err(e)),
+            Ok(None) => None,
+            Ok(Some(kv)) => Some(Ok(kv)),
+        }
+    }
+}
+
+impl<K, V> GhalaDbIter<'_, K, V>
+where
+    K: Decode,
+    V: Decode,
+{
+    fn nxt(&mut self) -> GhalaDbResult<Option<(K, V)>> {
+        if let Some(kv) = self.iter.next() {
+            let (key, val) = kv?;
+            let key: K = Dec::deser_raw(&key)?;
+            let val: V = Dec::deser_raw(&val)?;
+            Ok(Some((key, val)))
         } else {
             Ok(None)
         }
@@ -406,6 +1457,175 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn full_compaction() -> GhalaDbResult<()> {
+        let tmp_dir = tempdir()?;
+        let opts = DatabaseOptions::builder()
+            .max_vlog_size(4 * 1024)
+            .compact(false)
+            .build();
+        let mut db: GhalaDb<u32, Vec<u8>> =
+            GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        for i in 0..200 {
+            db.put(&i, &vec![1u8; 256])?;
+        }
+        for i in 0..200 {
+            db.put(&i, &vec![2u8; 256])?;
+        }
+        let old_count = db.vlogs_man.vlogs_count();
+        db.compact()?;
+        let count = db.vlogs_man.vlogs_count();
+        assert!(count < old_count, "vlogs count: {old_count} -> {count}");
+        for i in 0..200 {
+            assert_eq!(db.get(&i)?, Some(vec![2u8; 256]));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn ttl_expiry() -> GhalaDbResult<()> {
+        env_logger::try_init().ok();
+        let tmp_dir = tempdir()?;
+        let opts = DatabaseOptions::builder()
+            .max_vlog_size(4 * 1024)
+            .sync(false)
+            .build();
+        let mut db: GhalaDb<Vec<u8>, Vec<u8>> =
+            GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        let data = (0..50).map(|_| Bytes::gen()).collect::<Vec<_>>();
+        for entry in &data {
+            db.put_with_ttl(entry, entry, Duration::from_millis(100))?;
+        }
+        db.put(&data[0], &data[0])?;
+        assert_eq!(db.get(&data[1])?.as_ref(), Some(&data[1]));
+        std::thread::sleep(Duration::from_millis(150));
+        assert_eq!(db.get(&data[0])?.as_ref(), Some(&data[0]));
+        assert_eq!(db.get(&data[1])?, None);
+        assert!(!db.exists(&data[2])?);
+        assert_eq!(db.iter()?.count(), 1);
+
+        let old_count = db.vlogs_man.vlogs_count();
+        for _ in 0..50 {
+            db.delete(&Bytes::gen())?;
+        }
+        let count = db.vlogs_man.vlogs_count();
+        assert!(
+            count < old_count,
+            "vlogs count wrong: old {old_count} > cur {count}"
+        );
+        assert_eq!(db.get(&data[0])?.as_ref(), Some(&data[0]));
+        Ok(())
+    }
+
+    #[test]
+    fn compare_and_swap() -> GhalaDbResult<()> {
+        env_logger::try_init().ok();
+        let tmp_dir = tempdir()?;
+        let mut db: GhalaDb<String, String> = GhalaDb::new(tmp_dir.path(), None)?;
+        let k = s!("king");
+
+        assert_eq!(db.put_if_absent(&k, &s!("queen"))?, Ok(()));
+        assert_eq!(
+            db.put_if_absent(&k, &s!("prince"))?,
+            Err(CompareAndSwapError {
+                current: Some(s!("queen"))
+            })
+        );
+        assert_eq!(
+            db.compare_and_swap(&k, Some(&s!("prince")), Some(&s!("duke")))?,
+            Err(CompareAndSwapError {
+                current: Some(s!("queen"))
+            })
+        );
+        assert_eq!(
+            db.compare_and_swap(&k, Some(&s!("queen")), Some(&s!("duke")))?,
+            Ok(())
+        );
+        assert_eq!(db.get(&k)?, Some(s!("duke")));
+        assert!(db.delete_if_equals(&k, &s!("queen"))?.is_err());
+        assert_eq!(db.delete_if_equals(&k, &s!("duke"))?, Ok(()));
+        assert_eq!(db.get(&k)?, None);
+        assert_eq!(
+            db.delete_if_equals(&k, &s!("duke"))?,
+            Err(CompareAndSwapError { current: None })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn merge_operands() -> GhalaDbResult<()> {
+        env_logger::try_init().ok();
+        let tmp_dir = tempdir()?;
+        let opts = DatabaseOptions::builder()
+            .max_vlog_size(4 * 1024)
+            .sync(false)
+            .build();
+        let mut db: GhalaDb<String, u64> = GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        let k = s!("counter");
+        assert!(matches!(
+            db.merge(&k, &1),
+            Err(GhalaDbError::MissingMergeOperator)
+        ));
+        db.set_merge_operator(|old: Option<u64>, op: u64| old.unwrap_or(0) + op);
+        db.merge(&k, &1)?;
+        db.merge(&k, &2)?;
+        assert_eq!(db.get(&k)?, Some(3));
+        db.put(&k, &10)?;
+        db.merge(&k, &5)?;
+        assert_eq!(db.get(&k)?, Some(15));
+        assert_eq!(db.iter()?.collect::<GhalaDbResult<Vec<_>>>()?, [(k, 15)]);
+
+        let keys: Vec<String> = (0..20).map(|i| format!("key-{i}")).collect();
+        for _ in 0..100 {
+            for k in &keys {
+                db.merge(k, &1)?;
+            }
+        }
+        let mut folded = false;
+        for k in &keys {
+            assert_eq!(db.get(k)?, Some(100));
+            let entry = db.default_keys().get(&Dec::ser_raw(k)?)?.unwrap();
+            folded |= entry.val.is_some();
+        }
+        assert!(folded, "gc did not fold any merge operands");
+        db.merge(&s!("fresh"), &1)?;
+        drop(db);
+
+        let mut db: GhalaDb<String, u64> = GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        assert!(matches!(
+            db.get(&s!("fresh")),
+            Err(GhalaDbError::MissingMergeOperator)
+        ));
+        db.set_merge_operator(|old: Option<u64>, op: u64| old.unwrap_or(0) + op);
+        for k in &keys {
+            assert_eq!(db.get(k)?, Some(100));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn merge_checks() -> GhalaDbResult<()> {
+        let opts = DatabaseOptions::builder().in_memory(true).build();
+        let mut db: GhalaDb<Vec<u8>, u64> = GhalaDb::new("db", Some(opts))?;
+        db.set_merge_operator(|old: Option<u64>, op: u64| old.unwrap_or(0) + op);
+        let k = vec![1u8];
+        db.put_with_ttl(&k, &10, Duration::from_millis(1))?;
+        assert!(matches!(
+            db.merge(&vec![7u8; MAX_KEY_SZ], &1),
+            Err(GhalaDbError::KeyTooLarge(_))
+        ));
+        db.sync()?;
+        let tail = db.vlogs_man().tail();
+        assert_eq!(db.vlogs_man().reader(tail)?.count(), 1);
+
+        std::thread::sleep(Duration::from_millis(5));
+        db.merge(&k, &5)?;
+        assert_eq!(db.get(&k)?, Some(5));
+        let entry = db.default_keys().get(&Dec::ser_raw(&k)?)?.unwrap();
+        assert_eq!(entry.expires_at, None);
+        Ok(())
+    }
+
     #[test]
     fn gc() -> GhalaDbResult<()> {
         env_logger::try_init().ok();
@@ -428,6 +1648,602 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn max_value_size() -> GhalaDbResult<()> {
+        let opts = DatabaseOptions::builder()
+            .in_memory(true)
+            .max_value_size(256)
+            .build();
+        let mut db: GhalaDb<u32, Bytes> = GhalaDb::new("db", Some(opts))?;
+        db.put(&1, &vec![1; 128])?;
+        assert!(matches!(
+            db.put(&2, &vec![2; 512]),
+            Err(GhalaDbError::ValueTooLarge(_))
+        ));
+        assert_eq!(db.get(&1)?, Some(vec![1; 128]));
+        assert_eq!(db.get(&2)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn dict_compression() -> GhalaDbResult<()> {
+        env_logger::try_init().ok();
+        let tmp_dir = tempdir()?;
+        let opts = DatabaseOptions::builder()
+            .dict_compression(true)
+            .dict_train_samples(200)
+            .sync(false)
+            .build();
+        let data: Vec<(String, String)> = (0..1000)
+            .map(|i| {
+                let v = format!(r#"{{"id":{i},"name":"user-{i}","active":true}}"#);
+                (format!("key-{i}"), v)
+            })
+            .collect();
+        let mut db: GhalaDb<String, String> =
+            GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        for (k, v) in &data {
+            db.put(k, v)?;
+        }
+        assert!(db.vlogs_man.has_dict());
+        for (k, v) in &data {
+            assert_eq!(db.get(k)?.as_ref(), Some(v));
+        }
+        drop(db);
+        let mut db: GhalaDb<String, String> =
+            GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        for (k, v) in &data {
+            assert_eq!(db.get(k)?.as_ref(), Some(v));
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encryption_at_rest() -> GhalaDbResult<()> {
+        use crate::KeyRing;
+        use std::sync::Arc;
+
+        env_logger::try_init().ok();
+        let tmp_dir = tempdir()?;
+        let opts = DatabaseOptions::builder()
+            .max_vlog_size(4 * 1024)
+            .sync(false)
+            .build();
+        let ring = Arc::new(KeyRing::new(1, [42u8; 32]));
+        let mut db: GhalaDb<String, String> =
+            GhalaDb::with_encryption(tmp_dir.path(), Some(opts), ring.clone())?;
+        let secret = s!("top-secret-value");
+        db.put(&s!("key"), &secret)?;
+        ring.rotate(2, [7u8; 32]);
+        for i in 0..100 {
+            db.put(&format!("k{i}"), &format!("v{i}"))?;
+        }
+        drop(db);
+        let mut dirs = vec![tmp_dir.path().to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let bytes = std::fs::read(path)?;
+                assert!(!bytes
+                    .windows(secret.len())
+                    .any(|w| w == secret.as_bytes()));
+            }
+        }
+
+        let mut db: GhalaDb<String, String> =
+            GhalaDb::with_encryption(tmp_dir.path(), Some(opts), ring)?;
+        assert_eq!(db.get(&s!("key"))?, Some(secret));
+        drop(db);
+
+        let wrong = Arc::new(KeyRing::new(1, [0u8; 32]));
+        let res: GhalaDbResult<GhalaDb<String, String>> =
+            GhalaDb::with_encryption(tmp_dir.path(), Some(opts), wrong);
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn keyspaces() -> GhalaDbResult<()> {
+        env_logger::try_init().ok();
+        let tmp_dir = tempdir()?;
+        let opts = DatabaseOptions::builder()
+            .max_vlog_size(4 * 1024)
+            .sync(false)
+            .build();
+        let mut db: GhalaDb<String, String> =
+            GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        db.put(&s!("k"), &s!("default"))?;
+        {
+            let mut counts = db.keyspace::<String, u64>("counts")?;
+            for i in 0..200u64 {
+                counts.put(&s!("k"), &i)?;
+                counts.put(&format!("k{i}"), &i)?;
+            }
+            assert_eq!(counts.get(&s!("k"))?, Some(199));
+        }
+        let ks_opts = KeyspaceOptions::builder()
+            .compress(false)
+            .default_ttl(Duration::from_millis(1))
+            .build();
+        let mut tmp = db.keyspace_with_options::<u32, u32>("tmp", ks_opts)?;
+        tmp.put(&1, &1)?;
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(!tmp.exists(&1)?);
+        assert_eq!(db.get(&s!("k"))?, Some(s!("default")));
+        drop(db);
+
+        let mut db: GhalaDb<String, String> =
+            GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        assert_eq!(db.get(&s!("k"))?, Some(s!("default")));
+        assert_eq!(db.iter()?.count(), 1);
+        let mut counts = db.keyspace::<String, u64>("counts")?;
+        assert_eq!(counts.get(&s!("k"))?, Some(199));
+        assert_eq!(counts.get(&s!("k42"))?, Some(42));
+        assert_eq!(counts.iter()?.count(), 201);
+        Ok(())
+    }
+
+    #[test]
+    fn write_batch() -> GhalaDbResult<()> {
+        env_logger::try_init().ok();
+        let tmp_dir = tempdir()?;
+        let mut db: GhalaDb<String, String> = GhalaDb::new(tmp_dir.path(), None)?;
+        db.put(&s!("gone"), &s!("soon"))?;
+        let mut batch = WriteBatch::new();
+        batch.put(crate::DEFAULT_KEYSPACE, "a", "b")?;
+        batch.delete(crate::DEFAULT_KEYSPACE, "gone")?;
+        batch.put("nums", &1u32, &2u32)?;
+        assert_eq!(batch.len(), 3);
+        db.write(batch)?;
+        assert_eq!(db.get(&s!("a"))?, Some(s!("b")));
+        assert!(!db.exists(&s!("gone"))?);
+        assert_eq!(db.keyspace::<u32, u32>("nums")?.get(&1)?, Some(2));
+        Ok(())
+    }
+
+    #[test]
+    fn value_cache() -> GhalaDbResult<()> {
+        env_logger::try_init().ok();
+        let tmp_dir = tempdir()?;
+        let opts = DatabaseOptions::builder()
+            .max_vlog_size(4 * 1024)
+            .value_cache_size(1024 * 1024)
+            .sync(false)
+            .build();
+        let mut db: GhalaDb<Vec<u8>, Vec<u8>> =
+            GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        // 100 entries of less than 600 bytes fit in any single cache shard
+        let data = (0..100u8).map(|i| vec![i; 256]).collect::<Vec<_>>();
+        for entry in &data {
+            db.put(entry, entry)?;
+        }
+        for entry in &data {
+            assert_eq!(db.get(entry)?.as_ref(), Some(entry));
+        }
+        let stats = db.cache_stats();
+        assert_eq!(stats.hits, 0);
+        assert_eq!(stats.misses, 100);
+        assert_eq!(stats.entries, 100);
+        for entry in &data {
+            assert_eq!(db.get(entry)?.as_ref(), Some(entry));
+        }
+        assert_eq!(db.cache_stats().hits, 100);
+
+        // overwriting triggers gc, which must not serve stale entries
+        for entry in &data {
+            db.put(entry, &entry.repeat(2))?;
+        }
+        for entry in &data {
+            assert_eq!(db.get(entry)?, Some(entry.repeat(2)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn inlining_disabled() -> GhalaDbResult<()> {
+        let opts = DatabaseOptions::builder().in_memory(true).build();
+        let mut db: GhalaDb<u32, ()> = GhalaDb::new("db", Some(opts))?;
+        // unit values encode to zero bytes
+        db.put(&1, &())?;
+        let entry = db.default_keys().get(&Dec::ser_raw(&1u32)?)?.unwrap();
+        assert!(entry.dp().is_some());
+        assert_eq!(db.get(&1)?, Some(()));
+        Ok(())
+    }
+
+    #[test]
+    fn inline_values() -> GhalaDbResult<()> {
+        env_logger::try_init().ok();
+        let tmp_dir = tempdir()?;
+        let opts = DatabaseOptions::builder()
+            .inline_threshold(16)
+            .value_cache_size(1024 * 1024)
+            .sync(false)
+            .build();
+        let mut db: GhalaDb<u32, String> = GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        for i in 0..100 {
+            db.put(&i, &format!("v{i}"))?;
+        }
+        db.put(&1000, &"a value too large to be inlined".repeat(4))?;
+        for i in 0..100 {
+            assert_eq!(db.get(&i)?, Some(format!("v{i}")));
+        }
+        // inlined values are served without reading the vlogs
+        assert_eq!(db.cache_stats().misses, 0);
+        assert!(db.get(&1000)?.is_some());
+        assert_eq!(db.cache_stats().misses, 1);
+        db.delete(&0)?;
+        drop(db);
+
+        let mut db: GhalaDb<u32, String> = GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        assert_eq!(db.get(&0)?, None);
+        assert_eq!(db.get(&42)?, Some(s!("v42")));
+        assert_eq!(db.iter()?.count(), 100);
+        Ok(())
+    }
+
+    #[test]
+    fn streamed_values() -> GhalaDbResult<()> {
+        use std::io::{Seek, SeekFrom};
+
+        env_logger::try_init().ok();
+        let tmp_dir = tempdir()?;
+        let opts = DatabaseOptions::builder()
+            .max_vlog_size(4 * 1024)
+            .chunk_size(1000)
+            .sync(false)
+            .build();
+        let blob: Vec<u8> = (0..20_500u32).map(|i| (i % 251) as u8).collect();
+        let mut db: GhalaDb<String, Vec<u8>> =
+            GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        db.put_stream(&s!("blob"), blob.as_slice())?;
+        db.put_stream(&s!("empty"), std::io::empty())?;
+        let blob_key = Dec::ser_raw(&s!("blob"))?;
+        let before = db.default_keys().get(&blob_key)?.unwrap();
+
+        // churn the vlogs so that gc relocates the chunks
+        for i in 0..200u32 {
+            db.put(&s!("churn"), &Bytes::gen())?;
+            db.put(&format!("k{}", i % 10), &Bytes::gen())?;
+        }
+        assert_ne!(db.default_keys().get(&blob_key)?.unwrap(), before);
+        let mut reader = db.get_reader(&s!("blob"))?.unwrap();
+        assert_eq!(reader.len(), blob.len() as u64);
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf)?;
+        assert!(buf == blob);
+
+        reader.seek(SeekFrom::Start(2995))?;
+        let mut buf = [0u8; 10];
+        reader.read_exact(&mut buf)?;
+        assert_eq!(&buf[..], &blob[2995..3005]);
+        reader.seek(SeekFrom::End(-5))?;
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf)?;
+        assert_eq!(&buf[..], &blob[blob.len() - 5..]);
+        assert!(reader.seek(SeekFrom::Current(-100_000)).is_err());
+        assert!(db.get_reader(&s!("empty"))?.unwrap().is_empty());
+        assert!(db.get_reader(&s!("missing"))?.is_none());
+        drop(db);
+
+        let mut db: GhalaDb<String, Vec<u8>> =
+            GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        let mut buf = vec![];
+        db.get_reader(&s!("blob"))?.unwrap().read_to_end(&mut buf)?;
+        assert!(buf == blob);
+        Ok(())
+    }
+
+    #[test]
+    fn unordered_scan() -> GhalaDbResult<()> {
+        env_logger::try_init().ok();
+        for mmap_reads in [false, true] {
+            let tmp_dir = tempdir()?;
+            let opts = DatabaseOptions::builder()
+                .max_vlog_size(16 * 1024)
+                .inline_threshold(8)
+                .scan_readahead(4 * 1024)
+                .mmap_reads(mmap_reads)
+                .sync(false)
+                .build();
+            let mut db: GhalaDb<Vec<u8>, Vec<u8>> =
+                GhalaDb::new(tmp_dir.path(), Some(opts))?;
+            let mut data: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+            for _ in 0..300 {
+                let (k, v) = (Bytes::gen(), Bytes::gen());
+                db.put(&k, &v)?;
+                data.insert(k, v);
+            }
+            db.put(&vec![1], &vec![2])?;
+            data.insert(vec![1], vec![2]);
+            let scanned = db
+                .scan_unordered()?
+                .collect::<GhalaDbResult<BTreeMap<_, _>>>()?;
+            assert!(scanned == data);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn prefetching_iter() -> GhalaDbResult<()> {
+        env_logger::try_init().ok();
+        let tmp_dir = tempdir()?;
+        let opts = DatabaseOptions::builder()
+            .max_vlog_size(16 * 1024)
+            .inline_threshold(8)
+            .prefetch_depth(16)
+            .sync(false)
+            .build();
+        let mut db: GhalaDb<Vec<u8>, Vec<u8>> =
+            GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        db.set_merge_operator(|val: Option<Vec<u8>>, op: Vec<u8>| {
+            [val.unwrap_or_default(), op].concat()
+        });
+        let mut data: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+        for _ in 0..300 {
+            let (k, v) = (Bytes::gen(), Bytes::gen());
+            db.put(&k, &v)?;
+            data.insert(k, v);
+        }
+        db.put(&vec![1], &vec![2])?;
+        db.merge(&vec![1], &vec![3])?;
+        data.insert(vec![1], vec![2, 3]);
+        let iterated = db.iter()?.collect::<GhalaDbResult<BTreeMap<_, _>>>()?;
+        assert!(iterated == data);
+        // a partially consumed iterator leaves the pool usable
+        assert_eq!(db.iter()?.take(3).count(), 3);
+        assert_eq!(db.iter()?.count(), 301);
+        // values whose prefetch reply is lost are read synchronously
+        db.opts.prefetch_timeout_ms = 1;
+        db.read_pool = Some(ReadPool::stopped());
+        let iterated = db.iter()?.collect::<GhalaDbResult<BTreeMap<_, _>>>()?;
+        assert!(iterated == data);
+        Ok(())
+    }
+
+    #[test]
+    fn keys_and_values() -> GhalaDbResult<()> {
+        env_logger::try_init().ok();
+        let tmp_dir = tempdir()?;
+        let opts = DatabaseOptions::builder()
+            .value_cache_size(1024 * 1024)
+            .sync(false)
+            .build();
+        let mut db: GhalaDb<u32, String> = GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        assert!(db.is_empty());
+        for i in 0..100 {
+            db.put(&i, &format!("value-{i}"))?;
+        }
+        db.put(&7, &s!("seven"))?;
+        db.delete(&50)?;
+        assert_eq!(db.len(), 99);
+
+        let mut keys = db.keys()?.collect::<GhalaDbResult<Vec<_>>>()?;
+        keys.sort();
+        assert_eq!(keys, (0..100).filter(|i| *i != 50).collect::<Vec<_>>());
+        let mut range =
+            db.keys_range(40..=55)?.collect::<GhalaDbResult<Vec<_>>>()?;
+        range.sort();
+        assert_eq!(
+            range,
+            [40, 41, 42, 43, 44, 45, 46, 47, 48, 49, 51, 52, 53, 54, 55]
+        );
+        // keys are listed without reading the values
+        assert_eq!(db.cache_stats().misses, 0);
+
+        let values = db.values()?.collect::<GhalaDbResult<Vec<_>>>()?;
+        assert_eq!(values.len(), 99);
+        assert!(values.contains(&s!("seven")));
+        assert!(!values.contains(&s!("value-50")));
+        drop(db);
+
+        let db: GhalaDb<u32, String> = GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        assert_eq!(db.len(), 99);
+        Ok(())
+    }
+
+    #[test]
+    fn checkpoint() -> GhalaDbResult<()> {
+        env_logger::try_init().ok();
+        let tmp_dir = tempdir()?;
+        let cp_dir = tmp_dir.path().join("checkpoint");
+        let opts = DatabaseOptions::builder()
+            .max_vlog_size(4 * 1024)
+            .keys_memtable_size(4 * 1024)
+            .sync(false)
+            .build();
+        let mut db: GhalaDb<u32, Vec<u8>> =
+            GhalaDb::new(tmp_dir.path().join("db"), Some(opts))?;
+        let data: BTreeMap<u32, Vec<u8>> =
+            (0..200).map(|i| (i, Bytes::gen())).collect();
+        for (k, v) in &data {
+            db.put(k, v)?;
+        }
+        db.keyspace::<u32, u32>("nums")?.put(&1, &2)?;
+        db.checkpoint(&cp_dir)?;
+        assert!(matches!(
+            db.checkpoint(&cp_dir),
+            Err(GhalaDbError::DirNotEmpty(_))
+        ));
+
+        // later writes, and gc dropping vlogs, leave the checkpoint untouched
+        for k in data.keys() {
+            db.put(k, &Bytes::gen())?;
+        }
+        db.delete(&0)?;
+        drop(db);
+
+        let mut db: GhalaDb<u32, Vec<u8>> = GhalaDb::new(&cp_dir, Some(opts))?;
+        assert_eq!(db.keys()?.count(), data.len());
+        for (k, v) in &data {
+            assert_eq!(db.get(k)?.as_ref(), Some(v));
+        }
+        assert_eq!(db.keyspace::<u32, u32>("nums")?.get(&1)?, Some(2));
+        Ok(())
+    }
+
+    #[test]
+    fn key_too_large() -> GhalaDbResult<()> {
+        let opts = DatabaseOptions::builder().in_memory(true).build();
+        let mut db: GhalaDb<Vec<u8>, u32> = GhalaDb::new("db", Some(opts))?;
+        let key = vec![7u8; MAX_KEY_SZ];
+        assert!(matches!(
+            db.put(&key, &1),
+            Err(GhalaDbError::KeyTooLarge(_))
+        ));
+        // the stream is left unread
+        let mut stream = std::io::Cursor::new(vec![1u8; 4096]);
+        assert!(matches!(
+            db.put_stream(&key, &mut stream),
+            Err(GhalaDbError::KeyTooLarge(_))
+        ));
+        assert_eq!(stream.position(), 0);
+        db.put(&key[..MAX_KEY_SZ - 8].to_vec(), &1)?;
+        db.sync()?;
+        assert_eq!(db.len(), 1);
+        let tail = db.vlogs_man().tail();
+        assert_eq!(db.vlogs_man().reader(tail)?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn baseline_store() -> GhalaDbResult<()> {
+        let fs = InMemoryFs::new();
+        let base = Path::new("db");
+        fs.create_dir_all(base)?;
+        // vlog 0 holds the first values of every key, and vlog 1 the current
+        // values of odd keys
+        let mut keys = BTreeMap::new();
+        for vnum in 0..2u64 {
+            let mut bytes = vec![];
+            for i in (0..100u32).filter(|i| vnum == 0 || i % 2 == 1) {
+                let key = Dec::ser_raw(&i)?;
+                let val = Dec::ser_raw(&format!("{vnum}-{i}"))?;
+                // format version 0 data entries only hold a key and a value
+                let de = Dec::new(true).ser(&(&key, &val))?;
+                let offset = (bytes.len() + DataPtr::serde_sz()) as u64;
+                let dp = DataPtr::new(vnum, offset, de.len() as u32, true);
+                bytes.extend(Dec::ser_raw(&dp)?);
+                bytes.extend(de);
+                keys.insert(key, dp);
+            }
+            fs.write(&base.join(format!("{vnum}.vlog")), &bytes)?;
+        }
+        fs.write(
+            &base.join("vlog_info"),
+            &Dec::new(true).ser(&vec![0u64, 1])?,
+        )?;
+        // a single keys file holding the keys map, followed by its path and a
+        // sync timestamp
+        fs.write(
+            &base.join("keys"),
+            &Dec::ser_raw(&(&keys, "db/keys", 0u128))?,
+        )?;
+
+        let expected = |i: u32| format!("{}-{i}", i % 2);
+        let mut db: GhalaDb<u32, String> =
+            GhalaDb::with_fs(base, None, Arc::new(fs.clone()))?;
+        assert_eq!(db.len(), 100);
+        for i in 0..100 {
+            assert_eq!(db.get(&i)?, Some(expected(i)));
+        }
+        db.put(&100, &s!("new"))?;
+        db.compact()?;
+        assert!(db.verify()?.is_ok());
+        drop(db);
+        assert!(
+            !fs.exists(&base.join("0.vlog")) && !fs.exists(&base.join("1.vlog"))
+        );
+
+        let mut db: GhalaDb<u32, String> =
+            GhalaDb::with_fs(base, None, Arc::new(fs.clone()))?;
+        assert_eq!(db.len(), 101);
+        for i in 0..100 {
+            assert_eq!(db.get(&i)?, Some(expected(i)));
+        }
+        assert_eq!(db.get(&100)?, Some(s!("new")));
+        Ok(())
+    }
+
+    #[test]
+    fn oversized_baseline_value() -> GhalaDbResult<()> {
+        let fs = InMemoryFs::new();
+        let base = Path::new("db");
+        fs.create_dir_all(base)?;
+        // larger than the 64mb entries of earlier releases' default cap
+        let val = vec![7u8; 65_000_000];
+        let key = Dec::ser_raw(&1u32)?;
+        let de = Dec::new(true).ser(&(&key, &Dec::ser_raw(&val)?))?;
+        let dp = DataPtr::new(0, DataPtr::serde_sz() as u64, de.len() as u32, true);
+        let mut bytes = Dec::ser_raw(&dp)?;
+        bytes.extend(de);
+        fs.write(&base.join("0.vlog"), &bytes)?;
+        fs.write(&base.join("vlog_info"), &Dec::new(true).ser(&vec![0u64])?)?;
+        let keys = BTreeMap::from([(key, dp)]);
+        fs.write(
+            &base.join("keys"),
+            &Dec::ser_raw(&(&keys, "db/keys", 0u128))?,
+        )?;
+
+        let mut db: GhalaDb<u32, Bytes> =
+            GhalaDb::with_fs(base, None, Arc::new(fs.clone()))?;
+        assert_eq!(db.get(&1)?, Some(val.clone()));
+        db.put(&2, &val)?;
+        assert_eq!(db.get(&2)?, Some(val));
+        Ok(())
+    }
+
+    #[test]
+    fn in_memory() -> GhalaDbResult<()> {
+        env_logger::try_init().ok();
+        let fs = InMemoryFs::new();
+        let opts = DatabaseOptions::builder()
+            .max_vlog_size(4 * 1024)
+            .keys_memtable_size(4 * 1024)
+            .mmap_reads(true)
+            .prefetch_depth(8)
+            .build();
+        let data: BTreeMap<u32, Vec<u8>> =
+            (0..200).map(|i| (i, Bytes::gen())).collect();
+        let mut db: GhalaDb<u32, Vec<u8>> =
+            GhalaDb::with_fs("db", Some(opts), Arc::new(fs.clone()))?;
+        for (k, v) in &data {
+            db.put(k, &Bytes::gen())?;
+            db.put(k, v)?;
+        }
+        db.keyspace::<u32, u32>("nums")?.put(&1, &2)?;
+        db.compact()?;
+        assert!(db.verify()?.is_ok());
+        db.checkpoint("checkpoint")?;
+        drop(db);
+        assert!(!Path::new("db").exists());
+
+        for path in ["db", "checkpoint"] {
+            let mut db: GhalaDb<u32, Vec<u8>> =
+                GhalaDb::with_fs(path, Some(opts), Arc::new(fs.clone()))?;
+            assert_eq!(db.keys()?.count(), data.len());
+            let scanned = db.iter()?.collect::<GhalaDbResult<BTreeMap<_, _>>>()?;
+            assert_eq!(scanned, data);
+            assert_eq!(db.keyspace::<u32, u32>("nums")?.get(&1)?, Some(2));
+        }
+
+        // a store kept in memory through its options is dropped with it
+        let opts = DatabaseOptions::builder().in_memory(true).build();
+        let mut db: GhalaDb<u32, u32> = GhalaDb::new("db", Some(opts))?;
+        db.put(&1, &1)?;
+        assert_eq!(db.get(&1)?, Some(1));
+        drop(db);
+        assert!(!Path::new("db").exists());
+        let db: GhalaDb<u32, u32> = GhalaDb::new("db", Some(opts))?;
+        assert!(db.is_empty());
+        Ok(())
+    }
+
     #[test]
     fn data_integrity_1() -> GhalaDbResult<()> {
         env_logger::try_init().ok();
@@ -1,108 +1,666 @@
 use crate::{
     config::DatabaseOptions,
-    core::{Bytes, DataPtr, KeyRef},
+    core::{now_millis, Bytes, DataPtr, FileKind, KeyEntry, KeyRef, LiveFile},
+    crypto::{self, Cipher},
     dec::Dec,
-    error::GhalaDbResult,
-    utils::t,
+    error::{GhalaDbError, GhalaDbResult},
+    fs::{FileWriter, Fs, OpenMode},
+    sstable::{Record, SsTable, MAX_KEY_SZ},
+    utils::{sync_parent, t, write_atomic},
 };
 use bincode::{Decode, Encode};
 use std::{
-    collections::BTreeMap,
-    fmt::Debug,
-    fs::OpenOptions,
-    io::{BufReader, BufWriter, Read, Write},
+    collections::{BTreeMap, BTreeSet},
+    io::{BufWriter, Write},
+    iter::Peekable,
     path::{Path, PathBuf},
     time::{SystemTime, UNIX_EPOCH},
 };
 
+const MANIFEST_FILE: &str = "manifest";
+const TABLE_EXT: &str = "sst";
+const LOG_EXT: &str = "log";
+const BULK_DIR: &str = "bulk";
+const MIGRATE_EXT: &str = "migrate";
+const FRAME_LEN_SZ: usize = 4;
+/// Version of the keys tree format. Version 0 is the format of the first
+/// releases, where the keys of the data store were held in a single file.
+const FORMAT_VERSION: u32 = 1;
+
+/// Keys tables manifest. Lists the live SSTables, oldest first.
+#[derive(Debug, Default, Encode, Decode)]
+struct Manifest {
+    version: u32,
+    tables: Vec<u64>,
+    next_table: u64,
+    len: u64,
+}
+
+/// Keys file of format version 0, which maps every key to a data pointer.
+/// The fields following the map are not needed and left undecoded.
+#[derive(Decode)]
+struct LegacyKeys {
+    map: BTreeMap<Bytes, DataPtr>,
+}
+
 /// Keys
 ///
-/// This is an in-memory map that stores keys and their data pointer.
-/// It is automatically synced to disk during datastore shutdown (when GhalaDb
-/// is dropped) but it can also be synced manually using the `sync` method of
-/// GhalaDb.
-#[derive(Encode, Decode, Debug)]
+/// This is an LSM tree that maps keys to their data pointer, along with an
+/// optional expiry time. Expired keys are hidden from lookups and iteration,
+/// and are removed from the tree when looked up.
+///
+/// Writes go to an in-memory memtable, which is flushed to a sorted SSTable
+/// when full. Lookups check the memtable and then the SSTables from newest to
+/// oldest, using their bloom filters and fence pointers to read at most one
+/// data block per table. Once there are more than `keys_max_tables` SSTables,
+/// they are compacted into a single table.
+///
+/// SSTables are memory-mapped and searched in place, so loading the tree only
+/// reads their footers and replays the memtable's change log.
+///
+/// The tree lives in its own directory, along with a manifest listing its
+/// SSTables. Syncing appends the keys changed since the last sync to a change
+/// log, which is replayed into the memtable on load. Once the memtable is
+/// checkpointed to an SSTable, its change log is deleted. This way the cost of
+/// a sync is proportional to the number of changes rather than the number of
+/// keys.
+///
+/// Changes are synced during datastore shutdown (when GhalaDb is dropped) but
+/// they can also be synced manually using the `sync` method of GhalaDb.
+/// SSTables, change logs and the manifest are encrypted if a cipher is set.
 pub(crate) struct Keys {
-    map: BTreeMap<Bytes, DataPtr>,
+    /// Recent changes. A `None` entry is a tombstone.
+    mem: BTreeMap<Bytes, Option<KeyEntry>>,
+    mem_sz: usize,
+    /// Keys changed since the last sync.
+    dirty: BTreeSet<Bytes>,
+    /// Change log of the memtable. It is opened on the first sync.
+    log: Option<BufWriter<FileWriter>>,
+    /// Set if the change log may end with a torn frame, in which case it is
+    /// not appended to and the next sync checkpoints the memtable instead.
+    torn_log: bool,
+    /// SSTables, oldest first.
+    tables: Vec<SsTable>,
+    next_table: u64,
+    /// Approximate number of keys in the tree, see [Keys::len].
+    len: usize,
     path: PathBuf,
     magic: u128,
     conf: DatabaseOptions,
+    cipher: Option<Cipher>,
+    /// File system holding the tree.
+    fs: Fs,
 }
 
 impl Keys {
-    pub fn new(path: PathBuf, conf: DatabaseOptions) -> Keys {
-        let map = BTreeMap::new();
+    pub fn new(
+        fs: Fs,
+        path: PathBuf,
+        conf: DatabaseOptions,
+        cipher: Option<Cipher>,
+    ) -> Keys {
         let magic = 0;
         Self {
-            map,
+            mem: BTreeMap::new(),
+            mem_sz: 0,
+            dirty: BTreeSet::new(),
+            log: None,
+            torn_log: false,
+            tables: vec![],
+            next_table: 0,
+            len: 0,
             path,
             magic,
             conf,
+            cipher,
+            fs,
         }
     }
 
+    /// Loads the keys tree at `path`, which is empty if missing.
+    ///
+    /// A keys file of format version 0 found at `path` is migrated to a keys
+    /// tree first.
     pub fn from_path<P: AsRef<Path>>(
+        fs: Fs,
         path: P,
         conf: DatabaseOptions,
+        cipher: Option<Cipher>,
     ) -> GhalaDbResult<Keys> {
-        let keys = if path.as_ref().exists() {
-            let mut rdr =
-                BufReader::new(OpenOptions::new().read(true).open(path.as_ref())?);
-            let mut buf = vec![];
-            rdr.read_to_end(&mut buf)?;
-            Dec::deser_raw(&buf)?
+        let mut keys = Keys::new(fs, path.as_ref().to_path_buf(), conf, cipher);
+        let migrated = keys.migrate_path();
+        if keys.fs.exists(&keys.path) && !keys.fs.is_dir(&keys.path) {
+            t!("Keys::migrate", keys.migrate())?;
+        } else if !keys.fs.exists(&keys.path) && keys.fs.is_dir(&migrated) {
+            // the legacy keys file was removed but its migration not moved
+            keys.fs.rename(&migrated, &keys.path)?;
+            sync_parent(&*keys.fs, &keys.path)?;
+        }
+        if !keys.fs.is_dir(&keys.path) {
+            return Ok(keys);
+        }
+        let manifest_path = keys.path.join(MANIFEST_FILE);
+        let manifest: Manifest = if keys.fs.exists(&manifest_path) {
+            let bytes = keys.fs.read(&manifest_path)?;
+            Dec::deser_raw(&crypto::open(keys.cipher.as_ref(), &bytes)?)?
         } else {
-            Keys::new(path.as_ref().to_path_buf(), conf)
+            Manifest::default()
         };
+        if manifest.version > FORMAT_VERSION {
+            return Err(GhalaDbError::UnsupportedFormat(manifest.version));
+        }
+        for num in &manifest.tables {
+            let path = keys.table_path(*num);
+            let table = SsTable::open(&*keys.fs, &path, keys.cipher.clone())?;
+            keys.tables.push(table);
+        }
+        keys.next_table = manifest.next_table;
+        keys.len = manifest.len as usize;
+        keys.remove_orphans(&manifest)?;
+        keys.clear_bulk()?;
+        keys.replay()?;
         Ok(keys)
     }
 
-    pub fn exists(&self, key: KeyRef) -> bool {
-        self.map.contains_key(key)
+    pub fn exists(&mut self, key: KeyRef) -> GhalaDbResult<bool> {
+        Ok(self
+            .lookup(key)?
+            .is_some_and(|e| !e.is_expired(now_millis())))
     }
 
     pub fn delete(&mut self, key: KeyRef) -> GhalaDbResult<()> {
         trace!("Keys::delete");
-        self.map.remove(key);
-        Ok(())
+        self.remove(key)
     }
 
-    pub fn get(&mut self, key: KeyRef) -> Option<DataPtr> {
+    pub fn get(&mut self, key: KeyRef) -> GhalaDbResult<Option<KeyEntry>> {
         trace!("Keys::get");
-        self.map.get(key).copied()
+        let Some(entry) = self.lookup(key)? else {
+            return Ok(None);
+        };
+        if entry.is_expired(now_millis()) {
+            self.remove(key)?;
+            Ok(None)
+        } else {
+            Ok(Some(entry))
+        }
     }
 
-    pub fn put(&mut self, k: Bytes, v: DataPtr) -> GhalaDbResult<()> {
+    pub fn put(&mut self, k: Bytes, v: KeyEntry) -> GhalaDbResult<()> {
         trace!("Keys::put");
-        self.map.insert(k, v);
-        let elapsed = Self::time()? - self.magic;
+        self.insert(k, v)
+    }
 
-        if elapsed > (self.conf.keys_sync_interval * 10u128.pow(9)) {
-            self.sync()?;
+    /// Applies a set of insertions (`Some`) and deletions (`None`).
+    pub fn apply(
+        &mut self,
+        updates: Vec<(Bytes, Option<KeyEntry>)>,
+    ) -> GhalaDbResult<()> {
+        trace!("Keys::apply");
+        for (k, v) in updates {
+            match v {
+                Some(v) => self.insert(k, v)?,
+                None => self.remove(&k)?,
+            };
         }
-        self.magic = Self::time()?;
         Ok(())
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, &DataPtr)> {
-        self.map.iter()
+    /// Approximate number of keys in the tree, expired ones included.
+    ///
+    /// Writes keep the count using the memtable and the SSTables' bloom
+    /// filters only, so that they do not read the SSTables. Bloom filter false
+    /// positives and keys deleted once flushed to an SSTable throw the count
+    /// off until the SSTables are compacted, which recounts the keys.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// An iterator visiting all live keys and their entries in key order.
+    pub fn iter(
+        &self,
+    ) -> GhalaDbResult<impl Iterator<Item = GhalaDbResult<(Bytes, KeyEntry)>> + '_>
+    {
+        let now = now_millis();
+        let records = self.records()?;
+        Ok(records.filter_map(move |record| match record {
+            Ok((k, Some(e))) if !e.is_expired(now) => Some(Ok((k, e))),
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        }))
+    }
+
+    /// Checks if the memtable is full or the sync interval has elapsed, in
+    /// which case [Keys::maintain] persists changes.
+    pub fn maintenance_due(&self) -> GhalaDbResult<bool> {
+        Ok(self.mem_sz >= self.conf.keys_memtable_size || self.sync_due()?)
     }
 
-    // TODO: implement partial sync to only update changes instead of entire table
-    pub fn sync(&self) -> GhalaDbResult<()> {
+    /// Checkpoints the memtable if it is full, and syncs changes if the sync
+    /// interval has elapsed.
+    ///
+    /// The data entries the changed keys point to must be durable beforehand.
+    pub fn maintain(&mut self) -> GhalaDbResult<()> {
+        if self.mem_sz >= self.conf.keys_memtable_size {
+            t!("Keys::flush", self.flush())?;
+        }
+        if self.sync_due()? {
+            self.sync()?;
+            self.magic = Self::time()?;
+        }
+        Ok(())
+    }
+
+    /// Appends the keys changed since the last sync to the change log, and
+    /// makes it durable.
+    pub fn sync(&mut self) -> GhalaDbResult<()> {
         trace!("Keys::sync");
-        let mut wtr = BufWriter::new(
-            OpenOptions::new()
-                .write(true)
-                .create(true)
-                .truncate(true)
-                .open(&self.path)?,
+        if self.dirty.is_empty() {
+            return Ok(());
+        }
+        if self.torn_log {
+            return t!("Keys::flush", self.flush());
+        }
+        let records: Vec<Record> = self
+            .dirty
+            .iter()
+            .map(|k| {
+                let entry = self.mem.get(k).cloned().flatten();
+                (k.clone(), entry)
+            })
+            .collect();
+        let frame = crypto::seal(self.cipher.as_ref(), Dec::ser_raw(&records)?)?;
+        if let Err(e) = self.append_frame(&frame) {
+            // the frame may be partially written
+            self.log = None;
+            self.torn_log = true;
+            return Err(e);
+        }
+        self.dirty.clear();
+        trace!("Keys::sync changes: {}", records.len());
+        Ok(())
+    }
+
+    fn append_frame(&mut self, frame: &[u8]) -> GhalaDbResult<()> {
+        let log = match self.log {
+            Some(ref mut log) => log,
+            None => {
+                self.fs.create_dir_all(&self.path)?;
+                let file = self
+                    .fs
+                    .open(&self.log_path(self.next_table), OpenMode::Append)?;
+                self.log.insert(BufWriter::new(FileWriter(file)))
+            }
+        };
+        log.write_all(&(frame.len() as u32).to_le_bytes())?;
+        log.write_all(frame)?;
+        log.flush()?;
+        log.get_ref().0.sync()?;
+        Ok(())
+    }
+
+    /// Writes a sorted run of a bulk load to an SSTable kept out of the tree
+    /// until the load is ingested.
+    pub fn write_run(
+        &self,
+        num: usize,
+        run: &BTreeMap<Bytes, Option<KeyEntry>>,
+    ) -> GhalaDbResult<PathBuf> {
+        let dir = self.path.join(BULK_DIR);
+        self.fs.create_dir_all(&dir)?;
+        let path = dir.join(format!("{num}.{TABLE_EXT}"));
+        let records = run.iter().map(|(k, e)| Ok((k.clone(), e.clone())));
+        let cipher = self.cipher.clone();
+        SsTable::create(&*self.fs, &path, records, run.len(), &self.conf, cipher)?;
+        Ok(path)
+    }
+
+    /// Adds the runs of a bulk load to the tree, oldest first.
+    ///
+    /// The memtable is flushed beforehand, so that the runs shadow older
+    /// changes. The runs become SSTables of the tree with a single manifest
+    /// update, runs left behind by a crash are removed on load.
+    pub fn ingest(&mut self, runs: Vec<PathBuf>) -> GhalaDbResult<()> {
+        trace!("Keys::ingest runs: {}", runs.len());
+        if !self.mem.is_empty() {
+            t!("Keys::flush", self.flush())?;
+        }
+        let mut tables = vec![];
+        for run in runs {
+            let path = self.table_path(self.next_table);
+            self.fs.rename(&run, &path)?;
+            tables.push(SsTable::open(&*self.fs, &path, self.cipher.clone())?);
+            self.next_table += 1;
+        }
+        // the tables are durable before the manifest lists them
+        self.fs.sync_dir(&self.path)?;
+        // runs hold no tombstones, so every merged record is a live key
+        let sources = tables
+            .iter()
+            .rev()
+            .map(|t| Box::new(t.iter()) as RecordIter)
+            .collect();
+        let mut added = 0;
+        for record in MergeIter::new(sources) {
+            let (k, _) = record?;
+            if !self.maybe_flushed(&k)? {
+                added += 1;
+            }
+        }
+        self.tables.extend(tables);
+        self.len += added;
+        self.dump_manifest()?;
+        self.clear_bulk()?;
+        if self.tables.len() > self.conf.keys_max_tables {
+            t!("Keys::compact", self.compact())?;
+        }
+        Ok(())
+    }
+
+    /// Removes the runs of a bulk load that was not ingested.
+    pub fn clear_bulk(&self) -> GhalaDbResult<()> {
+        let dir = self.path.join(BULK_DIR);
+        if self.fs.exists(&dir) {
+            self.fs.remove_dir_all(&dir)?;
+        }
+        Ok(())
+    }
+
+    /// Syncs the tree and lists its files, relative to `base`.
+    pub fn live_files(&mut self, base: &Path) -> GhalaDbResult<Vec<LiveFile>> {
+        self.sync()?;
+        let mut files = vec![];
+        let fs = &*self.fs;
+        if !fs.is_dir(&self.path) {
+            return Ok(files);
+        }
+        for table in &self.tables {
+            files.push(LiveFile::from_path(
+                fs,
+                base,
+                table.path(),
+                FileKind::Immutable,
+            )?);
+        }
+        for path in [
+            self.path.join(MANIFEST_FILE),
+            self.log_path(self.next_table),
+        ] {
+            if fs.exists(&path) {
+                files.push(LiveFile::from_path(fs, base, &path, FileKind::Mutable)?);
+            }
+        }
+        Ok(files)
+    }
+
+    /// Merges the memtable and the SSTables records, tombstones included.
+    fn records(&self) -> GhalaDbResult<MergeIter<'_>> {
+        let mem = self.mem.iter().map(|(k, e)| Ok((k.clone(), e.clone())));
+        let mut sources: Vec<RecordIter> = vec![Box::new(mem)];
+        for table in self.tables.iter().rev() {
+            sources.push(Box::new(table.iter()));
+        }
+        Ok(MergeIter::new(sources))
+    }
+
+    /// Looks up a key's entry, expired or not.
+    fn lookup(&mut self, key: KeyRef) -> GhalaDbResult<Option<KeyEntry>> {
+        if let Some(entry) = self.mem.get(key) {
+            return Ok(entry.clone());
+        }
+        for table in self.tables.iter().rev() {
+            if let Some(entry) = table.get(key)? {
+                return Ok(entry);
+            }
+        }
+        Ok(None)
+    }
+
+    fn insert(&mut self, k: Bytes, v: KeyEntry) -> GhalaDbResult<()> {
+        if k.len() > MAX_KEY_SZ {
+            return Err(GhalaDbError::KeyTooLarge(k.len()));
+        }
+        if !self.maybe_live(&k)? {
+            self.len += 1;
+        }
+        self.write(k, Some(v));
+        Ok(())
+    }
+
+    fn remove(&mut self, k: KeyRef) -> GhalaDbResult<()> {
+        if self.maybe_live(k)? {
+            self.len = self.len.saturating_sub(1);
+            self.write(k.to_vec(), None);
+        }
+        Ok(())
+    }
+
+    /// Checks if a key may be live, to keep the count of keys. Keys missing
+    /// from the memtable are only looked up in the SSTables' bloom filters.
+    fn maybe_live(&self, key: KeyRef) -> GhalaDbResult<bool> {
+        match self.mem.get(key) {
+            Some(entry) => Ok(entry.is_some()),
+            None => self.maybe_flushed(key),
+        }
+    }
+
+    /// Checks if an SSTable may hold a key, using their bloom filters.
+    fn maybe_flushed(&self, key: KeyRef) -> GhalaDbResult<bool> {
+        for table in &self.tables {
+            if table.may_contain(key)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn write(&mut self, k: Bytes, v: Option<KeyEntry>) {
+        let record = (k, v);
+        self.mem_sz += SsTable::record_sz(&record);
+        self.dirty.insert(record.0.clone());
+        self.mem.insert(record.0, record.1);
+    }
+
+    /// Replays the change log of the memtable.
+    ///
+    /// A frame that was only partially written, when the store crashed during
+    /// a sync, ends the log. Such a log is not appended to anymore.
+    fn replay(&mut self) -> GhalaDbResult<()> {
+        let path = self.log_path(self.next_table);
+        if !self.fs.exists(&path) {
+            return Ok(());
+        }
+        let buf = self.fs.read(&path)?;
+        let mut frames = &buf[..];
+        while frames.len() >= FRAME_LEN_SZ {
+            let (len, rest) = frames.split_at(FRAME_LEN_SZ);
+            let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+            if rest.len() < len {
+                break;
+            }
+            let (frame, rest) = rest.split_at(len);
+            let records: Vec<Record> =
+                Dec::deser_raw(&crypto::open(self.cipher.as_ref(), frame)?)?;
+            for (k, v) in records {
+                match v {
+                    Some(v) => self.insert(k, v)?,
+                    None => self.remove(&k)?,
+                }
+            }
+            frames = rest;
+        }
+        if !frames.is_empty() {
+            warn!("Keys::replay torn frame in: {}", path.display());
+            self.torn_log = true;
+        }
+        // the replayed changes are already logged
+        self.dirty.clear();
+        debug!(
+            "Keys::replay path: {} keys: {}",
+            path.display(),
+            self.mem.len()
+        );
+        Ok(())
+    }
+
+    fn sync_due(&self) -> GhalaDbResult<bool> {
+        let elapsed = Self::time()? - self.magic;
+        Ok(!self.dirty.is_empty()
+            && elapsed > (self.conf.keys_sync_interval * 10u128.pow(9)))
+    }
+
+    /// Checkpoints the memtable to a new SSTable and deletes its change log,
+    /// compacting the SSTables if there are too many of them.
+    fn flush(&mut self) -> GhalaDbResult<()> {
+        self.fs.create_dir_all(&self.path)?;
+        let path = self.table_path(self.next_table);
+        let records = self.mem.iter().map(|(k, e)| Ok((k.clone(), e.clone())));
+        let table = SsTable::create(
+            &*self.fs,
+            &path,
+            records,
+            self.mem.len(),
+            &self.conf,
+            self.cipher.clone(),
+        )?;
+        let log_path = self.log_path(self.next_table);
+        self.next_table += 1;
+        self.tables.push(table);
+        self.dump_manifest()?;
+        self.log = None;
+        self.torn_log = false;
+        if self.fs.exists(&log_path) {
+            self.fs.remove(&log_path)?;
+        }
+        self.mem.clear();
+        self.mem_sz = 0;
+        self.dirty.clear();
+        if self.tables.len() > self.conf.keys_max_tables {
+            t!("Keys::compact", self.compact())?;
+        }
+        Ok(())
+    }
+
+    /// Merges all SSTables into a single one, dropping tombstones and expired
+    /// keys.
+    fn compact(&mut self) -> GhalaDbResult<()> {
+        debug!("Keys::compact tables: {}", self.tables.len());
+        let now = now_millis();
+        let path = self.table_path(self.next_table);
+        let expected = self.tables.iter().map(|t| t.len()).sum();
+        // the memtable is empty, so the merged records are the SSTables ones
+        let records = self.records()?.filter(|record| match record {
+            Ok((_, Some(e))) => !e.is_expired(now),
+            Ok((_, None)) => false,
+            Err(_) => true,
+        });
+        let table = SsTable::create(
+            &*self.fs,
+            &path,
+            records,
+            expected,
+            &self.conf,
+            self.cipher.clone(),
+        )?;
+        self.next_table += 1;
+        self.len = table.len();
+        let old = std::mem::replace(&mut self.tables, vec![table]);
+        self.dump_manifest()?;
+        for table in old {
+            self.fs.remove(table.path())?;
+        }
+        Ok(())
+    }
+
+    /// Migrates a keys file of format version 0 to a keys tree.
+    ///
+    /// The tree is built next to the keys file, which is only replaced once
+    /// the tree is durable. A migration interrupted before that point is
+    /// started over on load.
+    fn migrate(&mut self) -> GhalaDbResult<()> {
+        debug!("Keys::migrate path: {}", self.path.display());
+        let legacy: LegacyKeys = Dec::deser_raw(&self.fs.read(&self.path)?)?;
+        let dest = self.migrate_path();
+        if self.fs.exists(&dest) {
+            self.fs.remove_dir_all(&dest)?;
+        }
+        let mut keys = Keys::new(
+            self.fs.clone(),
+            dest.clone(),
+            self.conf,
+            self.cipher.clone(),
         );
-        let bytes = Dec::ser_raw(&self)?;
-        wtr.write_all(&bytes)?;
+        keys.mem = legacy
+            .map
+            .into_iter()
+            .map(|(k, dp)| (k, Some(KeyEntry::new(dp, None))))
+            .collect();
+        keys.len = keys.mem.len();
+        t!("Keys::flush", keys.flush())?;
+        self.fs.remove(&self.path)?;
+        self.fs.rename(&dest, &self.path)?;
+        sync_parent(&*self.fs, &self.path)?;
+        Ok(())
+    }
+
+    fn migrate_path(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{MIGRATE_EXT}"));
+        PathBuf::from(path)
+    }
+
+    fn dump_manifest(&self) -> GhalaDbResult<()> {
+        let manifest = Manifest {
+            version: FORMAT_VERSION,
+            tables: self
+                .tables
+                .iter()
+                .filter_map(|t| Self::table_num(t.path()))
+                .collect(),
+            next_table: self.next_table,
+            len: self.len as u64,
+        };
+        let bytes = crypto::seal(self.cipher.as_ref(), Dec::ser_raw(&manifest)?)?;
+        write_atomic(&*self.fs, &self.path.join(MANIFEST_FILE), &bytes)?;
+        Ok(())
+    }
+
+    /// Removes SSTables and change logs left behind by an interrupted flush
+    /// or compaction.
+    fn remove_orphans(&self, manifest: &Manifest) -> GhalaDbResult<()> {
+        for path in self.fs.list(&self.path)? {
+            let orphan = match Self::table_num(&path) {
+                Some(num) => !manifest.tables.contains(&num),
+                None => Self::file_num(&path, LOG_EXT)
+                    .is_some_and(|num| num < manifest.next_table),
+            };
+            if orphan {
+                debug!("Keys::remove_orphans path: {}", path.display());
+                self.fs.remove(&path)?;
+            }
+        }
         Ok(())
     }
 
+    fn table_path(&self, num: u64) -> PathBuf {
+        self.path.join(format!("{num}.{TABLE_EXT}"))
+    }
+
+    fn log_path(&self, num: u64) -> PathBuf {
+        self.path.join(format!("{num}.{LOG_EXT}"))
+    }
+
+    fn table_num(path: &Path) -> Option<u64> {
+        Self::file_num(path, TABLE_EXT)
+    }
+
+    fn file_num(path: &Path, ext: &str) -> Option<u64> {
+        if path.extension()? != ext {
+            return None;
+        }
+        path.file_stem()?.to_str()?.parse().ok()
+    }
+
     fn time() -> GhalaDbResult<u128> {
         Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos())
     }
@@ -112,3 +670,195 @@ impl Drop for Keys {
         t!("Keys::sync", self.sync()).ok();
     }
 }
+
+type RecordIter<'a> = Box<dyn Iterator<Item = GhalaDbResult<Record>> + 'a>;
+
+/// Merges sorted record iterators, given newest first.
+///
+/// When several iterators hold the same key, the record of the newest one is
+/// yielded and the others are skipped.
+struct MergeIter<'a> {
+    sources: Vec<Peekable<RecordIter<'a>>>,
+}
+
+impl<'a> MergeIter<'a> {
+    fn new(sources: Vec<RecordIter<'a>>) -> MergeIter<'a> {
+        let sources = sources.into_iter().map(Iterator::peekable).collect();
+        MergeIter { sources }
+    }
+}
+
+impl Iterator for MergeIter<'_> {
+    type Item = GhalaDbResult<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut min: Option<(usize, &Bytes)> = None;
+        let mut failed = None;
+        for (idx, source) in self.sources.iter_mut().enumerate() {
+            match source.peek() {
+                Some(Err(_)) => {
+                    failed = Some(idx);
+                    break;
+                }
+                Some(Ok((k, _))) if min.is_none_or(|(_, m)| k < m) => {
+                    min = Some((idx, k));
+                }
+                _ => {}
+            }
+        }
+        if let Some(idx) = failed {
+            return self.sources[idx].next();
+        }
+        let idx = min?.0;
+        let record = self.sources[idx].next()?;
+        if let Ok((key, _)) = &record {
+            for source in &mut self.sources[idx + 1..] {
+                if matches!(source.peek(), Some(Ok((k, _))) if k == key) {
+                    source.next();
+                }
+            }
+        }
+        Some(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{core::DataPtr, fs::InMemoryFs};
+    use std::sync::Arc;
+
+    fn entry(n: u64) -> KeyEntry {
+        KeyEntry::new(DataPtr::new(0, n, 0, false), None)
+    }
+
+    #[test]
+    fn lsm_keys() -> GhalaDbResult<()> {
+        let fs: Fs = Arc::new(InMemoryFs::new());
+        let path = PathBuf::from("keys");
+        let conf = DatabaseOptions::builder()
+            .keys_memtable_size(1024)
+            .keys_block_size(128)
+            .keys_max_tables(3)
+            .build();
+        let mut keys = Keys::from_path(fs.clone(), &path, conf, None)?;
+        for i in 0..500u64 {
+            keys.put(i.to_be_bytes().to_vec(), entry(i))?;
+            keys.maintain()?;
+        }
+        for i in (0..500u64).step_by(2) {
+            keys.delete(&i.to_be_bytes())?;
+            keys.maintain()?;
+        }
+        for i in (1..500u64).step_by(4) {
+            keys.put(i.to_be_bytes().to_vec(), entry(i + 1000))?;
+            keys.maintain()?;
+        }
+        assert!(!keys.tables.is_empty());
+        assert_eq!(keys.len(), 250);
+        drop(keys);
+
+        let mut keys = Keys::from_path(fs.clone(), &path, conf, None)?;
+        assert_eq!(keys.len(), 250);
+        for i in 0..500u64 {
+            let expected = match i {
+                i if i % 2 == 0 => None,
+                i if i % 4 == 1 => Some(entry(i + 1000)),
+                i => Some(entry(i)),
+            };
+            assert_eq!(keys.get(&i.to_be_bytes())?, expected);
+        }
+        let scanned = keys.iter()?.collect::<GhalaDbResult<Vec<_>>>()?;
+        assert_eq!(scanned.len(), 250);
+        assert!(scanned.windows(2).all(|w| w[0].0 < w[1].0));
+        Ok(())
+    }
+
+    #[test]
+    fn incremental_sync() -> GhalaDbResult<()> {
+        let fs: Fs = Arc::new(InMemoryFs::new());
+        let path = PathBuf::from("keys");
+        let conf = DatabaseOptions::builder().keys_sync_interval(1000).build();
+        let mut keys = Keys::from_path(fs.clone(), &path, conf, None)?;
+        for i in 0..1000u64 {
+            keys.put(i.to_be_bytes().to_vec(), entry(i))?;
+        }
+        keys.sync()?;
+        let log_path = keys.log_path(keys.next_table);
+        let full_sz = fs.size(&log_path)?;
+        keys.put(7u64.to_be_bytes().to_vec(), entry(7000))?;
+        keys.delete(&8u64.to_be_bytes())?;
+        keys.sync()?;
+        let delta_sz = fs.size(&log_path)? - full_sz;
+        assert!(
+            delta_sz * 100 < full_sz,
+            "delta: {delta_sz} full: {full_sz}"
+        );
+        assert!(keys.tables.is_empty());
+        drop(keys);
+
+        let mut keys = Keys::from_path(fs.clone(), &path, conf, None)?;
+        assert_eq!(keys.len(), 999);
+        assert_eq!(keys.get(&7u64.to_be_bytes())?, Some(entry(7000)));
+        assert_eq!(keys.get(&8u64.to_be_bytes())?, None);
+        assert_eq!(keys.get(&9u64.to_be_bytes())?, Some(entry(9)));
+        Ok(())
+    }
+
+    #[test]
+    fn torn_log() -> GhalaDbResult<()> {
+        let fs: Fs = Arc::new(InMemoryFs::new());
+        let path = PathBuf::from("keys");
+        let conf = DatabaseOptions::builder().build();
+        let mut keys = Keys::from_path(fs.clone(), &path, conf, None)?;
+        keys.put(b"a".to_vec(), entry(1))?;
+        keys.sync()?;
+        let log_path = keys.log_path(keys.next_table);
+        drop(keys);
+        // a frame cut short by a crash
+        let log = fs.open(&log_path, OpenMode::Append)?;
+        log.append(&64u32.to_le_bytes())?;
+        log.append(b"torn")?;
+
+        let mut keys = Keys::from_path(fs.clone(), &path, conf, None)?;
+        assert_eq!(keys.get(b"a")?, Some(entry(1)));
+        keys.put(b"b".to_vec(), entry(2))?;
+        keys.sync()?;
+        assert!(!fs.exists(&log_path));
+        drop(keys);
+
+        let mut keys = Keys::from_path(fs.clone(), &path, conf, None)?;
+        assert_eq!(keys.get(b"a")?, Some(entry(1)));
+        assert_eq!(keys.get(b"b")?, Some(entry(2)));
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_keys() -> GhalaDbResult<()> {
+        let fs: Fs = Arc::new(InMemoryFs::new());
+        let path = PathBuf::from("keys");
+        let conf = DatabaseOptions::builder().build();
+        let map: BTreeMap<Bytes, DataPtr> = (0..100u64)
+            .map(|i| (i.to_be_bytes().to_vec(), DataPtr::new(0, i, 0, false)))
+            .collect();
+        // format version 0 keys file: the map, its path and a sync timestamp
+        let bytes = Dec::ser_raw(&(&map, "keys", 0u128))?;
+        fs.write(&path, &bytes)?;
+        // leftovers of an interrupted migration
+        fs.create_dir_all(&path.with_extension(MIGRATE_EXT))?;
+        fs.write(&path.with_extension(MIGRATE_EXT).join("0.sst"), b"torn")?;
+
+        let mut keys = Keys::from_path(fs.clone(), &path, conf, None)?;
+        assert!(fs.is_dir(&path));
+        assert!(!fs.exists(&path.with_extension(MIGRATE_EXT)));
+        assert_eq!(keys.len(), 100);
+        assert_eq!(keys.get(&7u64.to_be_bytes())?, Some(entry(7)));
+        drop(keys);
+
+        let mut keys = Keys::from_path(fs.clone(), &path, conf, None)?;
+        assert_eq!(keys.len(), 100);
+        assert_eq!(keys.iter()?.count(), 100);
+        assert_eq!(keys.get(&99u64.to_be_bytes())?, Some(entry(99)));
+        Ok(())
+    }
+}
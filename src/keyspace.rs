@@ -0,0 +1,507 @@
+//! GhalaDb's keyspaces module.
+//!
+//! A keyspace is a named key map within a data store. All keyspaces share the
+//! data store's values logs, garbage collector and directory, but each has its
+//! own keys table, key and value types, and options.
+use crate::{
+    config::DatabaseOptions,
+    core::{now_millis, Bytes, FileKind, LiveFile},
+    crypto::{self, Cipher},
+    dec::Dec,
+    error::{GhalaDbError, GhalaDbResult},
+    fs::Fs,
+    keys::Keys,
+    sstable::Record,
+    utils::{sync_parent, write_atomic},
+};
+use bincode::{Decode, Encode};
+use std::{
+    borrow::Borrow,
+    collections::BTreeMap,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use typed_builder::TypedBuilder;
+
+/// Identifies a keyspace within a data store.
+pub(crate) type KeyspaceId = u32;
+/// The id of the data store's default keyspace.
+pub(crate) const DEFAULT_KEYSPACE_ID: KeyspaceId = 0;
+/// The name of the default keyspace, which is used by the [GhalaDb](crate::GhalaDb)
+/// methods.
+pub const DEFAULT_KEYSPACE: &str = "default";
+const KEYSPACES_FILE: &str = "keyspaces";
+const KEYS_FILE: &str = "keys";
+const BATCH_FILE: &str = "batch";
+
+/// Updates of a batch spanning keyspaces, per keyspace.
+pub(crate) type BatchUpdates = BTreeMap<KeyspaceId, Vec<Record>>;
+
+/// Keyspace Configuration
+#[derive(Debug, Copy, Clone, TypedBuilder)]
+pub struct KeyspaceOptions {
+    /// enable data compression. Data is only compressed if compression is
+    /// also enabled for the data store.
+    #[builder(default = true)]
+    pub compress: bool,
+    /// time-to-live of entries inserted without one
+    #[builder(default, setter(strip_option))]
+    pub default_ttl: Option<Duration>,
+}
+
+impl KeyspaceOptions {
+    /// Expiry time of an entry inserted now with the default time-to-live.
+    pub(crate) fn default_expiry(&self) -> Option<u64> {
+        self.default_ttl.map(expiry)
+    }
+}
+
+impl Default for KeyspaceOptions {
+    fn default() -> Self {
+        KeyspaceOptions::builder().build()
+    }
+}
+
+/// Expiry time of an entry inserted now that lives for `ttl`.
+pub(crate) fn expiry(ttl: Duration) -> u64 {
+    now_millis().saturating_add(ttl.as_millis() as u64)
+}
+
+/// Keyspaces manager.
+///
+/// Maps keyspace names to ids, and ids to keys tables. The name to id mapping
+/// of named keyspaces is persisted in the `keyspaces` file, while the keys
+/// table of a keyspace is persisted in the `keys.<id>` directory (`keys` for
+/// the default keyspace).
+///
+/// A batch of updates spanning keyspaces is committed by writing it to the
+/// `batch` file before applying it, see [Keyspaces::apply_batch].
+pub(crate) struct Keyspaces {
+    base_path: PathBuf,
+    ids: BTreeMap<String, KeyspaceId>,
+    keys: BTreeMap<KeyspaceId, Keys>,
+    opts: BTreeMap<KeyspaceId, KeyspaceOptions>,
+    conf: DatabaseOptions,
+    cipher: Option<Cipher>,
+    fs: Fs,
+}
+
+impl Keyspaces {
+    pub fn new(
+        fs: Fs,
+        path: &Path,
+        conf: DatabaseOptions,
+        cipher: Option<Cipher>,
+    ) -> GhalaDbResult<Keyspaces> {
+        let base_path = path.to_path_buf();
+        let ids_path = base_path.join(KEYSPACES_FILE);
+        let mut ids: BTreeMap<String, KeyspaceId> = if fs.exists(&ids_path) {
+            let bytes = fs.read(&ids_path)?;
+            Dec::deser_raw(&crypto::open(cipher.as_ref(), &bytes)?)?
+        } else {
+            BTreeMap::new()
+        };
+        ids.insert(DEFAULT_KEYSPACE.to_owned(), DEFAULT_KEYSPACE_ID);
+        let mut keys = BTreeMap::new();
+        for id in ids.values() {
+            let path = base_path.join(Self::keys_file(*id));
+            let id_keys = Keys::from_path(fs.clone(), path, conf, cipher.clone())?;
+            keys.insert(*id, id_keys);
+        }
+        let mut keyspaces = Keyspaces {
+            base_path,
+            ids,
+            keys,
+            opts: BTreeMap::new(),
+            conf,
+            cipher,
+            fs,
+        };
+        let batch_path = keyspaces.base_path.join(BATCH_FILE);
+        if keyspaces.fs.exists(&batch_path) {
+            let bytes = keyspaces.fs.read(&batch_path)?;
+            let cipher = keyspaces.cipher.as_ref();
+            let updates: BatchUpdates =
+                Dec::deser_raw(&crypto::open(cipher, &bytes)?)?;
+            debug!(
+                "Keyspaces::new replaying batch keyspaces: {}",
+                updates.len()
+            );
+            keyspaces.commit_batch(updates)?;
+        }
+        Ok(keyspaces)
+    }
+
+    /// Applies a batch of updates spanning keyspaces atomically.
+    ///
+    /// The updates are written to the `batch` file, which commits the batch,
+    /// before they are applied and the keys tables they touch are synced.
+    /// The file is removed once they are, so a file found on load holds the
+    /// last batch, which may only be persisted in some keyspaces, and is
+    /// applied again.
+    ///
+    /// The data entries the updates point to must be durable beforehand.
+    pub fn apply_batch(&mut self, updates: BatchUpdates) -> GhalaDbResult<()> {
+        let bytes = crypto::seal(self.cipher.as_ref(), Dec::ser_raw(&updates)?)?;
+        write_atomic(&*self.fs, &self.base_path.join(BATCH_FILE), &bytes)?;
+        self.commit_batch(updates)
+    }
+
+    /// Applies and syncs the updates of a committed batch, and removes its
+    /// `batch` file.
+    fn commit_batch(&mut self, updates: BatchUpdates) -> GhalaDbResult<()> {
+        for (id, updates) in updates {
+            let keys = self.keys(id)?;
+            keys.apply(updates)?;
+            keys.sync()?;
+        }
+        let path = self.base_path.join(BATCH_FILE);
+        self.fs.remove(&path)?;
+        sync_parent(&*self.fs, &path)?;
+        Ok(())
+    }
+
+    /// Returns the id of the named keyspace, creating the keyspace if needed.
+    ///
+    /// The keyspace's name is durable before its keys table is written to.
+    pub fn get_or_create(&mut self, name: &str) -> GhalaDbResult<KeyspaceId> {
+        if let Some(id) = self.ids.get(name) {
+            return Ok(*id);
+        }
+        let id = self.ids.values().max().copied().unwrap_or_default() + 1;
+        debug!("Keyspaces::create name: {name} id: {id}");
+        let path = self.base_path.join(Self::keys_file(id));
+        let keys = Keys::new(self.fs.clone(), path, self.conf, self.cipher.clone());
+        self.ids.insert(name.to_owned(), id);
+        self.keys.insert(id, keys);
+        self.dump_ids()?;
+        Ok(id)
+    }
+
+    /// Moves the keys tables of the data store at `path` to the `dest`
+    /// directory, and returns its keyspaces with empty keys tables. A pending
+    /// batch and an unreadable keyspaces file are moved to `dest` as well.
+    pub fn reset(
+        fs: Fs,
+        path: &Path,
+        conf: DatabaseOptions,
+        cipher: Option<Cipher>,
+        dest: &Path,
+    ) -> GhalaDbResult<Keyspaces> {
+        let prefix = format!("{KEYS_FILE}.");
+        for entry in fs.list(path)? {
+            let Some(name) = entry.file_name() else {
+                continue;
+            };
+            let is_keys = name.to_str().is_some_and(|name| {
+                name == KEYS_FILE || name.starts_with(&prefix) || name == BATCH_FILE
+            });
+            if is_keys {
+                fs.rename(&entry, &dest.join(name))?;
+            }
+        }
+        fs.sync_dir(dest)?;
+        match Self::new(fs.clone(), path, conf, cipher.clone()) {
+            Ok(keyspaces) => Ok(keyspaces),
+            Err(e) => {
+                warn!("Keyspaces::reset unreadable keyspaces: {e}");
+                let name = KEYSPACES_FILE;
+                fs.rename(&path.join(name), &dest.join(name))?;
+                fs.sync_dir(dest)?;
+                Self::new(fs, path, conf, cipher)
+            }
+        }
+    }
+
+    /// Returns the keys table of a keyspace, registering the keyspace as
+    /// `recovered.<id>` if its name is unknown.
+    pub fn recover(&mut self, id: KeyspaceId) -> GhalaDbResult<&mut Keys> {
+        if !self.keys.contains_key(&id) {
+            let name = format!("recovered.{id}");
+            debug!("Keyspaces::recover name: {name} id: {id}");
+            let path = self.base_path.join(Self::keys_file(id));
+            let keys =
+                Keys::new(self.fs.clone(), path, self.conf, self.cipher.clone());
+            self.keys.insert(id, keys);
+            self.ids.insert(name, id);
+            self.dump_ids()?;
+        }
+        self.keys(id)
+    }
+
+    /// Names and ids of the keyspaces.
+    pub fn names(&self) -> Vec<(String, KeyspaceId)> {
+        self.ids
+            .iter()
+            .map(|(name, id)| (name.clone(), *id))
+            .collect()
+    }
+
+    /// Returns the keys table of a keyspace.
+    pub fn keys(&mut self, id: KeyspaceId) -> GhalaDbResult<&mut Keys> {
+        self.keys
+            .get_mut(&id)
+            .ok_or(GhalaDbError::MissingKeyspace(id))
+    }
+
+    /// Returns a shared reference to the keys table of a keyspace.
+    pub fn keys_ref(&self, id: KeyspaceId) -> GhalaDbResult<&Keys> {
+        self.keys.get(&id).ok_or(GhalaDbError::MissingKeyspace(id))
+    }
+
+    /// Returns the keys table of the default keyspace.
+    pub fn default_keys(&mut self) -> &mut Keys {
+        self.keys
+            .get_mut(&DEFAULT_KEYSPACE_ID)
+            .expect("default keyspace always exists")
+    }
+
+    pub fn options(&self, id: KeyspaceId) -> KeyspaceOptions {
+        self.opts.get(&id).copied().unwrap_or_default()
+    }
+
+    pub fn set_options(&mut self, id: KeyspaceId, opts: KeyspaceOptions) {
+        self.opts.insert(id, opts);
+    }
+
+    pub fn sync(&mut self) -> GhalaDbResult<()> {
+        for keys in self.keys.values_mut() {
+            keys.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Syncs the keys tables and lists their files.
+    pub fn live_files(&mut self) -> GhalaDbResult<Vec<LiveFile>> {
+        let mut files = vec![];
+        if self.fs.exists(&self.base_path.join(KEYSPACES_FILE)) {
+            files.push(LiveFile::new(
+                &*self.fs,
+                &self.base_path,
+                KEYSPACES_FILE,
+                FileKind::Mutable,
+            )?);
+        }
+        for keys in self.keys.values_mut() {
+            files.extend(keys.live_files(&self.base_path)?);
+        }
+        Ok(files)
+    }
+
+    fn dump_ids(&self) -> GhalaDbResult<()> {
+        let ids: BTreeMap<&String, &KeyspaceId> = self
+            .ids
+            .iter()
+            .filter(|(_, id)| **id != DEFAULT_KEYSPACE_ID)
+            .collect();
+        let bytes = crypto::seal(self.cipher.as_ref(), Dec::ser_raw(&ids)?)?;
+        write_atomic(&*self.fs, &self.base_path.join(KEYSPACES_FILE), &bytes)?;
+        Ok(())
+    }
+
+    fn keys_file(id: KeyspaceId) -> String {
+        if id == DEFAULT_KEYSPACE_ID {
+            KEYS_FILE.to_owned()
+        } else {
+            format!("{KEYS_FILE}.{id}")
+        }
+    }
+}
+
+/// A fallible iterator over raw key-value pairs.
+pub(crate) type RawIter<'a> =
+    Box<dyn Iterator<Item = GhalaDbResult<(Bytes, Bytes)>> + 'a>;
+
+/// Untyped data store operations on a keyspace.
+pub(crate) trait RawStore {
+    fn exists_raw(&mut self, ks: KeyspaceId, key: &[u8]) -> GhalaDbResult<bool>;
+    fn get_raw(
+        &mut self,
+        ks: KeyspaceId,
+        key: &[u8],
+    ) -> GhalaDbResult<Option<Bytes>>;
+    fn put_raw(
+        &mut self,
+        ks: KeyspaceId,
+        key: Bytes,
+        val: Bytes,
+        expires_at: Option<u64>,
+    ) -> GhalaDbResult<()>;
+    fn delete_raw(&mut self, ks: KeyspaceId, key: &[u8]) -> GhalaDbResult<()>;
+    fn iter_raw(&mut self, ks: KeyspaceId) -> GhalaDbResult<RawIter<'_>>;
+}
+
+/// A handle to a named keyspace of a [GhalaDb](crate::GhalaDb).
+///
+/// Obtained using [GhalaDb::keyspace](crate::GhalaDb::keyspace).
+pub struct Keyspace<'a, K, V> {
+    db: &'a mut dyn RawStore,
+    id: KeyspaceId,
+    opts: KeyspaceOptions,
+    _k: PhantomData<K>,
+    _v: PhantomData<V>,
+}
+
+impl<'a, K, V> Keyspace<'a, K, V>
+where
+    K: Encode + Decode,
+    V: Encode + Decode,
+{
+    pub(crate) fn new(
+        db: &'a mut dyn RawStore,
+        id: KeyspaceId,
+        opts: KeyspaceOptions,
+    ) -> Keyspace<'a, K, V> {
+        Self {
+            db,
+            id,
+            opts,
+            _k: PhantomData,
+            _v: PhantomData,
+        }
+    }
+
+    /// Check if a key is present in the keyspace.
+    pub fn exists<Q>(&mut self, k: &Q) -> GhalaDbResult<bool>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Encode,
+    {
+        trace!("Keyspace::exists");
+        let key = Dec::ser_raw(k)?;
+        self.db.exists_raw(self.id, &key)
+    }
+
+    /// Deletes a key from the keyspace.
+    pub fn delete<Q>(&mut self, k: &Q) -> GhalaDbResult<()>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Encode,
+    {
+        trace!("Keyspace::delete");
+        let key = Dec::ser_raw(k)?;
+        self.db.delete_raw(self.id, &key)
+    }
+
+    /// Returns the value corresponding to the key.
+    pub fn get<Q>(&mut self, k: &Q) -> GhalaDbResult<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Encode,
+    {
+        trace!("Keyspace::get");
+        let key = Dec::ser_raw(k)?;
+        match self.db.get_raw(self.id, &key)? {
+            Some(bytes) => Ok(Some(Dec::deser_raw(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Inserts a key-value pair into the keyspace.
+    ///
+    /// The entry expires after the keyspace's default time-to-live, if any.
+    pub fn put<Q>(&mut self, k: &Q, v: &V) -> GhalaDbResult<()>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Encode,
+    {
+        trace!("Keyspace::put");
+        let key = Dec::ser_raw(k)?;
+        let val = Dec::ser_raw(v)?;
+        self.db
+            .put_raw(self.id, key, val, self.opts.default_expiry())
+    }
+
+    /// Inserts a key-value pair into the keyspace that expires after `ttl`.
+    pub fn put_with_ttl<Q>(
+        &mut self,
+        k: &Q,
+        v: &V,
+        ttl: Duration,
+    ) -> GhalaDbResult<()>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Encode,
+    {
+        trace!("Keyspace::put_with_ttl");
+        let key = Dec::ser_raw(k)?;
+        let val = Dec::ser_raw(v)?;
+        self.db.put_raw(self.id, key, val, Some(expiry(ttl)))
+    }
+
+    /// An iterator visiting all key-value pairs of the keyspace in an ordered
+    /// manner.
+    pub fn iter(
+        &mut self,
+    ) -> GhalaDbResult<impl Iterator<Item = GhalaDbResult<(K, V)>> + '_> {
+        trace!("Keyspace::iter");
+        let iter = self.db.iter_raw(self.id)?;
+        Ok(iter.map(|kv| {
+            let (key, val) = kv?;
+            Ok((Dec::deser_raw(&key)?, Dec::deser_raw(&val)?))
+        }))
+    }
+}
+
+/// A write operation within a [WriteBatch].
+pub(crate) struct BatchOp {
+    pub keyspace: String,
+    pub key: Bytes,
+    /// The value to insert, or `None` for deletes.
+    pub val: Option<Bytes>,
+}
+
+/// A set of writes, possibly spanning several keyspaces, that are applied
+/// atomically using [GhalaDb::write](crate::GhalaDb::write).
+///
+/// Keys and values are encoded when added to the batch, it is up to the caller
+/// to use the key and value types of the target keyspace.
+#[derive(Default)]
+pub struct WriteBatch {
+    pub(crate) ops: Vec<BatchOp>,
+}
+
+impl WriteBatch {
+    /// Creates an empty batch.
+    pub fn new() -> WriteBatch {
+        Self::default()
+    }
+
+    /// Adds the insertion of a key-value pair into the named keyspace.
+    pub fn put<K, V>(&mut self, keyspace: &str, k: &K, v: &V) -> GhalaDbResult<()>
+    where
+        K: ?Sized + Encode,
+        V: ?Sized + Encode,
+    {
+        self.ops.push(BatchOp {
+            keyspace: keyspace.to_owned(),
+            key: Dec::ser_raw(k)?,
+            val: Some(Dec::ser_raw(v)?),
+        });
+        Ok(())
+    }
+
+    /// Adds the deletion of a key from the named keyspace.
+    pub fn delete<K>(&mut self, keyspace: &str, k: &K) -> GhalaDbResult<()>
+    where
+        K: ?Sized + Encode,
+    {
+        self.ops.push(BatchOp {
+            keyspace: keyspace.to_owned(),
+            key: Dec::ser_raw(k)?,
+            val: None,
+        });
+        Ok(())
+    }
+
+    /// Returns the number of writes in the batch.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Check if the batch holds no writes.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
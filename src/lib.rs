@@ -3,13 +3,14 @@ GhalaDb is a key value datastore that implements keys and values separation
 inspired by the [WiscKey](https://pages.cs.wisc.edu/~ll/papers/wisckey.pdf) paper.
 
 GhalaDb implements a SSD-conscious data layout by decoupling the storage of
-keys from values. An in-memory tree stores the keys along with pointers to
+keys from values. An LSM tree stores the keys along with pointers to
 the values, while the values are stored in a separate log file.
 This significantly reduces write amplification during ingestion,
-while facilitating faster data loading.
+while facilitating faster data loading. Tiny values can optionally be
+inlined in the keys table, saving a values log read on lookups.
 
-Since GhalaDb keeps all its keys and data pointers in memory, it is suitable
-for applications that have small-sized keys.
+Only recently written keys and the SSTables' indexes (fence pointers and bloom
+filters) are kept in memory, so the dataset is not limited by the available RAM.
 
 <div class="warning">!! GhalaDb is experimental software and might not be suitable for your use case.</div>
 
@@ -30,19 +31,49 @@ fn main() -> GhalaDbResult<()> {
 #![deny(missing_docs, unused)]
 #[macro_use]
 extern crate log;
+mod backup;
+mod bulk;
+mod cache;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod cli;
 mod config;
 mod core;
+mod crypto;
 mod dec;
 mod error;
+mod export;
+mod fault;
+mod fs;
 mod gc;
 mod ghaladb;
 mod keys;
+mod keyspace;
+mod merge;
+mod prefetch;
+mod repair;
+mod sstable;
+mod stream;
 mod utils;
+mod verify;
 mod vlog;
+#[cfg(feature = "encryption")]
+pub use crate::crypto::{EncryptionKey, KeyId, KeyProvider, KeyRing};
 pub use crate::{
+    backup::{BackupEngine, BackupInfo},
+    bulk::BulkLoader,
+    cache::CacheStats,
     config::DatabaseOptions,
     error::{GhalaDbError, GhalaDbResult},
-    ghaladb::GhalaDb,
+    export::{ImportOptions, PROGRESS_INTERVAL},
+    fault::FaultInjectionFs,
+    fs::{FileMap, FileSystem, FsFile, InMemoryFs, OpenMode, StdFs},
+    ghaladb::{CompareAndSwapError, CompareAndSwapResult, GhalaDb},
+    keyspace::{Keyspace, KeyspaceOptions, WriteBatch, DEFAULT_KEYSPACE},
+    merge::MergeOperator,
+    repair::RepairReport,
+    stream::ValueReader,
+    verify::{VerifyIssue, VerifyReport},
 };
 
 //
@@ -0,0 +1,99 @@
+//! GhalaDb's merge operators module.
+use crate::{
+    core::{Bytes, KeyEntry, ValueRef},
+    dec::Dec,
+    error::{GhalaDbError, GhalaDbResult},
+    utils::t,
+    vlog::VlogsMan,
+};
+use bincode::{Decode, Encode};
+
+/// An associative operator used to combine merge operands with a value.
+///
+/// Operands written using [GhalaDb::merge](crate::GhalaDb::merge) are
+/// appended to the values log and lazily combined with the key's value, in
+/// the order they were written, when the key is read.
+///
+/// Closures of the form `Fn(Option<V>, V) -> V` are merge operators.
+pub trait MergeOperator<V>: Send + Sync {
+    /// Merges an operand into the existing value, if any, and returns the new
+    /// value.
+    fn merge(&self, existing: Option<V>, operand: V) -> V;
+}
+
+impl<V, F> MergeOperator<V> for F
+where
+    F: Fn(Option<V>, V) -> V + Send + Sync,
+{
+    fn merge(&self, existing: Option<V>, operand: V) -> V {
+        self(existing, operand)
+    }
+}
+
+/// Reads the value of a key entry, combining its merge operands if any.
+pub(crate) fn resolve<V>(
+    vlogs_man: &mut VlogsMan,
+    merge_op: Option<&dyn MergeOperator<V>>,
+    entry: &KeyEntry,
+) -> GhalaDbResult<Bytes>
+where
+    V: Encode + Decode,
+{
+    let base = match &entry.val {
+        Some(ValueRef::Inline(val)) => Some(val.clone()),
+        Some(ValueRef::Ptr(dp)) => Some(t!("vlogman::get", vlogs_man.get(dp))?.val),
+        Some(ValueRef::Chunked(manifest)) => {
+            let mut val = Vec::with_capacity(manifest.len as usize);
+            for dp in &manifest.chunks {
+                val.extend(t!("vlogman::get", vlogs_man.get(dp))?.val);
+            }
+            Some(val)
+        }
+        None => None,
+    };
+    if entry.operands.is_empty() {
+        // entries are only written with a value or merge operands
+        return base.ok_or(GhalaDbError::CorruptKeyEntry);
+    }
+    let merge_op = merge_op.ok_or(GhalaDbError::MissingMergeOperator)?;
+    let mut val: Option<V> = base.map(|b| Dec::deser_raw(&b)).transpose()?;
+    for dp in &entry.operands {
+        let operand = t!("vlogman::get", vlogs_man.get(dp))?.val;
+        val = Some(merge_op.merge(val, Dec::deser_raw(&operand)?));
+    }
+    // a non empty operands chain always yields a value
+    Dec::ser_raw(&val.unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::DatabaseOptions,
+        fs::{FileSystem, InMemoryFs},
+    };
+    use std::{path::Path, sync::Arc};
+
+    #[test]
+    fn resolve_entries() -> GhalaDbResult<()> {
+        let fs = InMemoryFs::new();
+        fs.create_dir_all(Path::new("db"))?;
+        let conf = DatabaseOptions::builder().build();
+        let mut vlogs_man =
+            VlogsMan::new(Arc::new(fs), Path::new("db"), conf, None)?;
+        let val = Dec::ser_raw(&1u32)?;
+        let entry = KeyEntry::inline(val.clone(), None);
+        assert_eq!(resolve::<u32>(&mut vlogs_man, None, &entry)?, val);
+
+        let empty = KeyEntry {
+            val: None,
+            operands: vec![],
+            expires_at: None,
+        };
+        assert!(matches!(
+            resolve::<u32>(&mut vlogs_man, None, &empty),
+            Err(GhalaDbError::CorruptKeyEntry)
+        ));
+        Ok(())
+    }
+}
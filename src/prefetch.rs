@@ -0,0 +1,162 @@
+//! GhalaDb's value prefetching module.
+use crate::{
+    core::{Bytes, DataPtr},
+    fs::FsFile,
+};
+use std::{
+    io,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+/// The raw bytes of a prefetched data entry, tagged with its request number.
+pub(crate) type Prefetched = (u64, io::Result<Bytes>);
+
+/// A request to read a data entry's raw bytes.
+struct ReadJob {
+    seq: u64,
+    file: Option<Arc<dyn FsFile>>,
+    dp: DataPtr,
+    reply: Sender<Prefetched>,
+}
+
+/// A pool of threads reading data entries from the values logs.
+///
+/// Reads are issued concurrently, which lets SSDs serve them in parallel.
+/// Workers only read raw bytes, decoding is left to the caller which owns the
+/// vlogs' decoders. Replies are sent to the channel given with each request,
+/// and are dropped if the requester is gone.
+pub(crate) struct ReadPool {
+    jobs: Option<Sender<ReadJob>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ReadPool {
+    pub fn new(threads: usize) -> ReadPool {
+        let (jobs, rx) = mpsc::channel::<ReadJob>();
+        let rx = Arc::new(Mutex::new(rx));
+        let workers = (0..threads.max(1))
+            .map(|_| {
+                let rx = rx.clone();
+                thread::spawn(move || Self::work(rx))
+            })
+            .collect();
+        ReadPool {
+            jobs: Some(jobs),
+            workers,
+        }
+    }
+
+    /// A pool whose workers are gone, which never replies.
+    #[cfg(test)]
+    pub fn stopped() -> ReadPool {
+        ReadPool {
+            jobs: None,
+            workers: vec![],
+        }
+    }
+
+    /// Requests the raw bytes of the data entry at `dp`, read from its vlog's
+    /// `file`. A missing file fails the read.
+    pub fn read(
+        &self,
+        seq: u64,
+        file: Option<Arc<dyn FsFile>>,
+        dp: DataPtr,
+        reply: Sender<Prefetched>,
+    ) {
+        if let Some(ref jobs) = self.jobs {
+            let job = ReadJob {
+                seq,
+                file,
+                dp,
+                reply,
+            };
+            if let Err(e) = jobs.send(job) {
+                // the caller falls back to a synchronous read
+                let job = e.0;
+                job.reply
+                    .send((job.seq, Err(io::Error::other("read pool is down"))))
+                    .ok();
+            }
+        }
+    }
+
+    fn work(rx: Arc<Mutex<Receiver<ReadJob>>>) {
+        loop {
+            let job = match rx.lock() {
+                Ok(rx) => rx.recv(),
+                Err(_) => return,
+            };
+            let Ok(job) = job else {
+                return;
+            };
+            let res = match job.file {
+                Some(ref file) => Self::read_at(&**file, &job.dp),
+                None => Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("vlog not found: {}", job.dp.vlog),
+                )),
+            };
+            job.reply.send((job.seq, res)).ok();
+        }
+    }
+
+    fn read_at(file: &dyn FsFile, dp: &DataPtr) -> io::Result<Bytes> {
+        let mut buf = vec![0u8; dp.len as usize];
+        file.read_exact_at(&mut buf, dp.offset)?;
+        Ok(buf)
+    }
+}
+
+impl Drop for ReadPool {
+    fn drop(&mut self) {
+        // closing the jobs channel stops the workers
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            worker.join().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::{FileSystem, InMemoryFs, OpenMode};
+    use std::path::Path;
+
+    #[test]
+    fn read_pool() -> io::Result<()> {
+        let fs = InMemoryFs::new();
+        let data: Bytes = (0..=255u8).collect();
+        let file = fs.open(Path::new("0.vlog"), OpenMode::Create)?;
+        file.append(&data)?;
+
+        let pool = ReadPool::new(4);
+        let (tx, rx) = mpsc::channel();
+        for i in 0..64u64 {
+            pool.read(
+                i,
+                Some(file.clone()),
+                DataPtr::new(0, i * 4, 4, false),
+                tx.clone(),
+            );
+        }
+        let dp = DataPtr::new(0, 255, 4, false);
+        pool.read(64, Some(file.clone()), dp, tx.clone());
+        pool.read(65, None, DataPtr::new(1, 0, 1, false), tx);
+        let mut replies: Vec<Prefetched> = rx.iter().collect();
+        replies.sort_by_key(|(seq, _)| *seq);
+        assert_eq!(replies.len(), 66);
+        for (seq, res) in &replies[..64] {
+            let off = *seq as usize * 4;
+            assert_eq!(res.as_ref().unwrap(), &data[off..off + 4]);
+        }
+        assert!(replies[64].1.is_err());
+        assert!(replies[65].1.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,341 @@
+//! GhalaDb's repair module.
+use crate::{
+    config::DatabaseOptions,
+    core::{now_millis, Bytes, ChunkManifest, DataPtr, KeyEntry, ValueRef, VlogNum},
+    crypto::Cipher,
+    error::{GhalaDbError, GhalaDbResult},
+    fs::{FileSystem, Fs, StdFs},
+    ghaladb::GhalaDb,
+    keyspace::{KeyspaceId, Keyspaces},
+    utils::t,
+    vlog::{read_vlogs_info, salvage_vlog, write_vlogs_info, DataEntry, EntryKind},
+};
+use bincode::{Decode, Encode};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Directory of a data store holding the data quarantined by repairs.
+const LOST_FOUND_DIR: &str = "lost+found";
+
+/// Report of a repair, returned by [GhalaDb::repair].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    /// Number of vlogs salvaged.
+    pub vlogs: u64,
+    /// Number of records salvaged from the vlogs.
+    pub records: u64,
+    /// Number of unreadable vlog fragments quarantined.
+    pub fragments: u64,
+    /// Number of keys rebuilt, across keyspaces.
+    pub keys: u64,
+    /// Directory the unreadable fragments and the replaced keys tables were
+    /// moved to.
+    pub lost_found: PathBuf,
+}
+
+impl<K, V> GhalaDb<K, V>
+where
+    K: Encode + Decode,
+    V: Encode + Decode,
+{
+    /// Repairs a damaged data store, which must not be open.
+    ///
+    /// The readable records of every `*.vlog` file are salvaged, skipping
+    /// unreadable regions, and the vlogs info is rebuilt from the vlog files
+    /// present. The keys tables are then regenerated from the newest salvaged
+    /// version of every key. Unreadable vlog fragments, named
+    /// `<vlog>.vlog.<offset>`, and the replaced keys tables are moved to a
+    /// new `lost+found/<timestamp>` directory of the data store.
+    ///
+    /// Deletions, expiry times and inlined values are only recorded in the
+    /// keys tables, so deleted keys whose values are still in the vlogs
+    /// reappear, keys no longer expire and inlined values are lost. Streamed
+    /// values are rebuilt from their last run of consecutive chunks.
+    ///
+    /// The options should be those the data store is opened with, as they
+    /// bound the size of the salvaged values and configure the rebuilt keys
+    /// tables.
+    pub fn repair<P: AsRef<Path>>(
+        path: P,
+        options: Option<DatabaseOptions>,
+    ) -> GhalaDbResult<RepairReport> {
+        trace!("GhalaDb::repair path: {}", path.as_ref().display());
+        repair(Arc::new(StdFs), path.as_ref(), options, None)
+    }
+
+    /// Repairs a damaged data store kept in the given [FileSystem], as
+    /// [GhalaDb::repair] does.
+    pub fn repair_with_fs<P: AsRef<Path>>(
+        path: P,
+        options: Option<DatabaseOptions>,
+        fs: Arc<dyn FileSystem>,
+    ) -> GhalaDbResult<RepairReport> {
+        trace!("GhalaDb::repair_with_fs path: {}", path.as_ref().display());
+        repair(fs, path.as_ref(), options, None)
+    }
+
+    /// Repairs a damaged encrypted data store, as [GhalaDb::repair] does.
+    #[cfg(feature = "encryption")]
+    pub fn repair_with_encryption<P: AsRef<Path>>(
+        path: P,
+        options: Option<DatabaseOptions>,
+        provider: std::sync::Arc<dyn crate::KeyProvider>,
+    ) -> GhalaDbResult<RepairReport> {
+        trace!(
+            "GhalaDb::repair_with_encryption path: {}",
+            path.as_ref().display()
+        );
+        let cipher = Some(Cipher::new(provider));
+        repair(Arc::new(StdFs), path.as_ref(), options, cipher)
+    }
+}
+
+fn repair(
+    fs: Fs,
+    path: &Path,
+    options: Option<DatabaseOptions>,
+    cipher: Option<Cipher>,
+) -> GhalaDbResult<RepairReport> {
+    if !fs.is_dir(path) {
+        return Err(GhalaDbError::DbPathNotDirectory(path.to_path_buf()));
+    }
+    let lost_found = path.join(LOST_FOUND_DIR).join(now_millis().to_string());
+    fs.create_dir_all(&lost_found)?;
+    let mut report = RepairReport {
+        lost_found: lost_found.clone(),
+        ..Default::default()
+    };
+
+    let mut vnums: Vec<VlogNum> = vec![];
+    for path in fs.list(path)? {
+        if path.extension().is_some_and(|ext| ext == "vlog") {
+            if let Some(vnum) =
+                path.file_stem().and_then(|s| s.to_str()?.parse().ok())
+            {
+                vnums.push(vnum);
+            }
+        }
+    }
+    vnums.sort_unstable();
+
+    // vlogs of format version 0, if the vlogs info is still readable
+    let mut legacy = match read_vlogs_info(&*fs, path, cipher.as_ref()) {
+        Ok(info) => info.legacy,
+        Err(e) => {
+            warn!("GhalaDb::repair unreadable vlogs info: {e}");
+            vec![]
+        }
+    };
+    let conf = options.unwrap_or_else(|| DatabaseOptions::builder().build());
+    let mut entries: BTreeMap<(KeyspaceId, Bytes), KeyEntry> = BTreeMap::new();
+    // key of the previous record, if it is a chunk
+    let mut chunk_run: Option<(KeyspaceId, Bytes)> = None;
+    for vnum in &vnums {
+        let vlog_path = path.join(format!("{vnum}.vlog"));
+        let is_legacy = legacy.contains(vnum);
+        let visit = |dp: DataPtr, de: DataEntry| {
+            let id = (de.keyspace, de.key);
+            let prev = chunk_run.take();
+            match de.kind {
+                EntryKind::Value => {
+                    entries.insert(id, KeyEntry::new(dp, None));
+                }
+                EntryKind::MergeOperand => {
+                    let entry = entries.entry(id).or_insert_with(|| KeyEntry {
+                        val: None,
+                        operands: vec![],
+                        expires_at: None,
+                    });
+                    entry.operands.push(dp);
+                }
+                EntryKind::Chunk => {
+                    let len = de.val.len();
+                    let entry =
+                        entries.entry(id.clone()).or_insert_with(|| KeyEntry {
+                            val: None,
+                            operands: vec![],
+                            expires_at: None,
+                        });
+                    match &mut entry.val {
+                        Some(ValueRef::Chunked(manifest))
+                            if prev.as_ref() == Some(&id) =>
+                        {
+                            manifest.len += len as u64;
+                            manifest.chunks.push(dp);
+                        }
+                        _ => {
+                            let manifest = ChunkManifest {
+                                chunk_size: len as u32,
+                                len: len as u64,
+                                chunks: vec![dp],
+                            };
+                            entry.val = Some(ValueRef::Chunked(manifest));
+                            entry.operands.clear();
+                        }
+                    }
+                    chunk_run = Some(id);
+                }
+            }
+        };
+        let salvaged = t!(
+            "vlog::salvage_vlog",
+            salvage_vlog(
+                &*fs,
+                &vlog_path,
+                *vnum,
+                cipher.clone(),
+                conf.max_value_size,
+                is_legacy,
+                &lost_found,
+                visit,
+            )
+        )?;
+        report.vlogs += 1;
+        report.records += salvaged.records;
+        report.fragments += salvaged.fragments;
+    }
+    legacy.retain(|vnum| vnums.contains(vnum));
+    t!(
+        "vlog::write_vlogs_info",
+        write_vlogs_info(&*fs, path, vnums, legacy, cipher.clone())
+    )?;
+
+    let mut keyspaces = t!(
+        "Keyspaces::reset",
+        Keyspaces::reset(fs, path, conf, cipher, &lost_found)
+    )?;
+    for ((ks, key), entry) in entries {
+        let keys = keyspaces.recover(ks)?;
+        t!("keys::put", keys.put(key, entry))?;
+        t!("keys::maintain", keys.maintain())?;
+        report.keys += 1;
+    }
+    t!("keyspaces::sync", keyspaces.sync())?;
+    debug!(
+        "GhalaDb::repair vlogs: {} records: {} fragments: {} keys: {}",
+        report.vlogs, report.records, report.fragments, report.keys
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use std::io::{Seek, SeekFrom, Write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn repair_store() -> GhalaDbResult<()> {
+        let tmp_dir = tempdir()?;
+        let opts = DatabaseOptions::builder()
+            .max_vlog_size(4096)
+            .chunk_size(1000)
+            .compact(false)
+            .build();
+        let mut db: GhalaDb<u32, String> = GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        for i in 0..200 {
+            db.put(&i, &format!("old-{i}"))?;
+        }
+        for i in 0..50 {
+            db.put(&i, &format!("new-{i}"))?;
+        }
+        db.put_stream(&500, &[7u8; 2500][..])?;
+        drop(db);
+
+        let path = |name: &str| tmp_dir.path().join(name);
+        let mut vlog = File::options().write(true).open(path("1.vlog"))?;
+        vlog.seek(SeekFrom::Start(100))?;
+        vlog.write_all(&[0xff; 30])?;
+        drop(vlog);
+        fs::write(path("vlog_info"), b"garbage")?;
+        fs::remove_dir_all(path("keys"))?;
+        let db: GhalaDbResult<GhalaDb<u32, String>> =
+            GhalaDb::new(tmp_dir.path(), Some(opts));
+        assert!(db.is_err());
+
+        let report = GhalaDb::<u32, String>::repair(tmp_dir.path(), Some(opts))?;
+        assert!(report.vlogs > 2);
+        assert!(report.fragments >= 1);
+        assert!(fs::read_dir(&report.lost_found)?.any(|e| e
+            .is_ok_and(|e| e.file_name().to_string_lossy().starts_with("1.vlog."))));
+
+        let mut db: GhalaDb<u32, String> = GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        assert!(db.verify()?.is_ok());
+        assert_eq!(db.len() as u64, report.keys);
+        assert!(report.keys > 190);
+        for i in 0..200 {
+            let expected = match i {
+                0..50 => format!("new-{i}"),
+                _ => format!("old-{i}"),
+            };
+            if let Some(val) = db.get(&i)? {
+                assert_eq!(val, expected);
+            }
+        }
+        let mut streamed = vec![];
+        std::io::Read::read_to_end(
+            &mut db.get_reader(&500)?.unwrap(),
+            &mut streamed,
+        )?;
+        assert_eq!(streamed, [7u8; 2500]);
+        Ok(())
+    }
+
+    #[test]
+    fn repair_in_memory() -> GhalaDbResult<()> {
+        let fs = crate::fs::InMemoryFs::new();
+        let opts = DatabaseOptions::builder().max_vlog_size(4096).build();
+        let mut db: GhalaDb<u32, String> =
+            GhalaDb::with_fs("db", Some(opts), Arc::new(fs.clone()))?;
+        for i in 0..100 {
+            db.put(&i, &format!("val-{i}"))?;
+        }
+        drop(db);
+        fs.write(Path::new("db/vlog_info"), b"garbage")?;
+        fs.remove_dir_all(Path::new("db/keys"))?;
+
+        let report = GhalaDb::<u32, String>::repair_with_fs(
+            "db",
+            Some(opts),
+            Arc::new(fs.clone()),
+        )?;
+        assert_eq!(report.keys, 100);
+        assert!(!Path::new("db").exists());
+        let mut db: GhalaDb<u32, String> =
+            GhalaDb::with_fs("db", Some(opts), Arc::new(fs.clone()))?;
+        assert!(db.verify()?.is_ok());
+        for i in 0..100 {
+            assert_eq!(db.get(&i)?, Some(format!("val-{i}")));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn repair_options() -> GhalaDbResult<()> {
+        let fs = crate::fs::InMemoryFs::new();
+        let opts = DatabaseOptions::builder().build();
+        let mut db: GhalaDb<u32, Vec<u8>> =
+            GhalaDb::with_fs("db", Some(opts), Arc::new(fs.clone()))?;
+        db.put(&1, &vec![1; 8])?;
+        db.put(&2, &vec![2; 8 * 1024])?;
+        drop(db);
+
+        // values larger than the options allow are not salvaged
+        let small = DatabaseOptions::builder().max_value_size(1024).build();
+        let report = GhalaDb::<u32, Vec<u8>>::repair_with_fs(
+            "db",
+            Some(small),
+            Arc::new(fs.clone()),
+        )?;
+        assert_eq!((report.records, report.fragments, report.keys), (1, 1, 1));
+        let mut db: GhalaDb<u32, Vec<u8>> =
+            GhalaDb::with_fs("db", Some(opts), Arc::new(fs.clone()))?;
+        assert_eq!(db.get(&1)?, Some(vec![1; 8]));
+        assert_eq!(db.get(&2)?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,659 @@
+//! GhalaDb's sorted string tables module.
+//!
+//! An SSTable is an immutable, sorted run of keys table records persisted to
+//! disk. Its layout is:
+//!
+//! ```text
+//! | data block | ... | index block | ... | bloom filter | footer |
+//! ```
+//!
+//! Data blocks hold consecutive records. Index blocks hold the fence pointers
+//! (first key, offset and length) of the data blocks, they all have the same
+//! size so that they can be binary searched in place. The fixed-size footer
+//! locates the index blocks and the bloom filter.
+//!
+//! Tables are memory-mapped, opening one only reads its footer. Data blocks,
+//! index blocks and the bloom filter are encrypted if a cipher is set, in which
+//! case the bloom filter is loaded when the table is opened and index blocks
+//! are cached once decrypted.
+use crate::{
+    config::DatabaseOptions,
+    core::{Bytes, KeyEntry, KeyRef, ValueRef},
+    crypto::{self, Cipher},
+    dec::Dec,
+    error::{GhalaDbError, GhalaDbResult},
+    fs::{FileMap, FileSystem, FileWriter, OpenMode},
+};
+use std::{
+    borrow::Cow,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+/// A keys table record. A `None` entry is a tombstone marking a deleted key.
+pub(crate) type Record = (Bytes, Option<KeyEntry>);
+
+/// Minimum index block size in bytes.
+const INDEX_BLOCK_SZ: usize = 4096;
+/// Size of the index block header: the number of fence pointers.
+const INDEX_HEADER_SZ: usize = 2;
+/// Size of a fence pointer, without its key: key length, offset and length.
+const FENCE_SZ: usize = 2 + 8 + 4;
+const FOOTER_MAGIC: u64 = u64::from_le_bytes(*b"GHALASST");
+/// Maximum key size in bytes, the key length of fence pointers being a u16.
+pub(crate) const MAX_KEY_SZ: usize = u16::MAX as usize;
+
+/// Bloom filter over a table's keys, probed in place.
+///
+/// Uses double hashing of a 64 bit FNV-1a hash to derive the probed bits.
+struct BloomFilter;
+
+impl BloomFilter {
+    fn build(keys: usize, bits_per_key: usize) -> Bytes {
+        vec![0; (keys * bits_per_key).max(64).div_ceil(8)]
+    }
+
+    /// Number of hash functions, ln(2) * bits per key minimizes the false
+    /// positive rate.
+    fn hashes(bits_per_key: usize) -> u32 {
+        ((bits_per_key as f64 * 0.69) as u32).clamp(1, 30)
+    }
+
+    fn insert(bits: &mut [u8], hashes: u32, key: KeyRef) {
+        for bit in Self::probes(bits.len(), hashes, key) {
+            bits[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    fn may_contain(bits: &[u8], hashes: u32, key: KeyRef) -> bool {
+        Self::probes(bits.len(), hashes, key)
+            .all(|bit| bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    fn probes(len: usize, hashes: u32, key: KeyRef) -> impl Iterator<Item = usize> {
+        let nbits = (len * 8) as u64;
+        let h1 = Self::hash(key);
+        let h2 = h1.rotate_left(31).wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+        (0..hashes as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % nbits) as usize)
+    }
+
+    fn hash(key: KeyRef) -> u64 {
+        key.iter().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+            (h ^ *b as u64).wrapping_mul(0x100_0000_01b3)
+        })
+    }
+}
+
+/// Fence pointer to a data block.
+#[derive(Debug, PartialEq)]
+struct Fence<'a> {
+    /// The first key in the block.
+    key: &'a [u8],
+    offset: u64,
+    len: u32,
+}
+
+/// Fixed-size table footer, all fields are little endian u64s.
+#[derive(Debug, Default, Clone, Copy)]
+struct Footer {
+    index_off: u64,
+    /// Number of index blocks.
+    index_blocks: u64,
+    /// Size of an index block on disk.
+    index_stride: u64,
+    bloom_off: u64,
+    bloom_len: u64,
+    bloom_hashes: u64,
+    /// Number of records.
+    len: u64,
+}
+
+impl Footer {
+    const SZ: usize = 8 * 8;
+
+    fn to_bytes(self) -> Bytes {
+        [
+            self.index_off,
+            self.index_blocks,
+            self.index_stride,
+            self.bloom_off,
+            self.bloom_len,
+            self.bloom_hashes,
+            self.len,
+            FOOTER_MAGIC,
+        ]
+        .iter()
+        .flat_map(|n| n.to_le_bytes())
+        .collect()
+    }
+
+    fn from_bytes(buf: &[u8]) -> Option<Footer> {
+        let mut fields = buf
+            .chunks_exact(8)
+            .map(|c| u64::from_le_bytes(c.try_into().unwrap()));
+        let footer = Footer {
+            index_off: fields.next()?,
+            index_blocks: fields.next()?,
+            index_stride: fields.next()?,
+            bloom_off: fields.next()?,
+            bloom_len: fields.next()?,
+            bloom_hashes: fields.next()?,
+            len: fields.next()?,
+        };
+        (fields.next()? == FOOTER_MAGIC).then_some(footer)
+    }
+}
+
+/// An immutable sorted table of keys and their entries.
+///
+/// The table is memory-mapped, a lookup probes the bloom filter, binary
+/// searches the index blocks in place and decodes at most a single data block.
+pub(crate) struct SsTable {
+    path: PathBuf,
+    map: FileMap,
+    footer: Footer,
+    /// Decrypted bloom filter of an encrypted table.
+    bloom: Option<Bytes>,
+    /// Decrypted index blocks of an encrypted table, loaded on first use.
+    index_cache: Vec<OnceLock<Bytes>>,
+    cipher: Option<Cipher>,
+}
+
+impl SsTable {
+    /// Writes sorted records to a new table at `path`.
+    ///
+    /// `expected` is an estimate of the number of records, used to size the
+    /// bloom filter.
+    pub fn create<I>(
+        fs: &dyn FileSystem,
+        path: &Path,
+        records: I,
+        expected: usize,
+        conf: &DatabaseOptions,
+        cipher: Option<Cipher>,
+    ) -> GhalaDbResult<SsTable>
+    where
+        I: IntoIterator<Item = GhalaDbResult<Record>>,
+    {
+        debug!("SsTable::create path: {}", path.display());
+        let file = fs.open(path, OpenMode::Create)?;
+        let mut wtr = BufWriter::new(FileWriter(file.clone()));
+        let mut footer = Footer {
+            bloom_hashes: BloomFilter::hashes(conf.keys_bloom_bits) as u64,
+            ..Default::default()
+        };
+        let hashes = footer.bloom_hashes as u32;
+        let mut bloom = BloomFilter::build(expected, conf.keys_bloom_bits);
+        let mut fences: Vec<(Bytes, u64, u32)> = vec![];
+        let mut offset = 0;
+        let mut block: Vec<Record> = vec![];
+        let mut block_sz = 0;
+        for record in records {
+            let record = record?;
+            if record.0.len() > MAX_KEY_SZ {
+                return Err(GhalaDbError::KeyTooLarge(record.0.len()));
+            }
+            BloomFilter::insert(&mut bloom, hashes, &record.0);
+            footer.len += 1;
+            block_sz += Self::record_sz(&record);
+            block.push(record);
+            if block_sz >= conf.keys_block_size {
+                offset += Self::write_block(
+                    &mut wtr,
+                    &mut fences,
+                    offset,
+                    &block,
+                    &cipher,
+                )?;
+                block.clear();
+                block_sz = 0;
+            }
+        }
+        if !block.is_empty() {
+            offset +=
+                Self::write_block(&mut wtr, &mut fences, offset, &block, &cipher)?;
+        }
+
+        footer.index_off = offset;
+        let max_key = fences.iter().map(|(k, _, _)| k.len()).max().unwrap_or(0);
+        let block_sz = INDEX_BLOCK_SZ.max(INDEX_HEADER_SZ + FENCE_SZ + max_key);
+        let mut index_block = Vec::with_capacity(block_sz);
+        let mut count: u16 = 0;
+        for (idx, (key, off, len)) in fences.iter().enumerate() {
+            if count == 0 {
+                index_block.clear();
+                index_block.extend_from_slice(&[0; INDEX_HEADER_SZ]);
+            }
+            index_block.extend_from_slice(&(key.len() as u16).to_le_bytes());
+            index_block.extend_from_slice(key);
+            index_block.extend_from_slice(&off.to_le_bytes());
+            index_block.extend_from_slice(&len.to_le_bytes());
+            count += 1;
+            let full = fences.get(idx + 1).is_none_or(|(k, _, _)| {
+                index_block.len() + FENCE_SZ + k.len() > block_sz
+                    || count == u16::MAX
+            });
+            if full {
+                index_block[..INDEX_HEADER_SZ].copy_from_slice(&count.to_le_bytes());
+                index_block.resize(block_sz, 0);
+                let bytes = crypto::seal(cipher.as_ref(), index_block.clone())?;
+                wtr.write_all(&bytes)?;
+                footer.index_stride = bytes.len() as u64;
+                footer.index_blocks += 1;
+                offset += bytes.len() as u64;
+                count = 0;
+            }
+        }
+
+        footer.bloom_off = offset;
+        let bloom = crypto::seal(cipher.as_ref(), bloom)?;
+        footer.bloom_len = bloom.len() as u64;
+        wtr.write_all(&bloom)?;
+        wtr.write_all(&footer.to_bytes())?;
+        wtr.flush()?;
+        file.sync()?;
+        trace!("SsTable::create records: {}", footer.len);
+        drop(wtr);
+
+        Self::open(fs, path, cipher)
+    }
+
+    /// Maps an existing table.
+    pub fn open(
+        fs: &dyn FileSystem,
+        path: &Path,
+        cipher: Option<Cipher>,
+    ) -> GhalaDbResult<SsTable> {
+        debug!("SsTable::open path: {}", path.display());
+        let corrupt = || GhalaDbError::CorruptKeysTable(path.to_path_buf());
+        // tables are immutable once written, they are only ever removed and
+        // never modified in place.
+        let map = fs.map(path)?;
+        let footer_off = map.len().checked_sub(Footer::SZ).ok_or_else(corrupt)?;
+        let footer = Footer::from_bytes(&map[footer_off..]).ok_or_else(corrupt)?;
+        let index_end = footer
+            .index_blocks
+            .checked_mul(footer.index_stride)
+            .and_then(|sz| sz.checked_add(footer.index_off))
+            .ok_or_else(corrupt)?;
+        let bloom_end = footer
+            .bloom_off
+            .checked_add(footer.bloom_len)
+            .ok_or_else(corrupt)?;
+        if index_end > footer.bloom_off
+            || bloom_end != footer_off as u64
+            || footer.bloom_len == 0
+        {
+            return Err(corrupt());
+        }
+        let mut table = SsTable {
+            path: path.to_path_buf(),
+            map,
+            footer,
+            bloom: None,
+            index_cache: vec![],
+            cipher,
+        };
+        if table.cipher.is_some() {
+            let bloom = table.section(footer.bloom_off, footer.bloom_len)?;
+            if bloom.is_empty() {
+                return Err(corrupt());
+            }
+            table.bloom = Some(bloom.into_owned());
+            table.index_cache =
+                (0..footer.index_blocks).map(|_| OnceLock::new()).collect();
+        }
+        Ok(table)
+    }
+
+    /// Looks up a key.
+    ///
+    /// Returns `None` if the table does not hold the key, and `Some(None)` if
+    /// the table holds a tombstone for it.
+    pub fn get(&self, key: KeyRef) -> GhalaDbResult<Option<Option<KeyEntry>>> {
+        if !self.may_contain(key)? {
+            return Ok(None);
+        }
+        // count the index blocks starting at or before the key
+        let (mut lo, mut hi) = (0, self.footer.index_blocks);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let block = self.index_block(mid)?;
+            let first = self.fences(&block).next().transpose()?;
+            if first.is_some_and(|f| f.key <= key) {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        if lo == 0 {
+            return Ok(None);
+        }
+        let block = self.index_block(lo - 1)?;
+        let mut last = None;
+        for fence in self.fences(&block) {
+            let fence = fence?;
+            if fence.key > key {
+                break;
+            }
+            last = Some(fence);
+        }
+        let Some(fence) = last else {
+            return Ok(None);
+        };
+        let mut records = self.data_block(fence.offset, fence.len)?;
+        match records.binary_search_by(|(k, _)| k.as_slice().cmp(key)) {
+            Ok(idx) => Ok(Some(records.swap_remove(idx).1)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Checks the bloom filter for a key, which may be in the table if set.
+    pub fn may_contain(&self, key: KeyRef) -> GhalaDbResult<bool> {
+        let bloom = match self.bloom {
+            Some(ref bloom) => bloom,
+            None => self.raw(self.footer.bloom_off, self.footer.bloom_len)?,
+        };
+        let hashes = self.footer.bloom_hashes as u32;
+        Ok(BloomFilter::may_contain(bloom, hashes, key))
+    }
+
+    /// Number of records in the table, tombstones included.
+    pub fn len(&self) -> usize {
+        self.footer.len as usize
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// An iterator visiting all records of the table in key order.
+    pub fn iter(&self) -> SsTableIter<'_> {
+        SsTableIter {
+            table: self,
+            index_block: 0,
+            fences: vec![].into_iter(),
+            block: vec![].into_iter(),
+        }
+    }
+
+    /// Approximate in-memory size of a record.
+    pub fn record_sz(record: &Record) -> usize {
+        let entry_sz = record.1.as_ref().map_or(0, |e| {
+            let inline_sz = match &e.val {
+                Some(ValueRef::Inline(val)) => val.len(),
+                Some(ValueRef::Chunked(manifest)) => {
+                    manifest.chunks.len()
+                        * std::mem::size_of::<crate::core::DataPtr>()
+                }
+                _ => 0,
+            };
+            std::mem::size_of::<KeyEntry>()
+                + inline_sz
+                + e.operands.len() * std::mem::size_of::<crate::core::DataPtr>()
+        });
+        record.0.len() + entry_sz
+    }
+
+    fn write_block<W: Write>(
+        wtr: &mut W,
+        fences: &mut Vec<(Bytes, u64, u32)>,
+        offset: u64,
+        block: &[Record],
+        cipher: &Option<Cipher>,
+    ) -> GhalaDbResult<u64> {
+        let bytes = crypto::seal(cipher.as_ref(), Dec::ser_raw(&block)?)?;
+        wtr.write_all(&bytes)?;
+        fences.push((block[0].0.clone(), offset, bytes.len() as u32));
+        Ok(bytes.len() as u64)
+    }
+
+    fn raw(&self, offset: u64, len: u64) -> GhalaDbResult<&[u8]> {
+        let end = offset.checked_add(len).ok_or_else(|| self.corrupt())?;
+        self.map
+            .get(offset as usize..end as usize)
+            .ok_or_else(|| self.corrupt())
+    }
+
+    /// A section of the table, decrypted if needed.
+    fn section(&self, offset: u64, len: u64) -> GhalaDbResult<Cow<'_, [u8]>> {
+        crypto::open(self.cipher.as_ref(), self.raw(offset, len)?)
+    }
+
+    fn corrupt(&self) -> GhalaDbError {
+        GhalaDbError::CorruptKeysTable(self.path.clone())
+    }
+
+    fn index_block(&self, idx: u64) -> GhalaDbResult<Cow<'_, [u8]>> {
+        let offset = self.footer.index_off + idx * self.footer.index_stride;
+        let Some(cached) = self.index_cache.get(idx as usize) else {
+            return Ok(Cow::Borrowed(self.raw(offset, self.footer.index_stride)?));
+        };
+        if let Some(block) = cached.get() {
+            return Ok(Cow::Borrowed(block));
+        }
+        let block = self.section(offset, self.footer.index_stride)?.into_owned();
+        Ok(Cow::Borrowed(cached.get_or_init(|| block)))
+    }
+
+    fn data_block(&self, offset: u64, len: u32) -> GhalaDbResult<Vec<Record>> {
+        Dec::deser_raw(&self.section(offset, len as u64)?)
+    }
+
+    /// Parses the fence pointers of an index block in place.
+    ///
+    /// A fence pointer running past the end of the block ends the iteration
+    /// with a [GhalaDbError::CorruptKeysTable] error.
+    fn fences<'a>(
+        &'a self,
+        block: &'a [u8],
+    ) -> impl Iterator<Item = GhalaDbResult<Fence<'a>>> + 'a {
+        // a block too short for its header yields a single error
+        let count = block
+            .get(..INDEX_HEADER_SZ)
+            .map_or(1, |header| u16::from_le_bytes([header[0], header[1]]));
+        let mut pos = INDEX_HEADER_SZ;
+        let mut corrupt = false;
+        (0..count).map_while(move |_| {
+            if corrupt {
+                return None;
+            }
+            match Self::fence_at(block, pos) {
+                Some((fence, next)) => {
+                    pos = next;
+                    Some(Ok(fence))
+                }
+                None => {
+                    corrupt = true;
+                    Some(Err(self.corrupt()))
+                }
+            }
+        })
+    }
+
+    /// Parses the fence pointer at `pos`, returning it along with the position
+    /// of the next one.
+    fn fence_at(block: &[u8], pos: usize) -> Option<(Fence<'_>, usize)> {
+        let key_len = u16::from_le_bytes(block.get(pos..pos + 2)?.try_into().ok()?);
+        let key_end = pos + 2 + key_len as usize;
+        let key = block.get(pos + 2..key_end)?;
+        let offset =
+            u64::from_le_bytes(block.get(key_end..key_end + 8)?.try_into().ok()?);
+        let len = u32::from_le_bytes(
+            block.get(key_end + 8..key_end + 12)?.try_into().ok()?,
+        );
+        Some((Fence { key, offset, len }, key_end + 12))
+    }
+}
+
+/// Iterates over the records of an [SsTable].
+pub(crate) struct SsTableIter<'a> {
+    table: &'a SsTable,
+    index_block: u64,
+    /// Offsets and lengths of the remaining data blocks of the current index
+    /// block.
+    fences: std::vec::IntoIter<(u64, u32)>,
+    block: std::vec::IntoIter<Record>,
+}
+
+impl SsTableIter<'_> {
+    fn next_block(&mut self) -> GhalaDbResult<Option<Vec<Record>>> {
+        loop {
+            if let Some((offset, len)) = self.fences.next() {
+                return self.table.data_block(offset, len).map(Some);
+            }
+            if self.index_block >= self.table.footer.index_blocks {
+                return Ok(None);
+            }
+            let block = self.table.index_block(self.index_block)?;
+            self.index_block += 1;
+            self.fences = self
+                .table
+                .fences(&block)
+                .map(|f| f.map(|f| (f.offset, f.len)))
+                .collect::<GhalaDbResult<Vec<_>>>()?
+                .into_iter();
+        }
+    }
+}
+
+impl Iterator for SsTableIter<'_> {
+    type Item = GhalaDbResult<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.block.next() {
+                return Some(Ok(record));
+            }
+            match self.next_block() {
+                Ok(Some(block)) => self.block = block.into_iter(),
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{core::DataPtr, fs::StdFs};
+    use tempfile::tempdir;
+
+    fn roundtrip(cipher: Option<Cipher>) -> GhalaDbResult<()> {
+        let tmp_dir = tempdir()?;
+        let path = tmp_dir.path().join("1.sst");
+        let conf = DatabaseOptions::builder().keys_block_size(64).build();
+        let records: Vec<Record> = (0..5000u32)
+            .map(|i| {
+                let entry = (i % 10 != 0).then(|| {
+                    KeyEntry::new(DataPtr::new(1, i as u64, 8, false), None)
+                });
+                (i.to_be_bytes().to_vec(), entry)
+            })
+            .collect();
+        let iter = records.iter().cloned().map(Ok);
+        let table = SsTable::create(
+            &StdFs,
+            &path,
+            iter,
+            records.len(),
+            &conf,
+            cipher.clone(),
+        )?;
+        assert!(table.footer.index_blocks > 1);
+        drop(table);
+
+        let table = SsTable::open(&StdFs, &path, cipher)?;
+        assert_eq!(table.len(), records.len());
+        for (k, e) in &records {
+            assert_eq!(table.get(k)?, Some(e.clone()));
+        }
+        assert_eq!(table.get(&9000u32.to_be_bytes())?, None);
+        assert_eq!(table.get(&[0])?, None);
+        let scanned = table.iter().collect::<GhalaDbResult<Vec<Record>>>()?;
+        assert_eq!(scanned, records);
+        Ok(())
+    }
+
+    #[test]
+    fn sstable_roundtrip() -> GhalaDbResult<()> {
+        roundtrip(None)
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_sstable_roundtrip() -> GhalaDbResult<()> {
+        let ring = crate::KeyRing::new(1, [3u8; 32]);
+        roundtrip(Some(Cipher::new(std::sync::Arc::new(ring))))
+    }
+
+    #[test]
+    fn bloom_filter() {
+        let hashes = BloomFilter::hashes(10);
+        let mut bloom = BloomFilter::build(1000, 10);
+        for i in 0..1000u32 {
+            BloomFilter::insert(&mut bloom, hashes, &i.to_le_bytes());
+        }
+        assert!((0..1000u32).all(|i| BloomFilter::may_contain(
+            &bloom,
+            hashes,
+            &i.to_le_bytes()
+        )));
+        let false_positives = (1000..11000u32)
+            .filter(|i| BloomFilter::may_contain(&bloom, hashes, &i.to_le_bytes()))
+            .count();
+        assert!(false_positives < 500, "false positives: {false_positives}");
+    }
+
+    #[test]
+    fn corrupt_sstable() -> GhalaDbResult<()> {
+        let fs = crate::fs::InMemoryFs::new();
+        let path = PathBuf::from("1.sst");
+        let conf = DatabaseOptions::builder().keys_block_size(64).build();
+        let records = (0..500u32).map(|i| {
+            let entry = KeyEntry::new(DataPtr::new(1, i as u64, 8, false), None);
+            Ok((i.to_be_bytes().to_vec(), Some(entry)))
+        });
+        let table = SsTable::create(&fs, &path, records, 500, &conf, None)?;
+        let (footer, bytes) = (table.footer, fs.read(&path)?);
+        drop(table);
+        let corrupt = |bytes: &[u8]| -> GhalaDbResult<()> {
+            fs.write(&path, bytes)?;
+            let table = SsTable::open(&fs, &path, None)?;
+            (0..500u32).try_for_each(|i| table.get(&i.to_be_bytes()).map(|_| ()))?;
+            table.iter().try_for_each(|r| r.map(|_| ()))
+        };
+        let is_corrupt = |res| matches!(res, Err(GhalaDbError::CorruptKeysTable(_)));
+
+        // index blocks overflowing the table
+        let mut bad = footer;
+        bad.index_blocks = u64::MAX / 2;
+        let footer_off = bytes.len() - Footer::SZ;
+        let mut buf = bytes.clone();
+        buf[footer_off..].copy_from_slice(&bad.to_bytes());
+        assert!(is_corrupt(corrupt(&buf)));
+        // fence pointer keys running past their index block
+        let mut buf = bytes.clone();
+        let key_len = footer.index_off as usize + INDEX_HEADER_SZ;
+        buf[key_len..key_len + 2].copy_from_slice(&u16::MAX.to_le_bytes());
+        assert!(is_corrupt(corrupt(&buf)));
+        // fence pointers to data blocks past the end of the table
+        let mut buf = bytes.clone();
+        let offset = key_len + 2 + 4;
+        buf[offset..offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(is_corrupt(corrupt(&buf)));
+        corrupt(&bytes)
+    }
+
+    #[test]
+    fn key_too_large() {
+        let conf = DatabaseOptions::builder().build();
+        let records = [Ok((vec![0; MAX_KEY_SZ + 1], None))];
+        let fs = crate::fs::InMemoryFs::new();
+        let res = SsTable::create(&fs, Path::new("1.sst"), records, 1, &conf, None);
+        assert!(matches!(res, Err(GhalaDbError::KeyTooLarge(_))));
+    }
+}
@@ -0,0 +1,115 @@
+//! GhalaDb's streamed values module.
+use crate::{
+    core::{Bytes, DataPtr},
+    utils::t,
+    vlog::VlogsMan,
+};
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// A reader over a value, returned by
+/// [GhalaDb::get_reader](crate::GhalaDb::get_reader).
+///
+/// Chunked values are read one chunk at a time, so only a single chunk is held
+/// in memory. Other values are read in full when the reader is created.
+pub struct ValueReader<'a> {
+    vlogs_man: &'a mut VlogsMan,
+    chunks: Vec<DataPtr>,
+    chunk_size: u64,
+    len: u64,
+    pos: u64,
+    /// The current chunk's index and data.
+    buf: Option<(usize, Bytes)>,
+}
+
+impl<'a> ValueReader<'a> {
+    pub(crate) fn chunked(
+        vlogs_man: &'a mut VlogsMan,
+        chunks: Vec<DataPtr>,
+        chunk_size: u32,
+        len: u64,
+    ) -> Self {
+        Self {
+            vlogs_man,
+            chunks,
+            chunk_size: chunk_size as u64,
+            len,
+            pos: 0,
+            buf: None,
+        }
+    }
+
+    pub(crate) fn in_memory(vlogs_man: &'a mut VlogsMan, val: Bytes) -> Self {
+        let len = val.len() as u64;
+        Self {
+            vlogs_man,
+            chunks: vec![],
+            chunk_size: len,
+            len,
+            pos: 0,
+            buf: Some((0, val)),
+        }
+    }
+
+    /// Total size of the value.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Check if the value is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Loads the chunk holding the current position.
+    fn chunk(&mut self) -> io::Result<(&[u8], usize)> {
+        let idx = (self.pos / self.chunk_size) as usize;
+        if self.buf.as_ref().is_none_or(|(i, _)| *i != idx) {
+            let dp = self.chunks.get(idx).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "missing value chunk")
+            })?;
+            let de = t!("vlogman::get", self.vlogs_man.get(dp))
+                .map_err(|e| io::Error::other(e.to_string()))?;
+            self.buf = Some((idx, de.val));
+        }
+        let offset = (self.pos - idx as u64 * self.chunk_size) as usize;
+        // the buffer was loaded above
+        let (_, buf) = self.buf.as_ref().unwrap();
+        Ok((buf, offset))
+    }
+}
+
+impl Read for ValueReader<'_> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || out.is_empty() {
+            return Ok(0);
+        }
+        let (buf, offset) = self.chunk()?;
+        let n = buf.len().saturating_sub(offset).min(out.len());
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated value chunk",
+            ));
+        }
+        out[..n].copy_from_slice(&buf[offset..offset + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for ValueReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        self.pos = pos.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
@@ -1,3 +1,12 @@
+use crate::{
+    error::{GhalaDbError, GhalaDbResult},
+    fs::{FileReader, FileSystem, FileWriter, OpenMode},
+};
+use std::{
+    io::{self, Read},
+    path::Path,
+};
+
 macro_rules! t {
     ($id:expr, $expr:expr $(,)?) => {
         match $expr {
@@ -14,3 +23,57 @@ macro_rules! t {
 }
 
 pub(crate) use t;
+
+/// Copies the `len` bytes of a file starting at `offset`.
+pub(crate) fn copy_range(
+    fs: &dyn FileSystem,
+    src: &Path,
+    dest: &Path,
+    offset: u64,
+    len: u64,
+) -> io::Result<()> {
+    let rdr = FileReader::new(fs.open(src, OpenMode::Read)?, offset);
+    let file = fs.open(dest, OpenMode::Create)?;
+    io::copy(&mut rdr.take(len), &mut FileWriter(file.clone()))?;
+    file.sync()
+}
+
+/// Creates a directory, which has to be empty if it exists.
+pub(crate) fn init_empty_dir(fs: &dyn FileSystem, path: &Path) -> GhalaDbResult<()> {
+    if fs.is_dir(path) && !fs.list(path)?.is_empty() {
+        return Err(GhalaDbError::DirNotEmpty(path.to_path_buf()));
+    }
+    fs.create_dir_all(path)?;
+    Ok(())
+}
+
+/// Replaces a file's contents atomically, by writing them to a temporary file
+/// which is synced and renamed over the file. The rename is made durable by
+/// syncing the file's directory.
+pub(crate) fn write_atomic(
+    fs: &dyn FileSystem,
+    path: &Path,
+    contents: &[u8],
+) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let file = fs.open(&tmp, OpenMode::Create)?;
+    file.append(contents)?;
+    file.sync()?;
+    fs.rename(&tmp, path)?;
+    sync_parent(fs, path)
+}
+
+/// Directory holding a file, which is the current directory for a relative
+/// path of a single component.
+pub(crate) fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Makes the creation, renaming or removal of a file durable by syncing its
+/// directory.
+pub(crate) fn sync_parent(fs: &dyn FileSystem, path: &Path) -> io::Result<()> {
+    fs.sync_dir(parent_dir(path))
+}
@@ -0,0 +1,308 @@
+//! GhalaDb's integrity verification module.
+use crate::{
+    core::{Bytes, ValueRef, VlogNum},
+    error::GhalaDbResult,
+    keyspace::Keyspaces,
+    vlog::VlogsMan,
+};
+use std::{collections::BTreeSet, fmt};
+
+/// Report of an integrity verification, returned by
+/// [GhalaDb::verify](crate::GhalaDb::verify).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Number of live keys checked, across keyspaces.
+    pub keys: u64,
+    /// Number of data pointers checked.
+    pub pointers: u64,
+    /// Number of vlogs parsed.
+    pub vlogs: u64,
+    /// Number of data entries parsed in the vlogs.
+    pub records: u64,
+    /// Problems found.
+    pub issues: Vec<VerifyIssue>,
+}
+
+impl VerifyReport {
+    /// Check if no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// A problem found by an integrity verification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyIssue {
+    /// A keyspace's keys table could not be read.
+    UnreadableKeys {
+        /// Keyspace name.
+        keyspace: String,
+        /// Read error.
+        error: String,
+    },
+    /// A key points into a vlog that is not listed in the vlogs info.
+    DanglingPointer {
+        /// Keyspace name.
+        keyspace: String,
+        /// Encoded key.
+        key: Bytes,
+        /// Vlog number.
+        vlog: u64,
+        /// Offset of the data entry in the vlog.
+        offset: u64,
+    },
+    /// The data entry a key points to could not be read.
+    UnreadableEntry {
+        /// Keyspace name.
+        keyspace: String,
+        /// Encoded key.
+        key: Bytes,
+        /// Vlog number.
+        vlog: u64,
+        /// Offset of the data entry in the vlog.
+        offset: u64,
+        /// Read error.
+        error: String,
+    },
+    /// The data entry a key points to belongs to another key.
+    KeyMismatch {
+        /// Keyspace name.
+        keyspace: String,
+        /// Encoded key.
+        key: Bytes,
+        /// Vlog number.
+        vlog: u64,
+        /// Offset of the data entry in the vlog.
+        offset: u64,
+        /// Encoded key of the data entry.
+        found: Bytes,
+    },
+    /// A vlog listed in the vlogs info has no file.
+    MissingVlog(u64),
+    /// A vlog file is not listed in the vlogs info.
+    OrphanVlog(u64),
+    /// A vlog could not be parsed to its end.
+    CorruptVlog {
+        /// Vlog number.
+        vlog: u64,
+        /// Number of data entries parsed before the failure.
+        records: u64,
+        /// Parse error.
+        error: String,
+    },
+}
+
+impl fmt::Display for VerifyIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyIssue::UnreadableKeys { keyspace, error } => {
+                write!(f, "unreadable keys in keyspace {keyspace}: {error}")
+            }
+            VerifyIssue::DanglingPointer {
+                keyspace,
+                key,
+                vlog,
+                ..
+            } => write!(
+                f,
+                "key {} in keyspace {keyspace} points to unknown vlog {vlog}",
+                key.escape_ascii(),
+            ),
+            VerifyIssue::UnreadableEntry {
+                keyspace,
+                key,
+                vlog,
+                offset,
+                error,
+            } => write!(
+                f,
+                "unreadable entry of key {} in keyspace {keyspace} at vlog {vlog} \
+                 offset {offset}: {error}",
+                key.escape_ascii(),
+            ),
+            VerifyIssue::KeyMismatch {
+                keyspace,
+                key,
+                vlog,
+                offset,
+                found,
+            } => write!(
+                f,
+                "key {} in keyspace {keyspace} points to the entry of key {} at \
+                 vlog {vlog} offset {offset}",
+                key.escape_ascii(),
+                found.escape_ascii(),
+            ),
+            VerifyIssue::MissingVlog(vnum) => write!(f, "missing vlog {vnum}"),
+            VerifyIssue::OrphanVlog(vnum) => write!(f, "orphan vlog {vnum}"),
+            VerifyIssue::CorruptVlog {
+                vlog,
+                records,
+                error,
+            } => write!(f, "corrupt vlog {vlog} after {records} entries: {error}"),
+        }
+    }
+}
+
+/// Verifies the keys tables and vlogs of a synced data store.
+pub(crate) fn verify(
+    keyspaces: &Keyspaces,
+    vlogs_man: &mut VlogsMan,
+) -> GhalaDbResult<VerifyReport> {
+    let mut report = VerifyReport::default();
+    let vlogs: BTreeSet<VlogNum> = vlogs_man.vlog_nums().into_iter().collect();
+    for (keyspace, id) in keyspaces.names() {
+        let iter = match keyspaces.keys_ref(id)?.iter() {
+            Ok(iter) => iter,
+            Err(e) => {
+                report.issues.push(VerifyIssue::UnreadableKeys {
+                    keyspace,
+                    error: e.to_string(),
+                });
+                continue;
+            }
+        };
+        for kv in iter {
+            let (key, entry) = match kv {
+                Ok(kv) => kv,
+                Err(e) => {
+                    report.issues.push(VerifyIssue::UnreadableKeys {
+                        keyspace: keyspace.clone(),
+                        error: e.to_string(),
+                    });
+                    break;
+                }
+            };
+            report.keys += 1;
+            let mut dps = entry.operands.clone();
+            match entry.val {
+                Some(ValueRef::Ptr(dp)) => dps.push(dp),
+                Some(ValueRef::Chunked(manifest)) => dps.extend(manifest.chunks),
+                Some(ValueRef::Inline(_)) | None => {}
+            }
+            for dp in dps {
+                report.pointers += 1;
+                let (key, keyspace) = (key.clone(), keyspace.clone());
+                let (vlog, offset) = (dp.vlog, dp.offset);
+                if !vlogs.contains(&vlog) {
+                    report.issues.push(VerifyIssue::DanglingPointer {
+                        keyspace,
+                        key,
+                        vlog,
+                        offset,
+                    });
+                    continue;
+                }
+                match vlogs_man.read(&dp) {
+                    Ok(de) if de.key == key && de.keyspace == id => {}
+                    Ok(de) => report.issues.push(VerifyIssue::KeyMismatch {
+                        keyspace,
+                        key,
+                        vlog,
+                        offset,
+                        found: de.key,
+                    }),
+                    Err(e) => report.issues.push(VerifyIssue::UnreadableEntry {
+                        keyspace,
+                        key,
+                        vlog,
+                        offset,
+                        error: e.to_string(),
+                    }),
+                }
+            }
+        }
+    }
+
+    for vnum in vlogs_man.missing() {
+        report.issues.push(VerifyIssue::MissingVlog(*vnum));
+    }
+    for path in vlogs_man.fs().list(vlogs_man.base_path())? {
+        if path.extension().is_some_and(|ext| ext == "vlog") {
+            let num = path.file_stem().and_then(|s| s.to_str()?.parse().ok());
+            if let Some(vnum) = num.filter(|vnum| !vlogs.contains(vnum)) {
+                report.issues.push(VerifyIssue::OrphanVlog(vnum));
+            }
+        }
+    }
+    for vnum in vlogs {
+        report.vlogs += 1;
+        let mut records = 0;
+        let res = vlogs_man.reader(vnum).and_then(|rdr| {
+            for record in rdr {
+                record?;
+                records += 1;
+            }
+            Ok(())
+        });
+        report.records += records;
+        if let Err(e) = res {
+            report.issues.push(VerifyIssue::CorruptVlog {
+                vlog: vnum,
+                records,
+                error: e.to_string(),
+            });
+        }
+    }
+    debug!(
+        "verify keys: {} vlogs: {} issues: {}",
+        report.keys,
+        report.vlogs,
+        report.issues.len()
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::DatabaseOptions, ghaladb::GhalaDb};
+    use std::fs::{self, File};
+    use tempfile::tempdir;
+
+    #[test]
+    fn verify_store() -> GhalaDbResult<()> {
+        let tmp_dir = tempdir()?;
+        let opts = DatabaseOptions::builder()
+            .max_vlog_size(4096)
+            .compact(false)
+            .build();
+        let mut db: GhalaDb<u32, String> = GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        for i in 0..200 {
+            db.put(&i, &"x".repeat(100))?;
+        }
+        let report = db.verify()?;
+        assert!(report.is_ok(), "{:?}", report.issues);
+        assert_eq!(report.keys, 200);
+        assert_eq!(report.pointers, 200);
+        assert_eq!(report.records, 200);
+        assert!(report.vlogs > 2);
+        drop(db);
+
+        let vlog = |vnum: u64| tmp_dir.path().join(format!("{vnum}.vlog"));
+        fs::copy(vlog(0), vlog(999))?;
+        let len = fs::metadata(vlog(0))?.len();
+        File::options()
+            .write(true)
+            .open(vlog(0))?
+            .set_len(len - 10)?;
+        fs::remove_file(vlog(1))?;
+        let mut db: GhalaDb<u32, String> = GhalaDb::new(tmp_dir.path(), Some(opts))?;
+        let report = db.verify()?;
+        assert!(!report.is_ok());
+        assert!(report.issues.contains(&VerifyIssue::OrphanVlog(999)));
+        assert!(report.issues.contains(&VerifyIssue::MissingVlog(1)));
+        let unreadable = |vnum| {
+            report.issues.iter().any(|issue| {
+                matches!(issue, VerifyIssue::UnreadableEntry { vlog, .. } if *vlog == vnum)
+            })
+        };
+        assert!(unreadable(0));
+        assert!(unreadable(1));
+        assert!(report
+            .issues
+            .iter()
+            .any(|issue| matches!(issue, VerifyIssue::CorruptVlog { vlog: 0, .. })));
+        Ok(())
+    }
+}
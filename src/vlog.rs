@@ -1,43 +1,104 @@
 #[cfg(test)]
 use crate::core::FixtureGen;
 use crate::{
+    cache::{CacheStats, ValueCache},
     config::DatabaseOptions,
-    core::{DataEntrySz, DataPtr, VlogNum},
+    core::{DataEntrySz, DataPtr, FileKind, LiveFile, VlogNum},
+    crypto::{self, Cipher},
     dec::Dec,
     error::{GhalaDbError, GhalaDbResult},
-    utils::t,
+    fs::{FileMap, FileReader, FileSystem, FileWriter, Fs, FsFile, OpenMode},
+    keyspace::{KeyspaceId, DEFAULT_KEYSPACE_ID},
+    utils::{copy_range, sync_parent, t, write_atomic},
 };
 use bincode::{Decode, Encode};
 use contracts::*;
 use std::{
+    borrow::Cow,
     collections::BTreeMap,
-    fs::{File, OpenOptions},
-    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    io::{self, BufReader, BufWriter, Read, Write},
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
 const VLOG_INFO_FILE: &str = "vlog_info";
+const DICT_EXT: &str = "dict";
+/// Version of the data entries and vlogs info format. Version 0 is the format
+/// of the first releases, whose data entries only hold a key and a value.
+const FORMAT_VERSION: u32 = 1;
 
 pub type Bytes = Vec<u8>;
 
+/// The kind of value held by a [DataEntry].
+#[derive(Debug, Clone, Copy, Encode, Decode, PartialEq, Eq)]
+pub enum EntryKind {
+    /// A full value.
+    Value,
+    /// A merge operand.
+    MergeOperand,
+    /// A chunk of a streamed value.
+    Chunk,
+}
+
 /// A key-value bytes pair that's persisted in a [Vlog] to disk.
 #[derive(Debug, Clone, Encode, Decode, PartialEq)]
 pub struct DataEntry {
     pub key: Bytes,
     pub val: Bytes,
+    pub kind: EntryKind,
+    /// The keyspace the key belongs to.
+    pub keyspace: KeyspaceId,
 }
 impl DataEntry {
     pub fn new(key: Bytes, val: Bytes) -> DataEntry {
-        Self { key, val }
+        Self {
+            key,
+            val,
+            kind: EntryKind::Value,
+            keyspace: DEFAULT_KEYSPACE_ID,
+        }
+    }
+
+    pub fn operand(key: Bytes, val: Bytes) -> DataEntry {
+        Self {
+            key,
+            val,
+            kind: EntryKind::MergeOperand,
+            keyspace: DEFAULT_KEYSPACE_ID,
+        }
+    }
+
+    pub fn chunk(key: Bytes, val: Bytes) -> DataEntry {
+        Self {
+            key,
+            val,
+            kind: EntryKind::Chunk,
+            keyspace: DEFAULT_KEYSPACE_ID,
+        }
+    }
+
+    pub fn in_keyspace(mut self, keyspace: KeyspaceId) -> DataEntry {
+        self.keyspace = keyspace;
+        self
+    }
+}
+
+/// A data entry of format version 0, which is a value of the default keyspace.
+#[derive(Debug, Clone, Encode, Decode)]
+struct LegacyDataEntry {
+    key: Bytes,
+    val: Bytes,
+}
+impl From<LegacyDataEntry> for DataEntry {
+    fn from(de: LegacyDataEntry) -> DataEntry {
+        DataEntry::new(de.key, de.val)
     }
 }
+
 #[cfg(test)]
 impl FixtureGen<DataEntry> for DataEntry {
     fn gen() -> DataEntry {
-        DataEntry {
-            key: Bytes::gen(),
-            val: Bytes::gen(),
-        }
+        DataEntry::new(Bytes::gen(), Bytes::gen())
     }
 }
 
@@ -61,15 +122,22 @@ impl FixtureGen<DataEntry> for DataEntry {
 /// | Data ptr N <21 bytes>|
 /// | Data entry N |
 /// | END |
+///
+/// If the vlog's data entries are compressed using a zstd dictionary, the
+/// dictionary is stored next to the vlog in a `<num>.dict` file.
 pub(crate) struct Vlog {
-    /// Data Reader
-    rdr: BufReader<File>,
+    /// Vlog file
+    file: Arc<dyn FsFile>,
     /// Data Writer
-    wtr: BufWriter<File>,
+    wtr: BufWriter<FileWriter>,
+    /// File system holding the vlog
+    fs: Fs,
     /// Vlog number
     num: VlogNum,
     /// Write offset
     w_off: u64,
+    /// Offset up to which the vlog is known to be durable
+    synced: u64,
     /// In-memory write buffer
     buf: Vec<(DataPtr, Bytes)>,
     /// Configuration
@@ -82,44 +150,109 @@ pub(crate) struct Vlog {
     active: bool,
     /// Data encoder and compressor
     dec: Dec,
+    /// Memory map of a sealed vlog, used for reads if enabled.
+    map: Option<FileMap>,
+    /// Write buffer size overriding the configured one, set during bulk loads.
+    buf_cap: Option<usize>,
+    /// Set if the vlog holds data entries of format version 0. Such a vlog
+    /// is never appended to.
+    legacy: bool,
 }
 
 impl Vlog {
     fn new(
-        rdr: BufReader<File>,
-        wtr: BufWriter<File>,
+        fs: Fs,
+        file: Arc<dyn FsFile>,
         num: VlogNum,
         offset: u64,
         conf: DatabaseOptions,
         path: PathBuf,
+        dec: Dec,
     ) -> Vlog {
-        let dec = Dec::new(conf.compress);
         Vlog {
-            rdr,
-            wtr,
+            wtr: BufWriter::new(FileWriter(file.clone())),
+            file,
+            fs,
             num,
             w_off: offset,
+            synced: offset,
             buf: vec![],
             conf,
             buf_sz: 0usize,
             path,
             active: true,
             dec,
+            map: None,
+            buf_cap: None,
+            legacy: false,
         }
     }
 
     fn from_path(
+        fs: Fs,
         path: PathBuf,
         num: VlogNum,
         conf: DatabaseOptions,
+        cipher: Option<Cipher>,
     ) -> GhalaDbResult<Vlog> {
-        let mut wtr = BufWriter::new(
-            OpenOptions::new().create(true).append(true).open(&path)?,
-        );
-        let rdr = BufReader::new(OpenOptions::new().read(true).open(&path)?);
-        wtr.seek(SeekFrom::End(0))?;
-        let offset = wtr.stream_position()?;
-        Ok(Vlog::new(rdr, wtr, num, offset, conf, path))
+        let file = fs.open(&path, OpenMode::Append)?;
+        let offset = file.size()?;
+        let dec = match load_dict(&*fs, &path, cipher.as_ref())? {
+            Some(dict) => Dec::with_dict(&dict, conf.zstd_level)?,
+            None => Dec::new(true),
+        };
+        let dec = dec.with_cipher(cipher).with_max_size(conf.max_value_size);
+        Ok(Vlog::new(fs, file, num, offset, conf, path, dec))
+    }
+
+    /// Truncates the vlog after its last intact entry, dropping a torn entry
+    /// a crash may have left at its end, so that it can be appended to.
+    ///
+    /// Only the data pointers heading the entries are read.
+    fn truncate_torn(&mut self) -> GhalaDbResult<()> {
+        let size = self.file.size()?;
+        let dp_sz = DataPtr::serde_sz() as u64;
+        let mut buf = vec![0u8; dp_sz as usize];
+        let mut end = 0;
+        while end + dp_sz <= size {
+            self.file.read_exact_at(&mut buf, end)?;
+            let Ok(dp) = Dec::deser_raw::<DataPtr>(&buf) else {
+                break;
+            };
+            let next = end + dp_sz + dp.len as u64;
+            if dp.vlog != self.num || dp.offset != end + dp_sz || next > size {
+                break;
+            }
+            end = next;
+        }
+        if end < size {
+            warn!(
+                "vlog::truncate_torn num: {} size: {size} truncated: {end}",
+                self.num
+            );
+            self.file.truncate(end)?;
+            self.file.sync()?;
+            self.w_off = end;
+            self.synced = end;
+        }
+        Ok(())
+    }
+
+    /// Seals the vlog once it is no longer the tail.
+    ///
+    /// Buffered entries are flushed and, if enabled, the vlog is
+    /// memory-mapped for reads.
+    fn seal(&mut self) -> GhalaDbResult<()> {
+        self.flush()?;
+        if self.conf.mmap_reads {
+            self.map = self.map_file()?;
+        }
+        Ok(())
+    }
+
+    fn map_file(&self) -> GhalaDbResult<Option<FileMap>> {
+        let map = self.fs.map(&self.path)?;
+        Ok(Some(map).filter(|map| !map.is_empty()))
     }
 
     #[debug_requires(self.active, "vlog not active")]
@@ -139,8 +272,8 @@ impl Vlog {
             .buf
             .binary_search_by(|item| item.0.offset.cmp(&dp.offset))
         {
-            let (_dp, de_bytes) = self.buf.get(index).cloned().unwrap();
-            let de = self.de(&de_bytes)?;
+            let (dp, de_bytes) = self.buf.get(index).cloned().unwrap();
+            let de = self.de(&de_bytes, dp.compressed)?;
             Ok(Some(de))
         } else {
             Ok(None)
@@ -149,29 +282,91 @@ impl Vlog {
 
     // requires that dp not in buf
     fn get_from_disk(&mut self, dp: &DataPtr) -> GhalaDbResult<DataEntry> {
+        if let Some(de) = self.get_from_map(dp)? {
+            return Ok(de);
+        }
         let mut buf = vec![0u8; dp.len as usize];
-        self.rdr.seek(SeekFrom::Start(dp.offset))?;
-        self.rdr.read_exact(&mut buf)?;
-        t!("vlog::de", self.de(&buf))
+        self.file.read_exact_at(&mut buf, dp.offset)?;
+        t!("vlog::de", self.de(&buf, dp.compressed))
+    }
+
+    /// Reads data entries, sorted by offset, with a single read of the span of
+    /// the vlog holding them.
+    fn get_span(&mut self, dps: &[DataPtr]) -> GhalaDbResult<Vec<DataEntry>> {
+        let buffered = self.buf.first().map_or(u64::MAX, |(dp, _)| dp.offset);
+        let (on_disk, in_buf) =
+            dps.split_at(dps.partition_point(|dp| dp.offset < buffered));
+        let mut des = Vec::with_capacity(dps.len());
+        if let (Some(first), Some(last)) = (on_disk.first(), on_disk.last()) {
+            let start = first.offset as usize;
+            let end = last.offset as usize + last.len as usize;
+            if self.map.as_ref().is_some_and(|map| map.len() < end) {
+                self.map = self.map_file()?;
+            }
+            let span = match self.map {
+                Some(ref map) if map.len() >= end => Cow::Borrowed(&map[start..end]),
+                _ => {
+                    let mut buf = vec![0u8; end - start];
+                    self.file.read_exact_at(&mut buf, start as u64)?;
+                    Cow::Owned(buf)
+                }
+            };
+            for dp in on_disk {
+                let off = dp.offset as usize - start;
+                let buf = &span[off..off + dp.len as usize];
+                let de = t!(
+                    "vlog::de",
+                    Self::decode(&mut self.dec, buf, dp.compressed, self.legacy)
+                )?;
+                des.push(de);
+            }
+        }
+        for dp in in_buf {
+            des.push(self.get(dp)?);
+        }
+        Ok(des)
+    }
+
+    /// Reads a data entry through the vlog's memory map, if any.
+    ///
+    /// The vlog is remapped if the entry lies past the end of the map, which
+    /// happens if the file grew after it was mapped.
+    fn get_from_map(&mut self, dp: &DataPtr) -> GhalaDbResult<Option<DataEntry>> {
+        let end = dp.offset as usize + dp.len as usize;
+        if self.map.as_ref().is_some_and(|map| map.len() < end) {
+            debug!("vlog::remap num: {}", self.num);
+            self.map = self.map_file()?;
+        }
+        match self.map {
+            Some(ref map) if map.len() >= end => {
+                let buf = &map[dp.offset as usize..end];
+                let de = t!(
+                    "vlog::de",
+                    Self::decode(&mut self.dec, buf, dp.compressed, self.legacy)
+                )?;
+                Ok(Some(de))
+            }
+            _ => Ok(None),
+        }
     }
 
     #[debug_invariant(self.buf_entries_sorted())]
-    #[debug_ensures(self.w_off > old(self.w_off), "w_off did not inc")]
-    fn write_to_buf(&mut self, de: &DataEntry) -> GhalaDbResult<DataPtr> {
+    #[debug_ensures(ret.is_err() || self.w_off > old(self.w_off), "w_off did not inc")]
+    fn write_to_buf(
+        &mut self,
+        de: &DataEntry,
+        compress: bool,
+    ) -> GhalaDbResult<DataPtr> {
         let offset = self.w_off;
-        let de_bytes = self.ser(de)?;
+        let compress = self.conf.compress && compress;
+        let de_bytes = self.ser(de, compress)?;
         let dp_sz = DataPtr::serde_sz() as u64;
-        if self.buf_sz + de_bytes.len() > self.conf.vlog_mem_buf_size
-            && !self.buf.is_empty()
-        {
+        let buf_cap = self.buf_cap.unwrap_or(self.conf.vlog_mem_buf_size);
+        if self.buf_sz + de_bytes.len() > buf_cap && !self.buf.is_empty() {
             self.flush()?;
         }
-        let dp = DataPtr::new(
-            self.num,
-            offset + dp_sz,
-            de_bytes.len() as u32,
-            self.conf.compress,
-        );
+        let dp =
+            DataPtr::new(self.num, offset + dp_sz, de_bytes.len() as u32, compress);
         self.buf_sz += de_bytes.len() + dp_sz as usize;
         self.w_off += dp_sz + de_bytes.len() as u64;
         self.buf.push((dp, de_bytes));
@@ -179,12 +374,13 @@ impl Vlog {
         Ok(dp)
     }
 
-    fn put(&mut self, entry: &DataEntry) -> GhalaDbResult<DataPtr> {
-        if self.conf.vlog_mem_buf_enabled && !self.conf.sync {
-            let dp = self.write_to_buf(entry)?;
+    fn put(&mut self, entry: &DataEntry, compress: bool) -> GhalaDbResult<DataPtr> {
+        let buffered = self.conf.vlog_mem_buf_enabled && !self.conf.sync;
+        if buffered || self.buf_cap.is_some() {
+            let dp = self.write_to_buf(entry, compress)?;
             Ok(dp)
         } else {
-            let dp = t!("vlog::write_entry", self.write_de(entry))?;
+            let dp = t!("vlog::write_entry", self.write_de(entry, compress))?;
             // why do we always flush here
             self.wtr.flush()?;
             Ok(dp)
@@ -195,17 +391,23 @@ impl Vlog {
         self.w_off as usize
     }
 
-    #[debug_ensures(self.buf.is_empty(), "buffer not flushed")]
-    #[debug_ensures(self.buf_sz == 0, "buffer size not reset")]
+    #[debug_ensures(ret.is_err() || self.buf.is_empty(), "buffer not flushed")]
+    #[debug_ensures(ret.is_err() || self.buf_sz == 0, "buffer size not reset")]
     fn flush(&mut self) -> GhalaDbResult<()> {
         let num = self.num;
         debug!("vlog::flush num: {}", num);
         for (dp, de_bytes) in &self.buf {
             // write to file
             let dp_offset = dp.offset - DataPtr::serde_sz() as u64;
-            let s_pos = self.wtr.stream_position().ok();
-            debug_assert!(Some(dp_offset) == s_pos, "offset do not match");
-            self.wtr.seek(SeekFrom::Start(dp_offset))?;
+            let s_pos = self
+                .file
+                .size()
+                .ok()
+                .map(|sz| sz + self.wtr.buffer().len() as u64);
+            debug_assert!(
+                s_pos.is_none_or(|pos| pos == dp_offset),
+                "offset mismatch"
+            );
             self.wtr.write_all(&Dec::ser_raw(dp)?)?;
             self.wtr.write_all(de_bytes)?;
         }
@@ -215,26 +417,76 @@ impl Vlog {
         Ok(())
     }
 
+    /// Flushes buffered entries and makes the vlog durable.
+    fn sync(&mut self) -> GhalaDbResult<()> {
+        self.flush()?;
+        if self.synced < self.w_off {
+            self.file.sync()?;
+            self.synced = self.w_off;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn ser(&mut self, de: &DataEntry, compress: bool) -> GhalaDbResult<Bytes> {
+        let bytes = Dec::ser_raw(de)?;
+        if bytes.len() > self.conf.max_value_size {
+            return Err(GhalaDbError::ValueTooLarge(bytes.len()));
+        }
+        let bytes = if compress {
+            self.dec.compress(bytes)?
+        } else {
+            bytes
+        };
+        self.dec.seal(bytes)
+    }
+
+    #[inline]
+    fn de(&mut self, buf: &[u8], compressed: bool) -> GhalaDbResult<DataEntry> {
+        Self::decode(&mut self.dec, buf, compressed, self.legacy)
+    }
+
+    /// Decodes a data entry, of format version 0 if `legacy` is set.
     #[inline]
-    fn ser(&mut self, de: &DataEntry) -> GhalaDbResult<Bytes> {
-        self.dec.ser(de)
+    fn decode(
+        dec: &mut Dec,
+        buf: &[u8],
+        compressed: bool,
+        legacy: bool,
+    ) -> GhalaDbResult<DataEntry> {
+        let buf = dec.open(buf)?;
+        let buf = buf.as_ref();
+        if legacy {
+            let de: LegacyDataEntry = Self::deser(dec, buf, compressed)?;
+            return Ok(de.into());
+        }
+        Self::deser(dec, buf, compressed)
     }
 
     #[inline]
-    fn de(&mut self, buf: &[u8]) -> GhalaDbResult<DataEntry> {
-        self.dec.deser(buf)
+    fn deser<T: Decode>(
+        dec: &mut Dec,
+        buf: &[u8],
+        compressed: bool,
+    ) -> GhalaDbResult<T> {
+        if compressed {
+            dec.deser(buf)
+        } else {
+            Dec::deser_raw(buf)
+        }
     }
 
-    fn write_de(&mut self, de: &DataEntry) -> GhalaDbResult<DataPtr> {
+    fn write_de(
+        &mut self,
+        de: &DataEntry,
+        compress: bool,
+    ) -> GhalaDbResult<DataPtr> {
         let offset = self.w_off;
-        let de_bytes = self.ser(de)?;
+        let compress = self.conf.compress && compress;
+        let de_bytes = self.ser(de, compress)?;
         let dp_sz = DataPtr::serde_sz() as u64;
-        let dp = DataPtr::new(
-            self.num,
-            offset + dp_sz,
-            de_bytes.len() as u32,
-            self.conf.compress,
-        );
+        let dp =
+            DataPtr::new(self.num, offset + dp_sz, de_bytes.len() as u32, compress);
         let dp_bytes = Dec::ser_raw(&dp)?;
         self.wtr.write_all(&dp_bytes)?;
         self.wtr.write_all(&de_bytes)?;
@@ -259,35 +511,53 @@ impl Vlog {
     }
 
     #[debug_requires(!self.active, "cannot del active vlog")]
-    #[debug_ensures(!self.path.exists())]
+    #[debug_ensures(ret.is_err() || !self.fs.exists(&self.path))]
     fn delete(&self) -> GhalaDbResult<()> {
         let vnum = self.num;
         debug!("vlog::delete vlog {} path: {}", vnum, self.path.display(),);
-        std::fs::remove_file(&self.path)?;
+        self.fs.remove(&self.path)?;
+        let dict_path = self.path.with_extension(DICT_EXT);
+        if self.fs.exists(&dict_path) {
+            self.fs.remove(&dict_path)?;
+        }
         Ok(())
     }
 }
 impl Drop for Vlog {
     fn drop(&mut self) {
         if !self.buf.is_empty() {
-            self.flush().ok();
-            debug_assert!(self.buf.is_empty(), "buf not empty at drop");
+            t!("vlog::drop_flush", self.flush()).ok();
         }
         if !self.active {
+            self.map = None;
             t!("vlog::drop", self.delete()).ok();
         }
     }
 }
 
 pub(crate) struct VlogReader {
-    rdr: BufReader<File>,
+    rdr: BufReader<FileReader>,
     dec: Dec,
+    legacy: bool,
 }
 impl VlogReader {
-    pub fn from_path(path: &Path) -> GhalaDbResult<Self> {
-        let rdr = BufReader::new(OpenOptions::new().read(true).open(path)?);
-        let dec = Dec::new(true);
-        Ok(Self { rdr, dec })
+    /// Opens a reader over the vlog at `path`, whose data entries are at most
+    /// `max_size` bytes and of format version 0 if `legacy` is set.
+    pub fn from_path(
+        fs: &dyn FileSystem,
+        path: &Path,
+        cipher: Option<Cipher>,
+        max_size: usize,
+        legacy: bool,
+    ) -> GhalaDbResult<Self> {
+        let file = fs.open(path, OpenMode::Read)?;
+        let rdr = BufReader::new(FileReader::new(file, 0));
+        let dec = match load_dict(fs, path, cipher.as_ref())? {
+            Some(dict) => Dec::with_dict(&dict, 0)?,
+            None => Dec::new(true),
+        };
+        let dec = dec.with_cipher(cipher).with_max_size(max_size);
+        Ok(Self { rdr, dec, legacy })
     }
     fn read_de(
         &mut self,
@@ -296,12 +566,7 @@ impl VlogReader {
     ) -> GhalaDbResult<DataEntry> {
         let mut buf = vec![0u8; sz as usize];
         self.rdr.read_exact(&mut buf)?;
-        let de = if compressed {
-            self.dec.deser(&buf)?
-        } else {
-            Dec::deser_raw(&buf)?
-        };
-        Ok(de)
+        Vlog::decode(&mut self.dec, &buf, compressed, self.legacy)
     }
     fn read_dp(&mut self) -> GhalaDbResult<Option<DataPtr>> {
         let dp_sz = DataPtr::serde_sz();
@@ -337,8 +602,21 @@ impl Iterator for VlogReader {
     }
 }
 
+/// Vlogs info, listing the vlogs of the data store.
+///
+/// The format version comes last, so that vlogs info of format version 0,
+/// which only lists the vlogs, fails to decode as such.
+#[derive(Debug, Clone, Encode, Decode)]
+pub(crate) struct VlogsInfo {
+    pub vlogs: Vec<VlogNum>,
+    /// Vlogs holding data entries of format version 0.
+    pub legacy: Vec<VlogNum>,
+    version: u32,
+}
+
+/// Vlogs info of format version 0.
 #[derive(Debug, Clone, Encode, Decode)]
-struct VlogsInfo {
+struct LegacyVlogsInfo {
     vlogs: Vec<VlogNum>,
 }
 
@@ -348,38 +626,117 @@ struct VlogsInfo {
 /// vlogs that it uses to redirect data requests to the write vlog.
 pub(crate) struct VlogsMan {
     base_path: PathBuf,
+    /// File system holding the vlogs.
+    fs: Fs,
     vlogs: BTreeMap<VlogNum, Vlog>,
     seq: VlogNum,
     conf: DatabaseOptions,
+    /// zstd dictionary used to compress entries in new vlogs.
+    dict: Option<Bytes>,
+    /// Cipher used to encrypt vlogs and their metadata.
+    cipher: Option<Cipher>,
+    /// Cache of recently read data entries.
+    cache: ValueCache,
+    /// Write buffer size of the vlogs, set during bulk loads.
+    buf_cap: Option<usize>,
+    /// Vlogs listed in the vlogs info whose file was missing when loaded.
+    missing: Vec<VlogNum>,
+    /// Set if new data entries go to a new tail vlog, as the tail vlog found
+    /// when loading is of format version 0 and is never appended to.
+    roll_tail: bool,
 }
 
 impl VlogsMan {
-    pub fn new(path: &Path, conf: DatabaseOptions) -> GhalaDbResult<VlogsMan> {
+    pub fn new(
+        fs: Fs,
+        path: &Path,
+        conf: DatabaseOptions,
+        cipher: Option<Cipher>,
+    ) -> GhalaDbResult<VlogsMan> {
         let base_path = path.to_path_buf();
-        let info = Self::load_vlogs_info(base_path.join(VLOG_INFO_FILE))?;
+        let info = Self::load_vlogs_info(&*fs, &base_path, cipher.as_ref())?;
         let mut vlogs = BTreeMap::new();
         let mut seq = VlogNum::MIN;
+        let mut missing = vec![];
         for vnum in info.vlogs {
             let lpath = base_path.join(format!("{}.vlog", vnum));
-            let vlog = Vlog::from_path(lpath, vnum, conf)?;
+            if !fs.exists(&lpath) {
+                warn!("vlogsman::new missing vlog: {vnum}");
+                missing.push(vnum);
+            }
+            let mut vlog =
+                Vlog::from_path(fs.clone(), lpath, vnum, conf, cipher.clone())?;
+            vlog.legacy = info.legacy.contains(&vnum);
             vlogs.insert(vnum, vlog);
             seq = std::cmp::max(vnum, seq);
         }
+        for vlog in vlogs.range_mut(..seq).map(|(_, vlog)| vlog) {
+            vlog.seal()?;
+        }
+        let dict = load_dict(
+            &*fs,
+            &base_path.join(format!("{}.vlog", seq)),
+            cipher.as_ref(),
+        )?;
+        let mut roll_tail = false;
+        if let Some(vlog) = vlogs.get_mut(&seq) {
+            if vlog.legacy {
+                roll_tail = true;
+            } else {
+                vlog.truncate_torn()?;
+            }
+        }
         Ok(VlogsMan {
             base_path,
+            fs,
             vlogs,
             seq,
             conf,
+            dict,
+            cipher,
+            cache: ValueCache::new(conf.value_cache_size),
+            buf_cap: None,
+            missing,
+            roll_tail,
         })
     }
 
-    #[debug_ensures(!self.vlogs.contains_key(&vnum))]
+    /// Sets the zstd dictionary used to compress new data entries.
+    ///
+    /// A new tail vlog is started so that every vlog is compressed using a
+    /// single dictionary. Entries in older vlogs get compressed with the new
+    /// dictionary as they are relocated by the garbage collector.
+    pub fn set_dict(&mut self, dict: Bytes) -> GhalaDbResult<()> {
+        debug!("vlogsman::set_dict size: {}", dict.len());
+        self.dict = Some(dict);
+        if let Some(vlog) = self.vlogs.get_mut(&self.seq) {
+            vlog.seal()?;
+            self.seq += 1;
+        }
+        let vlog = self.create_new_vlog()?;
+        self.vlogs.insert(self.seq, vlog);
+        self.roll_tail = false;
+        Ok(())
+    }
+
+    /// Check if new data entries are compressed using a zstd dictionary.
+    pub fn has_dict(&self) -> bool {
+        self.dict.is_some()
+    }
+
+    #[debug_ensures(ret.is_err() || !self.vlogs.contains_key(&vnum))]
     /// Remove values logs from the manager and deactivate it.
     ///
     /// Deactivating the vlog will earmark it for auto deletion during
-    /// Drop.
+    /// Drop. The vlog is only deleted once the vlogs info no longer lists it.
     pub fn drop_vlog(&mut self, vnum: VlogNum) -> GhalaDbResult<()> {
+        self.cache.invalidate_vlog(vnum);
         if let Some(mut vlog) = self.vlogs.remove(&vnum) {
+            if let Err(e) = self.dump_vlogs_info() {
+                // keep the vlog, it is still listed
+                self.vlogs.insert(vnum, vlog);
+                return Err(e);
+            }
             vlog.deactivate();
         } else {
             error!("vlog: {vnum} not found when dropping");
@@ -388,50 +745,155 @@ impl VlogsMan {
         Ok(())
     }
 
-    #[debug_ensures(self.base_path.join(VLOG_INFO_FILE).exists())]
+    #[debug_ensures(ret.is_err() || self.fs.exists(&self.base_path.join(VLOG_INFO_FILE)))]
     fn dump_vlogs_info(&self) -> GhalaDbResult<()> {
-        let path = self.base_path.join(VLOG_INFO_FILE);
-        let mut wtr = BufWriter::new(
-            OpenOptions::new()
-                .create(true)
-                .truncate(true)
-                .write(true)
-                .open(path)?,
-        );
-        let info = VlogsInfo {
-            vlogs: self.vlogs.keys().copied().collect(),
-        };
-        let mut dec = Dec::new(true);
-        let bytes = dec.ser(&info)?;
-        wtr.write_all(&bytes)?;
-
-        Ok(())
+        let vlogs = self.vlogs.keys().copied().collect();
+        let legacy = self
+            .vlogs
+            .iter()
+            .filter(|(_, vlog)| vlog.legacy)
+            .map(|(vnum, _)| *vnum)
+            .collect();
+        let cipher = self.cipher.clone();
+        write_vlogs_info(&*self.fs, &self.base_path, vlogs, legacy, cipher)
     }
 
-    fn load_vlogs_info(path: PathBuf) -> GhalaDbResult<VlogsInfo> {
-        if path.exists() {
-            let mut rdr = BufReader::new(OpenOptions::new().read(true).open(&path)?);
-            let mut bytes = vec![];
-            rdr.read_to_end(&mut bytes)?;
-            let mut dec = Dec::new(true);
-            let info: VlogsInfo = dec.deser(&bytes)?;
-            Ok(info)
+    fn load_vlogs_info(
+        fs: &dyn FileSystem,
+        base: &Path,
+        cipher: Option<&Cipher>,
+    ) -> GhalaDbResult<VlogsInfo> {
+        if fs.exists(&base.join(VLOG_INFO_FILE)) {
+            read_vlogs_info(fs, base, cipher)
         } else {
-            Ok(VlogsInfo { vlogs: vec![] })
+            Ok(VlogsInfo {
+                vlogs: vec![],
+                legacy: vec![],
+                version: FORMAT_VERSION,
+            })
         }
     }
 
+    #[allow(clippy::unnecessary_lazy_evaluations)]
     pub fn get(&mut self, dp: &DataPtr) -> GhalaDbResult<DataEntry> {
+        if let Some(de) = self.cache.get(dp) {
+            return Ok(de);
+        }
         let vlog = self
             .vlogs
             .get_mut(&dp.vlog)
             .ok_or_else(|| GhalaDbError::MissingVlog(dp.vlog))?;
+        let de = vlog.get(dp)?;
+        if self.cache.is_enabled() {
+            self.cache.insert(*dp, de.clone());
+        }
+        Ok(de)
+    }
+
+    /// Reads data entries of a single vlog, sorted by offset, in one go.
+    ///
+    /// Entries read this way bypass the value cache.
+    pub fn get_span(&mut self, dps: &[DataPtr]) -> GhalaDbResult<Vec<DataEntry>> {
+        let Some(first) = dps.first() else {
+            return Ok(vec![]);
+        };
+        let vlog = self
+            .vlogs
+            .get_mut(&first.vlog)
+            .ok_or(GhalaDbError::MissingVlog(first.vlog))?;
+        vlog.get_span(dps)
+    }
+
+    /// Decodes the raw bytes of the data entry at `dp`, as read from disk.
+    pub fn decode(&mut self, dp: &DataPtr, buf: &[u8]) -> GhalaDbResult<DataEntry> {
+        let vlog = self
+            .vlogs
+            .get_mut(&dp.vlog)
+            .ok_or(GhalaDbError::MissingVlog(dp.vlog))?;
+        vlog.de(buf, dp.compressed)
+    }
+
+    /// Reads the data entry at `dp` from its vlog, bypassing the value cache.
+    pub fn read(&mut self, dp: &DataPtr) -> GhalaDbResult<DataEntry> {
+        let vlog = self
+            .vlogs
+            .get_mut(&dp.vlog)
+            .ok_or(GhalaDbError::MissingVlog(dp.vlog))?;
         vlog.get(dp)
     }
 
-    pub fn put(&mut self, entry: &DataEntry) -> GhalaDbResult<DataPtr> {
+    /// Numbers of the vlogs, oldest first.
+    pub fn vlog_nums(&self) -> Vec<VlogNum> {
+        self.vlogs.keys().copied().collect()
+    }
+
+    /// Vlogs listed in the vlogs info whose file was missing when loaded.
+    pub fn missing(&self) -> &[VlogNum] {
+        &self.missing
+    }
+
+    /// Reader over a vlog's data entries.
+    pub fn reader(&self, vnum: VlogNum) -> GhalaDbResult<VlogReader> {
+        let path = self.vlog_path(vnum);
+        let max_size = self.conf.max_value_size;
+        let legacy = self.vlogs.get(&vnum).is_some_and(|vlog| vlog.legacy);
+        VlogReader::from_path(
+            &*self.fs,
+            &path,
+            self.cipher.clone(),
+            max_size,
+            legacy,
+        )
+    }
+
+    /// A vlog's file, for reading its data entries' raw bytes.
+    pub fn file(&self, vnum: VlogNum) -> Option<Arc<dyn FsFile>> {
+        self.vlogs.get(&vnum).map(|vlog| vlog.file.clone())
+    }
+
+    /// Path of a vlog's file.
+    pub fn vlog_path(&self, vnum: VlogNum) -> PathBuf {
+        self.base_path.join(format!("{}.vlog", vnum))
+    }
+
+    /// Writes the vlogs' buffered entries to disk.
+    pub fn flush(&mut self) -> GhalaDbResult<()> {
+        for vlog in self.vlogs.values_mut() {
+            t!("vlog::flush_buf", vlog.flush())?;
+        }
+        Ok(())
+    }
+
+    /// Removes the data entry at `dp` from the value cache, once it has been
+    /// relocated.
+    pub fn invalidate(&self, dp: &DataPtr) {
+        self.cache.invalidate(dp);
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Overrides the size of the vlogs' write buffers, which are used even if
+    /// writes are synchronous. `None` restores the configured buffering.
+    pub fn set_buf_cap(&mut self, buf_cap: Option<usize>) {
+        self.buf_cap = buf_cap;
+        for vlog in self.vlogs.values_mut() {
+            vlog.buf_cap = buf_cap;
+        }
+    }
+
+    /// Appends a data entry to the tail vlog.
+    ///
+    /// The entry is only compressed if `compress` is set and compression is
+    /// enabled for the data store.
+    pub fn put(
+        &mut self,
+        entry: &DataEntry,
+        compress: bool,
+    ) -> GhalaDbResult<DataPtr> {
         let vlog = self.get_tail()?;
-        vlog.put(entry)
+        vlog.put(entry, compress)
     }
 
     #[allow(unused)]
@@ -451,20 +913,61 @@ impl VlogsMan {
     pub fn get_gc_cand(&mut self) -> GhalaDbResult<Option<(VlogNum, PathBuf)>> {
         if self.vlogs.len() > 1 {
             let vnum = self.vlogs.keys().next().unwrap();
-            let path = self.base_path.join(format!("{}.vlog", vnum));
+            let path = self.vlog_path(*vnum);
             Ok(Some((*vnum, path)))
         } else {
             Ok(None)
         }
     }
 
-    /// Attempts to sync in-memory data to disk.
+    /// Makes the vlogs and the vlogs info durable.
     pub fn sync(&mut self) -> GhalaDbResult<()> {
-        t!("vlogsman::dump_vlogs_info", self.dump_vlogs_info())?;
         for (_vnum, vlog) in self.vlogs.iter_mut() {
-            t!("vlog::flush_buf", vlog.flush())?;
+            t!("vlog::sync", vlog.sync())?;
         }
-        Ok(())
+        t!("vlogsman::dump_vlogs_info", self.dump_vlogs_info())
+    }
+
+    /// Syncs the vlogs and lists their files.
+    pub fn live_files(&mut self) -> GhalaDbResult<Vec<LiveFile>> {
+        self.sync()?;
+        let fs = &*self.fs;
+        let mut files = vec![LiveFile::new(
+            fs,
+            &self.base_path,
+            VLOG_INFO_FILE,
+            FileKind::Mutable,
+        )?];
+        for (vnum, vlog) in &self.vlogs {
+            let dict_path = vlog.path.with_extension(DICT_EXT);
+            if fs.exists(&dict_path) {
+                files.push(LiveFile::from_path(
+                    fs,
+                    &self.base_path,
+                    &dict_path,
+                    FileKind::Immutable,
+                )?);
+            }
+            let kind = FileKind::Vlog {
+                num: *vnum,
+                tail: *vnum == self.seq,
+            };
+            files.push(LiveFile::from_path(fs, &self.base_path, &vlog.path, kind)?);
+        }
+        Ok(files)
+    }
+
+    /// Number of the tail vlog, which new data entries are appended to.
+    pub fn tail(&self) -> VlogNum {
+        self.seq
+    }
+
+    pub fn base_path(&self) -> &Path {
+        &self.base_path
+    }
+
+    pub fn fs(&self) -> &dyn FileSystem {
+        &*self.fs
     }
 
     /// Get the current active vlog
@@ -473,8 +976,9 @@ impl VlogsMan {
     /// current has reached it's max size.
     fn get_tail(&mut self) -> GhalaDbResult<&mut Vlog> {
         if let Some(vlog) = self.vlogs.get_mut(&self.seq) {
-            if vlog.size() > self.conf.max_vlog_size {
-                vlog.flush()?;
+            if vlog.size() > self.conf.max_vlog_size || self.roll_tail {
+                self.roll_tail = false;
+                vlog.seal()?;
                 self.seq += 1;
                 let next_vlog = self.create_new_vlog()?;
                 Ok(self.vlogs.entry(self.seq).or_insert(next_vlog))
@@ -489,8 +993,24 @@ impl VlogsMan {
 
     #[debug_requires(!self.vlogs.contains_key(&self.seq))]
     fn create_new_vlog(&self) -> GhalaDbResult<Vlog> {
-        let path = self.base_path.join(format!("{}.vlog", self.seq));
-        let vlog = Vlog::from_path(path, self.seq, self.conf)?;
+        let path = self.vlog_path(self.seq);
+        // leftovers of a vlog created before a crash are discarded
+        let dict_path = path.with_extension(DICT_EXT);
+        if let Some(ref dict) = self.dict {
+            let bytes = crypto::seal(self.cipher.as_ref(), dict.clone())?;
+            write_atomic(&*self.fs, &dict_path, &bytes)?;
+        } else if self.fs.exists(&dict_path) {
+            self.fs.remove(&dict_path)?;
+        }
+        self.fs.open(&path, OpenMode::Create)?;
+        let mut vlog = Vlog::from_path(
+            self.fs.clone(),
+            path,
+            self.seq,
+            self.conf,
+            self.cipher.clone(),
+        )?;
+        vlog.buf_cap = self.buf_cap;
         Ok(vlog)
     }
 }
@@ -501,34 +1021,264 @@ impl Drop for VlogsMan {
     }
 }
 
+/// Reads the vlogs info of the data store at `base`, upgrading vlogs info of
+/// format version 0.
+pub(crate) fn read_vlogs_info(
+    fs: &dyn FileSystem,
+    base: &Path,
+    cipher: Option<&Cipher>,
+) -> GhalaDbResult<VlogsInfo> {
+    let bytes = fs.read(&base.join(VLOG_INFO_FILE))?;
+    let bytes = crypto::open(cipher, &bytes)?;
+    let mut dec = Dec::new(true);
+    let info = match dec.deser::<VlogsInfo>(&bytes) {
+        Ok(info) => info,
+        Err(_) => {
+            let info: LegacyVlogsInfo = dec.deser(&bytes)?;
+            debug!("vlog::read_vlogs_info legacy vlogs: {:?}", info.vlogs);
+            VlogsInfo {
+                legacy: info.vlogs.clone(),
+                vlogs: info.vlogs,
+                version: 0,
+            }
+        }
+    };
+    if info.version > FORMAT_VERSION {
+        return Err(GhalaDbError::UnsupportedFormat(info.version));
+    }
+    Ok(info)
+}
+
+/// Writes the vlogs info of the data store at `base`. The `legacy` vlogs hold
+/// data entries of format version 0.
+pub(crate) fn write_vlogs_info(
+    fs: &dyn FileSystem,
+    base: &Path,
+    vlogs: Vec<VlogNum>,
+    legacy: Vec<VlogNum>,
+    cipher: Option<Cipher>,
+) -> GhalaDbResult<()> {
+    let info = VlogsInfo {
+        vlogs,
+        legacy,
+        version: FORMAT_VERSION,
+    };
+    let mut dec = Dec::new(true).with_cipher(cipher);
+    let bytes = dec.ser(&info)?;
+    write_atomic(fs, &base.join(VLOG_INFO_FILE), &dec.seal(bytes)?)?;
+    Ok(())
+}
+
+/// Counts of the records and unreadable fragments of a vlog, as salvaged by
+/// [salvage_vlog].
+pub(crate) struct Salvaged {
+    /// Number of readable records.
+    pub records: u64,
+    /// Number of unreadable fragments.
+    pub fragments: u64,
+}
+
+/// Size of the window [WindowReader] reads a file through.
+const SALVAGE_WINDOW: usize = 64 * 1024;
+
+/// Reads byte ranges of a file through a window of it, so that scanning a
+/// file byte by byte does not read it for every byte.
+struct WindowReader {
+    file: Arc<dyn FsFile>,
+    /// Offset of the window in the file.
+    start: u64,
+    buf: Bytes,
+}
+
+impl WindowReader {
+    fn new(file: Arc<dyn FsFile>) -> WindowReader {
+        WindowReader {
+            file,
+            start: 0,
+            buf: vec![],
+        }
+    }
+
+    /// Reads the `len` bytes at `offset`, which must be in the file.
+    fn read(&mut self, offset: u64, len: usize) -> GhalaDbResult<&[u8]> {
+        let in_window = offset >= self.start
+            && offset + len as u64 <= self.start + self.buf.len() as u64;
+        if !in_window {
+            self.buf.resize(len.max(SALVAGE_WINDOW), 0);
+            let mut filled = 0;
+            while filled < self.buf.len() {
+                let buf = &mut self.buf[filled..];
+                match self.file.read_at(buf, offset + filled as u64)? {
+                    0 => break,
+                    n => filled += n,
+                }
+            }
+            self.buf.truncate(filled);
+            self.start = offset;
+            if filled < len {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+        }
+        let pos = (offset - self.start) as usize;
+        Ok(&self.buf[pos..pos + len])
+    }
+}
+
+/// Salvages the readable records of a vlog, skipping unreadable regions by
+/// resyncing on the next record boundary.
+///
+/// A record boundary is a data pointer into this vlog whose offset is right
+/// past the pointer itself, followed by a data entry that decodes. Every
+/// readable record is passed to `visit`, in offset order. If unreadable
+/// regions are found, they are copied to `lost_found` as
+/// `<vlog>.vlog.<offset>` files and the vlog is rewritten with its readable
+/// records only, so the pointers passed to `visit` are those of the rewritten
+/// vlog. The vlog is read in windows rather than as a whole. The data entries
+/// are of format version 0 if `legacy` is set.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn salvage_vlog(
+    fs: &dyn FileSystem,
+    path: &Path,
+    vnum: VlogNum,
+    cipher: Option<Cipher>,
+    max_size: usize,
+    legacy: bool,
+    lost_found: &Path,
+    mut visit: impl FnMut(DataPtr, DataEntry),
+) -> GhalaDbResult<Salvaged> {
+    let file = fs.open(path, OpenMode::Read)?;
+    let size = file.size()?;
+    let dec = match load_dict(fs, path, cipher.as_ref())? {
+        Some(dict) => Dec::with_dict(&dict, 0)?,
+        None => Dec::new(true),
+    };
+    let mut dec = dec.with_cipher(cipher).with_max_size(max_size);
+    let mut rdr = WindowReader::new(file.clone());
+    let dp_sz = DataPtr::serde_sz() as u64;
+    let mut record_at = |pos: u64| -> GhalaDbResult<Option<(DataPtr, DataEntry)>> {
+        if pos + dp_sz > size {
+            return Ok(None);
+        }
+        let Ok(dp) = Dec::deser_raw::<DataPtr>(rdr.read(pos, dp_sz as usize)?)
+        else {
+            return Ok(None);
+        };
+        let end = pos + dp_sz + dp.len as u64;
+        if dp.vlog != vnum || dp.offset != pos + dp_sz || end > size {
+            return Ok(None);
+        }
+        let buf = rdr.read(dp.offset, dp.len as usize)?;
+        let de = Vlog::decode(&mut dec, buf, dp.compressed, legacy).ok();
+        Ok(de.map(|de| (dp, de)))
+    };
+
+    // pointers of the readable records, and the byte ranges of the others
+    let mut records = vec![];
+    let mut fragments = vec![];
+    // offset of the next record in the rewritten vlog
+    let mut offset = 0;
+    let (mut pos, mut lost) = (0, None);
+    while pos < size {
+        match record_at(pos)? {
+            Some((dp, de)) => {
+                if let Some(start) = lost.take() {
+                    fragments.push((start, pos - start));
+                }
+                pos = dp.offset + dp.len as u64;
+                let new_dp =
+                    DataPtr::new(vnum, offset + dp_sz, dp.len, dp.compressed);
+                offset = new_dp.offset + new_dp.len as u64;
+                records.push(dp);
+                visit(new_dp, de);
+            }
+            None => {
+                lost.get_or_insert(pos);
+                pos += 1;
+            }
+        }
+    }
+    if let Some(start) = lost {
+        fragments.push((start, size - start));
+    }
+    let salvaged = Salvaged {
+        records: records.len() as u64,
+        fragments: fragments.len() as u64,
+    };
+    if fragments.is_empty() {
+        return Ok(salvaged);
+    }
+
+    for (start, len) in fragments {
+        let dest = lost_found.join(format!("{vnum}.vlog.{start}"));
+        copy_range(fs, path, &dest, start, len)?;
+    }
+    debug!("vlog::salvage rewriting vlog: {vnum}");
+    let tmp_path = path.with_extension("repair");
+    let tmp = fs.open(&tmp_path, OpenMode::Create)?;
+    let mut wtr = BufWriter::new(FileWriter(tmp.clone()));
+    let mut offset = 0;
+    for dp in records {
+        let mut de_bytes = vec![0u8; dp.len as usize];
+        file.read_exact_at(&mut de_bytes, dp.offset)?;
+        let new_dp = DataPtr::new(vnum, offset + dp_sz, dp.len, dp.compressed);
+        wtr.write_all(&Dec::ser_raw(&new_dp)?)?;
+        wtr.write_all(&de_bytes)?;
+        offset = new_dp.offset + new_dp.len as u64;
+    }
+    wtr.flush()?;
+    tmp.sync()?;
+    fs.rename(&tmp_path, path)?;
+    sync_parent(fs, path)?;
+    Ok(salvaged)
+}
+
+/// Loads the zstd dictionary of the vlog at `path`, if any.
+fn load_dict(
+    fs: &dyn FileSystem,
+    path: &Path,
+    cipher: Option<&Cipher>,
+) -> GhalaDbResult<Option<Bytes>> {
+    let dict_path = path.with_extension(DICT_EXT);
+    if fs.exists(&dict_path) {
+        let bytes = fs.read(&dict_path)?;
+        Ok(Some(crypto::open(cipher, &bytes)?.into_owned()))
+    } else {
+        Ok(None)
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use crate::fs::{InMemoryFs, StdFs};
     use tempfile::{tempdir, TempDir};
     fn init_vlog(temp_dir: &TempDir) -> GhalaDbResult<Vlog> {
         let file_path = temp_dir.path().join("test_vlog.db");
         let conf = DatabaseOptions::builder().vlog_mem_buf_size(1024).build();
 
-        Vlog::from_path(file_path.clone(), 1, conf)
+        Vlog::from_path(Arc::new(StdFs), file_path.clone(), 1, conf, None)
     }
 
     #[test]
+    #[allow(clippy::useless_conversion)]
     fn vlog_iter() -> GhalaDbResult<()> {
         let tmp_dir = tempdir()?;
         let path = tmp_dir.path().join("1.vlog");
         let conf = DatabaseOptions::builder()
             .vlog_mem_buf_size(1_000_000)
             .build();
-        let mut vlog = Vlog::from_path(path.clone(), 1, conf)?;
+        let mut vlog =
+            Vlog::from_path(Arc::new(StdFs), path.clone(), 1, conf, None)?;
         let data: Vec<DataEntry> = (0..100)
             .map(|_| DataEntry::new(Bytes::gen(), Bytes::gen()))
             .collect();
         for de in &data {
-            vlog.put(de)?;
+            vlog.put(de, true)?;
         }
         drop(vlog);
-        let vlog_iter = VlogReader::from_path(&path)?;
+        let vlog_iter =
+            VlogReader::from_path(&StdFs, &path, None, usize::MAX, false)?;
         let iter_data: Vec<DataEntry> = vlog_iter
             .into_iter()
             .map(|i| i.map(|(_dp, de)| de))
@@ -549,11 +1299,8 @@ mod tests {
     #[test]
     fn vlog_write_and_read() -> GhalaDbResult<()> {
         let mut vlog = init_vlog(&tempdir()?)?;
-        let test_entry = DataEntry {
-            key: vec![1, 2, 3],
-            val: vec![4, 5, 6],
-        };
-        let data_ptr = vlog.put(&test_entry)?;
+        let test_entry = DataEntry::new(vec![1, 2, 3], vec![4, 5, 6]);
+        let data_ptr = vlog.put(&test_entry, true)?;
         let read_entry = vlog.get(&data_ptr)?;
         assert_eq!(read_entry, test_entry);
         Ok(())
@@ -562,12 +1309,62 @@ mod tests {
     #[test]
     fn vlog_flush() -> GhalaDbResult<()> {
         let mut vlog = init_vlog(&tempdir()?)?;
-        let test_entry = DataEntry {
-            key: vec![1, 2, 3],
-            val: vec![4, 5, 6],
-        };
-        vlog.put(&test_entry)?;
+        let test_entry = DataEntry::new(vec![1, 2, 3], vec![4, 5, 6]);
+        vlog.put(&test_entry, true)?;
+        vlog.flush()?;
+        Ok(())
+    }
+
+    #[test]
+    fn vlog_mmap_reads() -> GhalaDbResult<()> {
+        let tmp_dir = tempdir()?;
+        let path = tmp_dir.path().join("1.vlog");
+        let conf = DatabaseOptions::builder().mmap_reads(true).build();
+        let mut vlog = Vlog::from_path(Arc::new(StdFs), path, 1, conf, None)?;
+        let first = DataEntry::gen();
+        let first_dp = vlog.put(&first, true)?;
+        vlog.seal()?;
+        assert_eq!(vlog.get_from_map(&first_dp)?, Some(first.clone()));
+
+        // entries appended after mapping are read by remapping the vlog
+        let second = DataEntry::gen();
+        let second_dp = vlog.put(&second, true)?;
         vlog.flush()?;
+        assert_eq!(vlog.get_from_map(&second_dp)?, Some(second));
+        assert_eq!(vlog.get(&first_dp)?, first);
+
+        vlog.deactivate();
+        drop(vlog);
+        assert!(!tmp_dir.path().join("1.vlog").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn vlog_in_memory() -> GhalaDbResult<()> {
+        let fs = InMemoryFs::new();
+        let path = PathBuf::from("db/1.vlog");
+        fs.create_dir_all(Path::new("db"))?;
+        let conf = DatabaseOptions::builder().mmap_reads(true).build();
+        let mut vlog =
+            Vlog::from_path(Arc::new(fs.clone()), path.clone(), 1, conf, None)?;
+        let data: Vec<DataEntry> = (0..10).map(|_| DataEntry::gen()).collect();
+        let dps = data
+            .iter()
+            .map(|de| vlog.put(de, true))
+            .collect::<GhalaDbResult<Vec<DataPtr>>>()?;
+        vlog.seal()?;
+        for (dp, de) in dps.iter().zip(&data) {
+            assert_eq!(&vlog.get(dp)?, de);
+        }
+        assert_eq!(&vlog.get_span(&dps)?, &data);
+        let read = VlogReader::from_path(&fs, &path, None, usize::MAX, false)?
+            .map(|r| r.map(|(_, de)| de))
+            .collect::<GhalaDbResult<Vec<DataEntry>>>()?;
+        assert_eq!(read, data);
+
+        vlog.deactivate();
+        drop(vlog);
+        assert!(!fs.exists(&path));
         Ok(())
     }
 
@@ -585,12 +1382,108 @@ mod tests {
     fn vlog_flush_buf() -> GhalaDbResult<()> {
         let mut vlog = init_vlog(&tempdir()?)?;
 
-        vlog.write_to_buf(&DataEntry::gen())?;
-        vlog.write_to_buf(&DataEntry::gen())?;
+        vlog.write_to_buf(&DataEntry::gen(), true)?;
+        vlog.write_to_buf(&DataEntry::gen(), true)?;
         vlog.flush()?;
 
         assert!(vlog.buf.is_empty());
         assert_eq!(vlog.buf_sz, 0);
         Ok(())
     }
+
+    #[test]
+    fn reopened_tail() -> GhalaDbResult<()> {
+        let fs: Fs = Arc::new(InMemoryFs::new());
+        let base = PathBuf::from("db");
+        fs.create_dir_all(&base)?;
+        let conf = DatabaseOptions::builder().build();
+        let mut entries = vec![];
+        for _ in 0..3 {
+            let mut vlogs = VlogsMan::new(fs.clone(), &base, conf, None)?;
+            let de = DataEntry::gen();
+            entries.push((vlogs.put(&de, true)?, de));
+            vlogs.sync()?;
+        }
+        // a torn entry at the end of the tail is dropped on reopening
+        let path = base.join("0.vlog");
+        let size = fs.open(&path, OpenMode::Read)?.size()?;
+        let dp = DataPtr::new(0, size + DataPtr::serde_sz() as u64, 64, true);
+        let file = fs.open(&path, OpenMode::Append)?;
+        file.append(&Dec::ser_raw(&dp)?)?;
+        file.append(&[7; 10])?;
+
+        let mut vlogs = VlogsMan::new(fs.clone(), &base, conf, None)?;
+        let de = DataEntry::gen();
+        let dp = vlogs.put(&de, true)?;
+        assert_eq!(dp.offset, size + DataPtr::serde_sz() as u64);
+        entries.push((dp, de));
+        assert_eq!(vlogs.vlogs.keys().copied().collect::<Vec<_>>(), vec![0]);
+        for (dp, de) in &entries {
+            assert_eq!(&vlogs.get(dp)?, de);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_vlogs() -> GhalaDbResult<()> {
+        let fs: Fs = Arc::new(InMemoryFs::new());
+        let base = PathBuf::from("db");
+        fs.create_dir_all(&base)?;
+        let conf = DatabaseOptions::builder().build();
+        // format version 0 vlog and vlogs info
+        let mut bytes = vec![];
+        let mut dps = vec![];
+        for (i, compressed) in [(0u8, false), (1, true)] {
+            let de = LegacyDataEntry {
+                key: vec![i],
+                val: vec![i; 64],
+            };
+            let de_bytes = if compressed {
+                Dec::new(true).ser(&de)?
+            } else {
+                Dec::ser_raw(&de)?
+            };
+            let offset = (bytes.len() + DataPtr::serde_sz()) as u64;
+            let dp = DataPtr::new(0, offset, de_bytes.len() as u32, compressed);
+            bytes.extend(Dec::ser_raw(&dp)?);
+            bytes.extend(de_bytes);
+            dps.push(dp);
+        }
+        fs.write(&base.join("0.vlog"), &bytes)?;
+        let info = LegacyVlogsInfo { vlogs: vec![0] };
+        fs.write(&base.join(VLOG_INFO_FILE), &Dec::new(true).ser(&info)?)?;
+
+        let mut vlogs = VlogsMan::new(fs.clone(), &base, conf, None)?;
+        for (i, dp) in dps.iter().enumerate() {
+            let i = i as u8;
+            assert_eq!(vlogs.get(dp)?, DataEntry::new(vec![i], vec![i; 64]));
+        }
+        let read = vlogs.reader(0)?.collect::<GhalaDbResult<Vec<_>>>()?;
+        assert_eq!(read.len(), 2);
+        // new entries go to a new vlog of the current format
+        let de = DataEntry::operand(vec![2], vec![2; 64]).in_keyspace(3);
+        let dp = vlogs.put(&de, true)?;
+        assert_eq!(dp.vlog, 1);
+        drop(vlogs);
+
+        let info = read_vlogs_info(&*fs, &base, None)?;
+        assert_eq!((info.vlogs, info.legacy), (vec![0, 1], vec![0]));
+        let mut vlogs = VlogsMan::new(fs.clone(), &base, conf, None)?;
+        assert_eq!(vlogs.get(&dps[1])?, DataEntry::new(vec![1], vec![1; 64]));
+        assert_eq!(vlogs.get(&dp)?, de);
+        drop(vlogs);
+
+        // vlogs info written by a newer format
+        let info = VlogsInfo {
+            vlogs: vec![0, 1],
+            legacy: vec![],
+            version: FORMAT_VERSION + 1,
+        };
+        fs.write(&base.join(VLOG_INFO_FILE), &Dec::new(true).ser(&info)?)?;
+        assert!(matches!(
+            VlogsMan::new(fs.clone(), &base, conf, None),
+            Err(GhalaDbError::UnsupportedFormat(_))
+        ));
+        Ok(())
+    }
 }